@@ -1,11 +1,11 @@
 const babel = require('@babel/core');
 const babelPluginSkypack = require('./babel-plugin-skypack');
 
-const makeOptions = ({ react = true, typescript = true }) => {
+const makeOptions = ({ react = true, typescript = true, skypack = true }) => {
   return {
     filename: 'page.js',
     babelrc: false,
-    plugins: [babelPluginSkypack],
+    plugins: [skypack && babelPluginSkypack].filter(Boolean),
     presets: [
       react && [
         '@babel/preset-react',
@@ -56,10 +56,12 @@ const replaceCwd = (code) => {
  * Transform some code, with output suitable for evergreen browsers.
  *
  * @param {string} code - The JS code (with JSX allowed)
+ * @param {{ skypack?: boolean }} [options] - Pass `skypack: false` to leave bare imports
+ *   for an import map to resolve
  * @returns {Promise<{ code: string, map: string }>}
  */
-const defaultCompile = async (code) => {
-  return transformInternal(code, makeOptions({ react: true }));
+const defaultCompile = async (code, { skypack = true } = {}) => {
+  return transformInternal(code, makeOptions({ react: true, skypack }));
 };
 
 exports.defaultCompile = defaultCompile;
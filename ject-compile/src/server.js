@@ -41,7 +41,9 @@ apiRouter.post('/babel', express.json(), express.text(), async (req, res) => {
   }
 
   try {
-    const output = await babel.defaultCompile(bodyRaw.code);
+    const output = await babel.defaultCompile(bodyRaw.code, {
+      skypack: bodyRaw.skypack !== false,
+    });
     res.setHeader('content-type', 'application/javascript');
     res.end(output.code);
   } catch (error) {
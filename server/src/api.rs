@@ -1,13 +1,29 @@
+mod account;
+mod collab;
+mod compile;
+mod error;
+mod export;
 mod frame;
+mod import;
+mod logs;
+mod run;
 mod saved;
+mod scaffold;
+mod search;
 mod session;
+mod templates;
+mod tests;
 mod util;
 
+use crate::env;
 use actix_web::{get, web, HttpResponse, Responder, Scope};
+use error::ApiError;
 
-#[cfg(debug_assert)]
+pub use saved::unfurl_head;
+
+#[cfg(debug_assertions)]
 const SESSION_LIMIT: u32 = 512;
-#[cfg(not(debug_assert))]
+#[cfg(not(debug_assertions))]
 const SESSION_LIMIT: u32 = 1024 * 8;
 
 #[get("/health")]
@@ -18,14 +34,77 @@ async fn r_health() -> impl Responder {
 }
 
 pub fn service() -> Scope {
+    let max_payload = env::max_payload_bytes();
+    let json_config = web::JsonConfig::default()
+        .limit(max_payload)
+        .error_handler(move |err, _req| ApiError::from_json_payload(err, max_payload).into());
+    // For the routes that take the body as web::Bytes
+    let payload_config = web::PayloadConfig::new(max_payload);
+    let query_config = web::QueryConfig::default()
+        .error_handler(|err, _req| ApiError::InvalidQuery(err.to_string()).into());
+
     web::scope("/api")
+        .app_data(json_config)
+        .app_data(payload_config)
+        .app_data(query_config)
         .service(r_health)
+        .service(account::r_post_register)
+        .service(account::r_post_login)
+        .service(account::r_post_logout)
+        .service(account::r_get_account)
+        .service(account::r_post_token)
+        .service(account::r_get_tokens)
+        .service(account::r_delete_token)
+        .service(saved::r_list_saved)
         .service(saved::r_get_saved)
+        .service(saved::r_post_fork)
         .service(saved::r_post_save)
+        .service(saved::r_patch_saved)
+        .service(saved::r_delete_saved)
+        .service(logs::r_get_saved_logs)
+        .service(export::r_get_saved_export_html)
+        .service(export::r_get_saved_export_zip)
+        .service(export::r_get_saved_export_tar_gz)
+        .service(import::r_post_import)
+        .service(search::r_get_search)
+        .service(templates::r_get_templates)
+        .service(templates::r_get_template)
+        .service(templates::r_post_saved_template)
+        .service(templates::r_delete_template)
+        // Before r_post_session_new, which has the same path without the template query
+        .service(templates::r_post_session_new_template)
         .service(session::r_post_session_new)
         .service(session::r_put_session)
+        .service(session::r_get_session)
+        .service(session::r_get_session_deps)
+        .service(session::r_get_session_meta)
+        .service(collab::r_get_session_collab)
+        .service(logs::r_get_session_logs)
+        .service(run::r_post_session_run)
+        .service(export::r_get_session_export_html)
+        .service(export::r_get_session_export_zip)
+        .service(export::r_get_session_export_tar_gz)
+        .service(tests::r_get_session_tests)
+        .service(tests::r_get_session_tests_xml)
+        .service(tests::r_post_session_tests_run)
         .service(frame::r_get_session_page_js)
         .service(frame::r_get_session_page_js_raw)
         .service(frame::r_get_session_page_css)
         .service(frame::r_get_session_page_html)
+        .service(frame::r_get_saved_page_js)
+        .service(frame::r_get_saved_page_js_raw)
+        .service(frame::r_get_saved_page_css)
+        .service(frame::r_get_saved_page_html)
+        .service(tests::r_get_tests_js)
+        .service(tests::r_get_session_page_test_js)
+        .service(frame::r_get_session_events)
+        .service(logs::r_post_session_logs)
+        .service(tests::r_post_session_tests)
+        .service(frame::csp_report_service())
+}
+
+/// Start the background jobs. Must be called from within the actix system.
+pub fn spawn_jobs() {
+    actix_rt::spawn(saved::purge_deleted_job());
+    actix_rt::spawn(collab::write_rooms_job());
 }
@@ -0,0 +1,257 @@
+use crate::{
+    api::error::{ApiError, ApiResult},
+    db::{unix_now, Db, DbError},
+    env,
+    forwarded::ClientInfo,
+    hash::sha1_hex,
+    http, ids,
+    password::{hash_password, verify_password},
+    rate_limit::{self, Budget},
+    state::{ApiToken, User},
+};
+use actix_web::{
+    delete, dev::Payload, get, post, web, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use futures::future::{FutureExt, LocalBoxFuture};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+
+pub const LOGIN_COOKIE: &str = "ject_login";
+const LOGIN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// The user authenticated by an `Authorization: Bearer <api token>` header or the login
+/// cookie, if any. An unknown API token is an error, while an expired login is just logged out.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub Option<User>);
+
+impl CurrentUser {
+    pub fn require(self) -> ApiResult<User> {
+        self.0.ok_or(ApiError::Unauthenticated)
+    }
+
+    /// The user, if they're one of [env::admins].
+    pub fn require_admin(self) -> ApiResult<User> {
+        let user = self.require()?;
+        if env::admins().contains(&user.user_id) {
+            Ok(user)
+        } else {
+            Err(ApiError::AdminOnly)
+        }
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = ApiError;
+
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let api_token = req
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_owned());
+        let login_token = req.cookie(LOGIN_COOKIE).map(|c| c.value().to_owned());
+
+        async move {
+            let user = match (api_token, login_token) {
+                (Some(token), _) => {
+                    let db = Db::open_env().await?;
+                    let (_, user) = db.get_api_token_user(&sha1_hex(token)).await?;
+                    Some(user.ok_or(ApiError::InvalidApiToken)?)
+                }
+                (None, Some(token)) => {
+                    let db = Db::open_env().await?;
+                    db.get_login_user(&sha1_hex(token)).await?.1
+                }
+                (None, None) => None,
+            };
+
+            Ok(CurrentUser(user))
+        }
+        .boxed_local()
+    }
+}
+
+/// The login cookie has no Domain attribute, so it's host-only: sent to the main domain, but
+/// never to the frame domain where user code runs.
+fn login_cookie(value: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax{}",
+        LOGIN_COOKIE,
+        value,
+        max_age,
+        if env::is_production() { "; Secure" } else { "" }
+    )
+}
+
+/// Password hashing is deliberately slow, so keep it off the event loop.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> ApiResult<T> {
+    let result = web::block(move || Ok::<_, DbError>(f())).await;
+    Ok(result.map_err(DbError::from)?)
+}
+
+/// Compared against when the username doesn't exist, so the response time doesn't reveal it.
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| hash_password("ject dummy password"));
+
+fn validate_username(username: &str) -> ApiResult<()> {
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if (3..=32).contains(&username.len()) && valid_chars {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidUsername)
+    }
+}
+
+fn validate_password(password: &str) -> ApiResult<()> {
+    if (8..=1024).contains(&password.chars().count()) {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidPassword)
+    }
+}
+
+/// Start a browser login for the user, responding with the cookie.
+async fn log_in(db: Db, user: User) -> ApiResult<HttpResponse> {
+    let token = ids::make_login_token();
+    db.put_login(
+        &sha1_hex(&token),
+        &user.user_id,
+        unix_now() + LOGIN_TTL_SECS,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .header("set-cookie", login_cookie(&token, LOGIN_TTL_SECS))
+        .json(json!({ "user": user })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[post("/account/register", wrap = "http::MAIN_API")]
+pub async fn r_post_register(
+    web::Json(Credentials { username, password }): web::Json<Credentials>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Login, client.ip)?;
+    validate_username(&username)?;
+    validate_password(&password)?;
+
+    let password_hash = blocking(move || hash_password(&password)).await?;
+    let user = User {
+        user_id: ids::make_user_id(),
+        username,
+        created_at: unix_now(),
+    };
+
+    let (db, created) = Db::open_env()
+        .await?
+        .put_user(user.clone(), password_hash)
+        .await?;
+    if !created {
+        return Err(ApiError::UsernameTaken);
+    }
+
+    log_in(db, user).await
+}
+
+#[post("/account/login", wrap = "http::MAIN_API")]
+pub async fn r_post_login(
+    web::Json(Credentials { username, password }): web::Json<Credentials>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Login, client.ip)?;
+
+    let (db, found) = Db::open_env().await?.get_user_password(&username).await?;
+    let (user, password_hash) = match found {
+        Some((user, password_hash)) => (Some(user), password_hash),
+        None => (None, DUMMY_HASH.clone()),
+    };
+
+    let valid = blocking(move || verify_password(&password, &password_hash)).await?;
+    match user {
+        Some(user) if valid => log_in(db, user).await,
+        _ => Err(ApiError::InvalidCredentials),
+    }
+}
+
+#[post("/account/logout", wrap = "http::MAIN_API")]
+pub async fn r_post_logout(req: HttpRequest) -> ApiResult<HttpResponse> {
+    if let Some(cookie) = req.cookie(LOGIN_COOKIE) {
+        Db::open_env()
+            .await?
+            .delete_login(&sha1_hex(cookie.value()))
+            .await?;
+    }
+
+    Ok(HttpResponse::NoContent()
+        .header("set-cookie", login_cookie("", 0))
+        .finish())
+}
+
+#[get("/account", wrap = "http::MAIN_API")]
+pub async fn r_get_account(user: CurrentUser) -> ApiResult<HttpResponse> {
+    let user = user.require()?;
+    Ok(HttpResponse::Ok().json(json!({ "user": user })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenNew {
+    name: String,
+}
+
+/// Create a personal API token. The secret is only returned in this response.
+#[post("/account/tokens", wrap = "http::MAIN_API")]
+pub async fn r_post_token(
+    web::Json(TokenNew { name }): web::Json<TokenNew>,
+    user: CurrentUser,
+) -> ApiResult<HttpResponse> {
+    let user = user.require()?;
+    let secret = ids::make_api_token();
+    let token = ApiToken {
+        token_id: ids::make_token_id(),
+        name: name.trim().chars().take(100).collect(),
+        created_at: unix_now(),
+        last_used_at: None,
+    };
+
+    Db::open_env()
+        .await?
+        .put_api_token(&user.user_id, token.clone(), &sha1_hex(&secret))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "token": secret, "info": token })))
+}
+
+#[get("/account/tokens", wrap = "http::MAIN_API")]
+pub async fn r_get_tokens(user: CurrentUser) -> ApiResult<HttpResponse> {
+    let user = user.require()?;
+    let (_, tokens) = Db::open_env().await?.get_api_tokens(&user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "tokens": tokens })))
+}
+
+#[delete("/account/tokens/{token_id}", wrap = "http::MAIN_API")]
+pub async fn r_delete_token(info: web::Path<String>, user: CurrentUser) -> ApiResult<HttpResponse> {
+    let user = user.require()?;
+    let (_, deleted) = Db::open_env()
+        .await?
+        .delete_api_token(&user.user_id, &info.0)
+        .await?;
+
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::ApiTokenNotFound)
+    }
+}
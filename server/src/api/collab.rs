@@ -0,0 +1,326 @@
+use crate::{
+    api::{
+        account::CurrentUser,
+        error::{ApiError, ApiResult},
+        session::publish_files_changed,
+    },
+    collab::{self, ClientMessage, CollabError, PeerId, Room, ServerMessage},
+    db::{Db, DbResult, FileWrites, SessionWrite},
+    env,
+    forwarded::ClientInfo,
+    http,
+};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws;
+use actix_rt::time;
+use actix_web::{
+    get,
+    web::{self, BytesMut},
+    HttpRequest, HttpResponse,
+};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future::{self, Either},
+    stream, StreamExt,
+};
+use serde::Deserialize;
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Pings sent to idle sockets. A peer that hasn't sent anything for two of these is gone.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(25);
+
+/// Longest display name a guest may pick.
+const MAX_NAME_CHARS: usize = 32;
+
+/// Subprotocol the client offers, which is chosen in the response.
+const PROTOCOL: &str = "ject.collab";
+
+/// Prefix of another subprotocol the client offers, with the edit token after it. It's sent
+/// this way rather than in the URL, which is logged.
+const EDIT_TOKEN_PROTOCOL: &str = "ject.edit-token.";
+
+#[derive(Debug, Deserialize)]
+pub struct CollabParams {
+    /// Display name for guests; logged in users go by their username
+    name: Option<String>,
+}
+
+/// The subprotocols in the request's `Sec-WebSocket-Protocol` headers.
+fn offered_protocols(req: &HttpRequest) -> Vec<String> {
+    let headers = req.headers().get_all("sec-websocket-protocol");
+    headers
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|protocol| protocol.trim().to_owned())
+        .collect()
+}
+
+/// Opens a WebSocket for editing the session together with everyone else connected to it,
+/// with JSON messages (see [collab::ClientMessage] and [collab::ServerMessage]). The first
+/// message is the current text of each file. Peers that offer the edit token as a
+/// subprotocol (see [EDIT_TOKEN_PROTOCOL]) can edit, the others can only watch.
+///
+/// While anyone who can edit is connected, the session can only be changed through here;
+/// the files are written to the database every few seconds, when asked to with a `save`
+/// message, and when the last peer disconnects.
+#[get("/session/{session_id}/collab", wrap = "http::MAIN_API")]
+pub async fn r_get_session_collab(
+    req: HttpRequest,
+    info: web::Path<String>,
+    params: web::Query<CollabParams>,
+    payload: web::Payload,
+    user: CurrentUser,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    let session_id = info.0;
+    let CollabParams { name } = params.into_inner();
+    let mut response =
+        ws::handshake(req.head()).map_err(|err| ApiError::NotWebSocket(err.to_string()))?;
+    let protocols = offered_protocols(&req);
+    if protocols.iter().any(|protocol| protocol == PROTOCOL) {
+        response.header("sec-websocket-protocol", PROTOCOL);
+    }
+    let edit_token = protocols
+        .iter()
+        .find_map(|protocol| protocol.strip_prefix(EDIT_TOKEN_PROTOCOL));
+
+    let db = Db::open_env().await?;
+    let (db, meta) = db.get_session(&session_id).await?;
+    let (db, can_edit) = match edit_token {
+        Some(token) => (
+            super::util::check_edit_token(db, &session_id, token).await?,
+            true,
+        ),
+        None => (db, false),
+    };
+    let name = match user.0 {
+        Some(user) => Some(user.username),
+        None => name
+            .map(|name| name.trim().chars().take(MAX_NAME_CHARS).collect::<String>())
+            .filter(|name| !name.is_empty()),
+    };
+
+    let joined = match collab::join(&session_id, name.clone(), can_edit) {
+        Some(joined) => joined,
+        None => {
+            let (_, files) = super::util::get_files(db, &session_id, &meta).await?;
+            collab::open_and_join(&session_id, files, name, can_edit)
+        }
+    };
+
+    let (control, control_receiver) = unbounded();
+    actix_rt::spawn(receive(
+        payload,
+        joined.room,
+        joined.peer_id,
+        control,
+        client.ip,
+    ));
+
+    let messages = joined.receiver.map(|message| {
+        let json = serde_json::to_string(&message).expect("ject: ServerMessage to json");
+        ws::Message::Text(json)
+    });
+    let mut codec = ws::Codec::new();
+    let body = stream::select(messages, control_receiver).map(move |message| {
+        let mut frame = BytesMut::new();
+        codec.encode(message, &mut frame).map(|()| frame.freeze())
+    });
+
+    Ok(response.streaming(body))
+}
+
+/// Handle the frames a peer sends until they close the socket or go quiet, then remove them
+/// from the room. Pings, pongs and closes are answered through `control`.
+async fn receive(
+    mut payload: web::Payload,
+    room: Arc<Mutex<Room>>,
+    peer_id: PeerId,
+    control: UnboundedSender<ws::Message>,
+    client_ip: Option<IpAddr>,
+) {
+    // Room for the largest file in an insert, and the rest of the message
+    let mut codec = ws::Codec::new().max_size(env::max_file_bytes() * 2 + 1024);
+    let mut buffer = BytesMut::new();
+    let mut heartbeat = time::interval_at(
+        time::Instant::now() + HEARTBEAT_INTERVAL,
+        HEARTBEAT_INTERVAL,
+    );
+    let mut last_heard = Instant::now();
+
+    'socket: loop {
+        loop {
+            let frame = match codec.decode(&mut buffer) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => {
+                    eprintln!("[collab] protocol error from peer {}: {}", peer_id, err);
+                    break 'socket;
+                }
+            };
+            last_heard = Instant::now();
+
+            match frame {
+                ws::Frame::Text(text) => {
+                    let message = serde_json::from_slice::<ClientMessage>(&text)
+                        .map_err(|err| CollabError::InvalidMessage(err.to_string()));
+                    let handled = match message {
+                        Ok(message) => {
+                            let is_save = matches!(message, ClientMessage::Save);
+                            // Only checks that the peer may save, the files are written here
+                            let handled = collab::lock(&room).handle(peer_id, message);
+                            if is_save && handled.is_ok() {
+                                save(&room, peer_id, client_ip).await;
+                            }
+                            handled
+                        }
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = handled {
+                        collab::lock(&room).send(peer_id, (&err).into());
+                    }
+                }
+                ws::Frame::Binary(_) | ws::Frame::Continuation(_) => {
+                    let err = CollabError::InvalidMessage("expected a text frame".to_owned());
+                    collab::lock(&room).send(peer_id, (&err).into());
+                }
+                ws::Frame::Ping(bytes) => {
+                    let _ = control.unbounded_send(ws::Message::Pong(bytes));
+                }
+                ws::Frame::Pong(_) => {}
+                ws::Frame::Close(_) => {
+                    let _ = control.unbounded_send(ws::Message::Close(None));
+                    break 'socket;
+                }
+            }
+        }
+
+        let tick = heartbeat.tick();
+        futures::pin_mut!(tick);
+        match future::select(payload.next(), tick).await {
+            Either::Left((Some(Ok(chunk)), _)) => buffer.extend_from_slice(&chunk),
+            Either::Left((None, _)) | Either::Left((Some(Err(_)), _)) => break,
+            Either::Right(_) => {
+                if last_heard.elapsed() > HEARTBEAT_INTERVAL * 2 {
+                    let _ = control.unbounded_send(ws::Message::Close(None));
+                    break;
+                }
+                let _ = control.unbounded_send(ws::Message::Ping(web::Bytes::new()));
+            }
+        }
+    }
+
+    let session_id = {
+        let mut room = collab::lock(&room);
+        room.leave(peer_id);
+        room.session_id().to_owned()
+    };
+    if collab::lock(&room).is_empty() {
+        if let Err(err) = write_room(&room).await {
+            eprintln!("[collab] writing session {}: {:?}", session_id, err);
+        }
+        collab::close_if_empty(&session_id);
+    }
+}
+
+/// Write the room's files for a peer's `save` message, then reload the session's frames and
+/// tell everyone the new revision.
+async fn save(room: &Arc<Mutex<Room>>, peer_id: PeerId, client_ip: Option<IpAddr>) {
+    let session_id = collab::lock(room).session_id().to_owned();
+    let revision = match write_room(room).await {
+        Ok(Some(revision)) => Ok(revision),
+        // Nothing changed, but the frames should still run again
+        Ok(None) => session_revision(&session_id).await,
+        Err(err) => Err(err),
+    };
+
+    let room = collab::lock(room);
+    match revision {
+        Ok(revision) => {
+            publish_files_changed(&session_id, room.file_kinds(), revision, client_ip);
+            let saved = ServerMessage::Saved { revision, peer_id };
+            room.send(peer_id, saved.clone());
+            room.broadcast(peer_id, saved);
+        }
+        Err(err) => {
+            let err = ApiError::from(err);
+            room.send(
+                peer_id,
+                ServerMessage::Error {
+                    code: err.code(),
+                    message: err.to_string(),
+                },
+            );
+        }
+    }
+}
+
+async fn session_revision(session_id: &str) -> DbResult<i64> {
+    let (_, revision) = Db::open_env()
+        .await?
+        .get_session_revision(session_id)
+        .await?;
+    Ok(revision)
+}
+
+/// Write the files changed since the last write, as a new session revision. Returns the
+/// revision, or None if there was nothing to write.
+async fn write_room(room: &Arc<Mutex<Room>>) -> DbResult<Option<i64>> {
+    let write_lock = collab::lock(room).write_lock();
+    let _writing = write_lock.lock().await;
+
+    let (session_id, files) = {
+        let room = collab::lock(room);
+        (room.session_id().to_owned(), room.unsaved_files())
+    };
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let writes = FileWrites::Full {
+        files: files
+            .iter()
+            .map(|(file, _)| {
+                (
+                    file.kind.to_default_name().to_owned(),
+                    file.contents.clone(),
+                )
+            })
+            .collect(),
+        file_kinds: None,
+    };
+    let (_, written) = Db::open_env()
+        .await?
+        .write_session_files(&session_id, None, writes)
+        .await?;
+    // Only the session being gone stops an unconditional write
+    let revision = match written {
+        SessionWrite::Written(revision) => Some(revision),
+        _ => None,
+    };
+
+    let mut room = collab::lock(room);
+    for (file, doc_revision) in files {
+        room.mark_saved(file.kind, doc_revision);
+    }
+    Ok(revision)
+}
+
+/// Write the files of every room every few seconds, so little is lost if the server stops.
+pub async fn write_rooms_job() {
+    let secs = env::collab_flush_secs().max(1) as u64;
+    let mut interval = time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+
+        for room in collab::all_rooms() {
+            if let Err(err) = write_room(&room).await {
+                eprintln!("[write_rooms_job]: {:?}", anyhow::Error::from(err));
+            }
+        }
+    }
+}
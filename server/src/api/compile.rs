@@ -0,0 +1,107 @@
+use crate::{
+    compile_service::{babel_compile, CompileError, CompileOptions},
+    db::{Db, DbResult},
+    hash::sha1_hex,
+    http_error::{ErrorMime, HttpError},
+    imports::DetectedDeps,
+    rate_limit::{self, Budget},
+    state::{CompileCache, FileKind},
+};
+use std::net::IpAddr;
+
+fn source_hash(code: &str, options: CompileOptions) -> String {
+    sha1_hex(format!("skypack={}\n{}", options.skypack, code))
+}
+
+/// Get the cache entry for this exact source, or a fresh one (with deps detected) if the
+/// source changed. The bool is true if the entry is fresh and hasn't been stored yet.
+async fn load_cache(
+    db: Db,
+    session_id: &str,
+    file_name: &str,
+    code: &str,
+    options: CompileOptions,
+) -> DbResult<(Db, CompileCache, bool)> {
+    let source_hash = source_hash(code, options);
+    let (db, cache) = db.get_compile_cache(session_id, file_name).await?;
+
+    Ok(match cache {
+        Some(cache) if cache.source_hash == source_hash => (db, cache, false),
+        _ => {
+            let cache = CompileCache {
+                source_hash,
+                code: None,
+                deps: DetectedDeps::detect(code),
+            };
+            (db, cache, true)
+        }
+    })
+}
+
+/// Compile page.js or page.test.js with babel, reusing the previous output if the source
+/// hasn't changed. Only cache misses count against the client's compile budget.
+pub async fn compile_cached(
+    db: Db,
+    session_id: &str,
+    file_name: &str,
+    code: &str,
+    options: CompileOptions,
+    client_ip: Option<IpAddr>,
+    err_mime: ErrorMime,
+) -> Result<(Db, String), HttpError> {
+    let db_err = |err| HttpError::db_error(err).with_mime(err_mime);
+
+    let (db, mut cache, _) = load_cache(db, session_id, file_name, code, options)
+        .await
+        .map_err(db_err)?;
+    if let Some(compiled) = cache.code {
+        return Ok((db, compiled));
+    }
+
+    if let Err(limited) = rate_limit::check(Budget::Compile, client_ip) {
+        return Err(HttpError::rate_limited(limited).with_mime(err_mime));
+    }
+
+    let compiled = match babel_compile(code, options).await {
+        Ok(compiled) => compiled,
+        Err(CompileError::Compile { err_id, message }) => {
+            if &err_id == "ject_compile::babel::compiler_error" {
+                return Err(HttpError::js_compile_fail(message).with_mime(err_mime));
+            } else {
+                let message = format!(
+                    "Unknown compiler err_id of {}.\nMessage: {}",
+                    err_id, message
+                );
+                return Err(HttpError::js_compile_fail(message).with_mime(err_mime));
+            }
+        }
+        Err(err) => {
+            return Err(HttpError::js_compile_fail(err).with_mime(err_mime));
+        }
+    };
+
+    cache.code = Some(compiled.clone());
+    let db = db
+        .put_compile_cache(session_id, file_name, cache)
+        .await
+        .map_err(db_err)?;
+
+    Ok((db, compiled))
+}
+
+/// The imports/packages used by page.js, cached alongside the compile output.
+pub async fn detect_deps(
+    db: Db,
+    session_id: &str,
+    code: &str,
+    options: CompileOptions,
+) -> DbResult<(Db, DetectedDeps)> {
+    let file_name = FileKind::JavaScript.to_default_name();
+    let (mut db, cache, fresh) = load_cache(db, session_id, file_name, code, options).await?;
+    let deps = cache.deps.clone();
+    if fresh {
+        db = db.put_compile_cache(session_id, file_name, cache).await?;
+    }
+
+    Ok((db, deps))
+}
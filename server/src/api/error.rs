@@ -0,0 +1,242 @@
+use crate::{
+    archive::ArchiveError,
+    db::DbError,
+    patch::PatchError,
+    rate_limit::RateLimited,
+    state::{FileKind, SessionState},
+};
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use thiserror::Error;
+
+pub type ApiResult<T, E = ApiError> = Result<T, E>;
+
+/// Errors of the JSON API routes, rendered as `{code, message}` like [DbError].
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error(transparent)]
+    Db(#[from] DbError),
+
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+
+    #[error("Request body is larger than the limit of {max} bytes")]
+    PayloadTooLarge { max: usize },
+
+    #[error("Invalid JSON request body: {source}")]
+    InvalidJson { source: JsonPayloadError },
+
+    #[error("Missing the x-edit-token header")]
+    MissingEditToken,
+
+    #[error("The edit token doesn't match")]
+    InvalidEditToken,
+
+    #[error("This session was made before edit tokens and is read-only, save it to fork it")]
+    SessionReadOnly,
+
+    #[error("This save has been deleted")]
+    SavedGone,
+
+    #[error("Only the owner of this save can change it")]
+    SavedForbidden,
+
+    #[error("{0}")]
+    InvalidDetails(String),
+
+    #[error("Invalid query string: {0}")]
+    InvalidQuery(String),
+
+    #[error("Usernames must be 3 to 32 letters, digits, '-' or '_'")]
+    InvalidUsername,
+
+    #[error("Passwords must be 8 to 1024 characters")]
+    InvalidPassword,
+
+    #[error("That username is taken")]
+    UsernameTaken,
+
+    #[error("Incorrect username or password")]
+    InvalidCredentials,
+
+    #[error("You need to log in or provide an API token")]
+    Unauthenticated,
+
+    #[error("The API token is invalid or has been revoked")]
+    InvalidApiToken,
+
+    #[error("No API token with that id")]
+    ApiTokenNotFound,
+
+    #[error("Only admins can do this")]
+    AdminOnly,
+
+    #[error("No template with that name")]
+    TemplateNotFound,
+
+    #[error("{0}")]
+    InvalidTemplate(String),
+
+    #[error("{} has changed since the base revision of the patch", kind.to_default_name())]
+    PatchConflict { kind: FileKind },
+
+    #[error("Can't apply the patch to {}: {source}", kind.to_default_name())]
+    InvalidPatch { kind: FileKind, source: PatchError },
+
+    #[error("Invalid If-Match header {0:?}, expected a session revision")]
+    InvalidIfMatch(String),
+
+    #[error(
+        "The session has changed since revision {expected}, it's now at revision {}",
+        current.revision
+    )]
+    RevisionConflict {
+        expected: i64,
+        /// Included in the response, so the client can merge or reload
+        current: Box<SessionState>,
+    },
+
+    #[error("Expected a WebSocket request: {0}")]
+    NotWebSocket(String),
+
+    #[error("The session is being edited collaboratively, send changes over its collab socket")]
+    CollabActive,
+
+    #[error("The run token is invalid or expired, reload the page")]
+    InvalidRunToken,
+
+    #[error("Invalid console log batch: {0}")]
+    InvalidLogBatch(String),
+
+    #[error("Invalid test results: {0}")]
+    InvalidTestRun(String),
+
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+
+    #[error("{0}")]
+    InvalidArchive(String),
+
+    #[error("Too many files ({count}), the limit is {max}")]
+    TooManyFiles { count: usize, max: usize },
+
+    #[error("{} is {size} bytes, the limit is {max} bytes", kind.to_default_name())]
+    FileTooLarge {
+        kind: FileKind,
+        size: usize,
+        max: usize,
+    },
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Db(err) => err.code(),
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::PayloadTooLarge { .. } => "payload_too_large",
+            ApiError::InvalidJson { .. } => "invalid_json",
+            ApiError::MissingEditToken => "missing_edit_token",
+            ApiError::InvalidEditToken => "invalid_edit_token",
+            ApiError::SessionReadOnly => "session_read_only",
+            ApiError::SavedGone => "saved_gone",
+            ApiError::SavedForbidden => "saved_forbidden",
+            ApiError::InvalidDetails(_) => "invalid_details",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::InvalidUsername => "invalid_username",
+            ApiError::InvalidPassword => "invalid_password",
+            ApiError::UsernameTaken => "username_taken",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Unauthenticated => "unauthenticated",
+            ApiError::InvalidApiToken => "invalid_api_token",
+            ApiError::ApiTokenNotFound => "api_token_not_found",
+            ApiError::AdminOnly => "admin_only",
+            ApiError::TemplateNotFound => "template_not_found",
+            ApiError::InvalidTemplate(_) => "invalid_template",
+            ApiError::PatchConflict { .. } => "patch_conflict",
+            ApiError::InvalidPatch { .. } => "invalid_patch",
+            ApiError::InvalidIfMatch(_) => "invalid_if_match",
+            ApiError::RevisionConflict { .. } => "revision_conflict",
+            ApiError::NotWebSocket(_) => "not_websocket",
+            ApiError::CollabActive => "collab_active",
+            ApiError::InvalidRunToken => "invalid_run_token",
+            ApiError::InvalidLogBatch(_) => "invalid_log_batch",
+            ApiError::InvalidTestRun(_) => "invalid_test_run",
+            ApiError::Archive(_) | ApiError::InvalidArchive(_) => "invalid_archive",
+            ApiError::TooManyFiles { .. } => "too_many_files",
+            ApiError::FileTooLarge { .. } => "file_too_large",
+        }
+    }
+
+    /// Used by the API's `JsonConfig` so body errors have the same shape as the others.
+    pub fn from_json_payload(error: JsonPayloadError, max: usize) -> Self {
+        match error {
+            JsonPayloadError::Overflow => ApiError::PayloadTooLarge { max },
+            source => ApiError::InvalidJson { source },
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Db(err) => err.status_code(),
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Archive(
+                ArchiveError::TooManyEntries { .. }
+                | ArchiveError::EntryTooLarge { .. }
+                | ArchiveError::TooLarge { .. },
+            )
+            | ApiError::PayloadTooLarge { .. }
+            | ApiError::TooManyFiles { .. }
+            | ApiError::FileTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::InvalidJson { .. }
+            | ApiError::InvalidDetails(_)
+            | ApiError::InvalidQuery(_)
+            | ApiError::InvalidUsername
+            | ApiError::InvalidPassword
+            | ApiError::InvalidPatch { .. }
+            | ApiError::InvalidIfMatch(_)
+            | ApiError::NotWebSocket(_)
+            | ApiError::InvalidLogBatch(_)
+            | ApiError::InvalidTestRun(_)
+            | ApiError::Archive(_)
+            | ApiError::InvalidArchive(_)
+            | ApiError::InvalidTemplate(_) => StatusCode::BAD_REQUEST,
+            ApiError::UsernameTaken
+            | ApiError::PatchConflict { .. }
+            | ApiError::RevisionConflict { .. }
+            | ApiError::CollabActive => StatusCode::CONFLICT,
+            ApiError::MissingEditToken
+            | ApiError::InvalidCredentials
+            | ApiError::Unauthenticated
+            | ApiError::InvalidApiToken => StatusCode::UNAUTHORIZED,
+            ApiError::ApiTokenNotFound | ApiError::TemplateNotFound => StatusCode::NOT_FOUND,
+            ApiError::SavedGone => StatusCode::GONE,
+            ApiError::InvalidEditToken
+            | ApiError::SessionReadOnly
+            | ApiError::InvalidRunToken
+            | ApiError::SavedForbidden
+            | ApiError::AdminOnly => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ApiError::Db(err) => return err.error_response(),
+            ApiError::RateLimited(limited) => {
+                response.header("retry-after", limited.retry_after.to_string());
+            }
+            ApiError::RevisionConflict { current, .. } => {
+                return response.json(json!({
+                    "code": self.code(),
+                    "message": self.to_string(),
+                    "current": current,
+                }));
+            }
+            _ => {}
+        }
+
+        response.json(json!({ "code": self.code(), "message": self.to_string() }))
+    }
+}
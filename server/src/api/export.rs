@@ -0,0 +1,450 @@
+use crate::{
+    api::{
+        compile::{compile_cached, detect_deps},
+        frame::{load_page_deps, render_page, PageLinks},
+        scaffold::{npm_path, npm_project},
+        util::get_files,
+    },
+    archive::{write_archive, ArchiveFormat},
+    cdn::cdnjs_src,
+    compile_service::CompileOptions,
+    db::{unix_now, Db},
+    forwarded::ClientInfo,
+    http,
+    http_error::{ErrorMime, HttpError},
+    import_map::DepsManifest,
+    imports::DetectedDeps,
+    parser::parse_html,
+    state::{File, FileKind, SavedDetails, SessionMeta},
+};
+use actix_web::{client::Client, get, http::StatusCode, web, HttpResponse};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+
+/// Most bytes of a script downloaded for `?vendor=true`.
+const MAX_VENDOR_BYTES: usize = 8 * 1024 * 1024;
+
+/// Scripts already downloaded for `?vendor=true`, by cdnjs path. The paths include the
+/// version, so the contents don't change.
+static VENDORED: Lazy<Mutex<HashMap<&'static str, String>>> = Lazy::new(Default::default);
+
+/// A session or save to export, with its files.
+pub struct Export {
+    pub id: String,
+    pub meta: SessionMeta,
+    pub files: Vec<File>,
+    /// The title, description and tags, for saves
+    pub details: Option<SavedDetails>,
+}
+
+impl Export {
+    pub fn file(&self, kind: FileKind) -> Option<&str> {
+        let file = self.files.iter().find(|file| file.kind == kind);
+        file.map(|file| file.contents.as_str())
+    }
+}
+
+/// Load a session, or a save that hasn't been deleted, to export.
+pub async fn load_export(
+    db: Db,
+    id: &str,
+    saved: bool,
+    err_mime: ErrorMime,
+) -> Result<(Db, Export), HttpError> {
+    let db_err = |err| HttpError::db_error(err).with_mime(err_mime);
+    let (db, meta, details) = if saved {
+        let (db, info) = db.get_saved_info(id).await.map_err(db_err)?;
+        if info.deleted_at.is_some() {
+            return Err(HttpError::saved_gone().with_mime(err_mime));
+        }
+        let (db, meta) = db.get_saved(id).await.map_err(db_err)?;
+        let (db, details) = db.get_saved_details(id).await.map_err(db_err)?;
+        (db, meta, Some(details))
+    } else {
+        let (db, meta) = db.get_session(id).await.map_err(db_err)?;
+        (db, meta, None)
+    };
+    let (db, files) = get_files(db, id, &meta).await.map_err(db_err)?;
+
+    let export = Export {
+        id: id.to_owned(),
+        meta,
+        files,
+        details,
+    };
+    Ok((db, export))
+}
+
+/// Compile page.js of an export, or None if it has none. Compile errors fail the export.
+pub async fn compile_export(
+    db: Db,
+    export: &Export,
+    client_ip: Option<IpAddr>,
+    err_mime: ErrorMime,
+) -> Result<(Db, Option<String>), HttpError> {
+    let code = match export.file(FileKind::JavaScript) {
+        Some(code) => code,
+        None => return Ok((db, None)),
+    };
+    let options = CompileOptions::for_file_kinds(&export.meta.file_kinds);
+    let file_name = FileKind::JavaScript.to_default_name();
+    match compile_cached(
+        db, &export.id, file_name, code, options, client_ip, err_mime,
+    )
+    .await
+    {
+        Ok((db, compiled)) => Ok((db, Some(compiled))),
+        // Sent with 200 for the frame, where the body is shown instead
+        Err(mut err) => {
+            if err.status.is_success() {
+                err.status = StatusCode::UNPROCESSABLE_ENTITY;
+            }
+            Err(err)
+        }
+    }
+}
+
+fn data_url(mime: &str, contents: &str) -> String {
+    format!("data:{};base64,{}", mime, base64::encode(contents))
+}
+
+/// Download the cdnjs scripts at these paths.
+async fn vendor_scripts(
+    paths: Vec<&'static str>,
+    err_mime: ErrorMime,
+) -> Result<HashMap<&'static str, String>, HttpError> {
+    let fail = |message: String| HttpError::vendor_fail(message).with_mime(err_mime);
+    let client = Client::default();
+    let mut scripts = HashMap::new();
+    for path in paths {
+        let cached = VENDORED
+            .lock()
+            .expect("ject: vendored lock")
+            .get(path)
+            .cloned();
+        if let Some(code) = cached {
+            scripts.insert(path, code);
+            continue;
+        }
+
+        let src = cdnjs_src(path);
+        let mut res = client
+            .get(&src)
+            .timeout(Duration::from_secs(20))
+            .send()
+            .await
+            .map_err(|err| fail(format!("{}: {}", src, err)))?;
+        if !res.status().is_success() {
+            return Err(fail(format!("{} responded with {}", src, res.status())));
+        }
+        let body = res
+            .body()
+            .limit(MAX_VENDOR_BYTES)
+            .await
+            .map_err(|err| fail(format!("{}: {}", src, err)))?;
+        let code = String::from_utf8_lossy(&body).into_owned();
+
+        VENDORED
+            .lock()
+            .expect("ject: vendored lock")
+            .insert(path, code.clone());
+        scripts.insert(path, code);
+    }
+    Ok(scripts)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HtmlExportParams {
+    /// Inline the cdnjs scripts of `inject!(deps…)` instead of linking to them
+    #[serde(default)]
+    vendor: bool,
+}
+
+/// page.html with its `inject!` directives resolved, as one file that works without the
+/// server: the compiled page.js and page.css are inlined as data URLs. `inject!(console)` and
+/// `inject!(tests)` are left out, as they need the editor.
+async fn export_html(
+    db: Db,
+    export: Export,
+    vendor: bool,
+    client_ip: Option<IpAddr>,
+    err_mime: ErrorMime,
+) -> Result<HttpResponse, HttpError> {
+    let html = match export.file(FileKind::Html) {
+        Some(html) => html,
+        None => return Err(HttpError::file_not_found(err_mime).with_mime(err_mime)),
+    };
+    let parts = match parse_html(html) {
+        Ok(parts) => parts,
+        Err(err) => return Err(HttpError::invalid_html(err).with_mime(err_mime)),
+    };
+
+    let (db, deps) = load_page_deps(db, &export.id, &export.meta, &parts, err_mime).await?;
+    let (_, compiled) = compile_export(db, &export, client_ip, err_mime).await?;
+    let vendored = if vendor {
+        vendor_scripts(deps.umd_scripts(&parts), err_mime).await?
+    } else {
+        HashMap::new()
+    };
+
+    let file = |kind| export.file(kind).unwrap_or_default();
+    let links = PageLinks {
+        js: data_url("text/javascript", compiled.as_deref().unwrap_or_default()),
+        js_raw: data_url("text/javascript", file(FileKind::JavaScript)),
+        css: data_url("text/css", file(FileKind::Css)),
+        tests: String::new(),
+        frame: false,
+        run_token: None,
+        import_maps: true,
+        vendored,
+    };
+    let html = render_page(parts, &deps, &links)
+        .map_err(|err| HttpError::generate_html_fail(err).with_mime(err_mime))?;
+
+    Ok(HttpResponse::Ok()
+        .header("content-type", "text/html; charset=utf-8")
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"ject-{}.html\"", export.id),
+        )
+        // Never run as a page of the main domain, in case it's opened rather than downloaded
+        .header("content-security-policy", "sandbox")
+        .header("x-content-type-options", "nosniff")
+        .body(html))
+}
+
+#[get("/session/{session_id}/export.html", wrap = "http::MAIN_API")]
+pub async fn r_get_session_export_html(
+    info: web::Path<String>,
+    params: web::Query<HtmlExportParams>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let db = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+    let (db, export) = load_export(db, &info.0, false, err_mime).await?;
+    export_html(db, export, params.vendor, client.ip, err_mime).await
+}
+
+#[get("/saved/{saved_id}/export.html", wrap = "http::MAIN_API")]
+pub async fn r_get_saved_export_html(
+    info: web::Path<String>,
+    params: web::Query<HtmlExportParams>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let db = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+    let (db, export) = load_export(db, &info.0, true, err_mime).await?;
+    export_html(db, export, params.vendor, client.ip, err_mime).await
+}
+
+/// Version of the manifest.json written to archives.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A file listed in the manifest.json of an archive, by its path in the archive's folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub name: String,
+    pub kind: FileKind,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestDeps {
+    /// Packages imported by page.js
+    detected: Vec<String>,
+    /// The contents of deps.json, if it's valid
+    declared: Option<DepsManifest>,
+}
+
+/// manifest.json of an exported archive. [super::import] only needs the version and files.
+#[derive(Debug, Serialize)]
+struct ExportManifest<'a> {
+    version: u32,
+    /// The session or save that was exported
+    source: &'a str,
+    exported_at: i64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    details: Option<&'a SavedDetails>,
+    files: Vec<ManifestFile>,
+    compiler: CompileOptions,
+    deps: ManifestDeps,
+}
+
+fn archive_readme(export: &Export, folder: &str) -> String {
+    let mut readme = String::new();
+    match &export.details {
+        Some(details) if !details.title.is_empty() => {
+            readme.push_str(&format!("# {}\n\n", details.title));
+        }
+        _ => readme.push_str(&format!("# {}\n\n", folder)),
+    }
+    if let Some(details) = export
+        .details
+        .as_ref()
+        .filter(|d| !d.description.is_empty())
+    {
+        readme.push_str(&format!("{}\n\n", details.description.trim_end()));
+    }
+    readme.push_str(&format!("Exported from ject ({}).\n\n", export.id));
+    for file in &export.files {
+        let name = file.kind.to_default_name();
+        readme.push_str(&format!("- `{}`: {}\n", name, file.kind.to_variant_name()));
+    }
+    readme.push_str(concat!(
+        "\n`page.html` uses `inject!(…)` directives, which ject replaces with the compiled\n",
+        "page.js, page.css, import maps and scripts when it serves the page.\n",
+        "`manifest.json` lists the files and the dependencies page.js imports.\n\n",
+        "To open this in ject again, upload the archive to `POST /api/import`:\n\n",
+        "    curl --data-binary @archive.zip https://<ject host>/api/import\n",
+    ));
+    readme
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveLayout {
+    /// The files with their default names
+    #[default]
+    Files,
+    /// A Vite project, see [super::scaffold]
+    Npm,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveParams {
+    #[serde(default)]
+    layout: ArchiveLayout,
+}
+
+/// The files of a session or save in a zip or tar.gz, in a folder named after it, with a
+/// manifest.json that [super::import] reads back and a README.md. With the npm layout, the
+/// folder is a project that builds the page instead.
+async fn export_archive(
+    db: Db,
+    export: Export,
+    format: ArchiveFormat,
+    layout: ArchiveLayout,
+    err_mime: ErrorMime,
+) -> Result<HttpResponse, HttpError> {
+    let (db, detected) = match export.file(FileKind::JavaScript) {
+        Some(code) => {
+            let options = CompileOptions::for_file_kinds(&export.meta.file_kinds);
+            detect_deps(db, &export.id, code, options)
+                .await
+                .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?
+        }
+        None => (db, DetectedDeps::default()),
+    };
+    let declared = export
+        .file(FileKind::Deps)
+        .and_then(|deps| DepsManifest::parse(deps).ok());
+
+    let folder = format!("ject-{}", export.id);
+    let file_path = |kind: FileKind| match layout {
+        ArchiveLayout::Files => Some(kind.to_default_name()),
+        ArchiveLayout::Npm => npm_path(kind),
+    };
+    let mut contents: Vec<(String, String)> = vec![];
+    let mut files = vec![];
+    for file in &export.files {
+        if let Some(name) = file_path(file.kind) {
+            contents.push((name.to_owned(), file.contents.clone()));
+            files.push(ManifestFile {
+                name: name.to_owned(),
+                kind: file.kind,
+            });
+        }
+    }
+    match layout {
+        ArchiveLayout::Files => {
+            contents.push(("README.md".to_owned(), archive_readme(&export, &folder)));
+        }
+        ArchiveLayout::Npm => {
+            let packages = &detected.packages;
+            let (_, project) =
+                npm_project(db, &export, packages, declared.as_ref(), err_mime).await?;
+            contents.extend(project);
+        }
+    }
+
+    let manifest = ExportManifest {
+        version: MANIFEST_VERSION,
+        source: &export.id,
+        exported_at: unix_now(),
+        details: export.details.as_ref(),
+        files,
+        compiler: CompileOptions::for_file_kinds(&export.meta.file_kinds),
+        deps: ManifestDeps {
+            detected: detected.packages,
+            declared,
+        },
+    };
+    let manifest = serde_json::to_string_pretty(&manifest).expect("ject: manifest to json");
+    contents.push(("manifest.json".to_owned(), manifest));
+
+    let entries: Vec<(String, &[u8])> = contents
+        .iter()
+        .map(|(name, contents)| (format!("{}/{}", folder, name), contents.as_bytes()))
+        .collect();
+    let archive = write_archive(format, &entries)
+        .map_err(|err| HttpError::export_archive_fail(err).with_mime(err_mime))?;
+
+    Ok(HttpResponse::Ok()
+        .header("content-type", format.mime())
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"{}.{}\"", folder, format.extension()),
+        )
+        .header("x-content-type-options", "nosniff")
+        .body(archive))
+}
+
+async fn get_export_archive(
+    id: &str,
+    saved: bool,
+    format: ArchiveFormat,
+    params: ArchiveParams,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let db = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+    let (db, export) = load_export(db, id, saved, err_mime).await?;
+    export_archive(db, export, format, params.layout, err_mime).await
+}
+
+#[get("/session/{session_id}/export.zip", wrap = "http::MAIN_API")]
+pub async fn r_get_session_export_zip(
+    info: web::Path<String>,
+    params: web::Query<ArchiveParams>,
+) -> Result<HttpResponse, HttpError> {
+    get_export_archive(&info.0, false, ArchiveFormat::Zip, params.into_inner()).await
+}
+
+#[get("/session/{session_id}/export.tar.gz", wrap = "http::MAIN_API")]
+pub async fn r_get_session_export_tar_gz(
+    info: web::Path<String>,
+    params: web::Query<ArchiveParams>,
+) -> Result<HttpResponse, HttpError> {
+    get_export_archive(&info.0, false, ArchiveFormat::TarGz, params.into_inner()).await
+}
+
+#[get("/saved/{saved_id}/export.zip", wrap = "http::MAIN_API")]
+pub async fn r_get_saved_export_zip(
+    info: web::Path<String>,
+    params: web::Query<ArchiveParams>,
+) -> Result<HttpResponse, HttpError> {
+    get_export_archive(&info.0, true, ArchiveFormat::Zip, params.into_inner()).await
+}
+
+#[get("/saved/{saved_id}/export.tar.gz", wrap = "http::MAIN_API")]
+pub async fn r_get_saved_export_tar_gz(
+    info: web::Path<String>,
+    params: web::Query<ArchiveParams>,
+) -> Result<HttpResponse, HttpError> {
+    get_export_archive(&info.0, true, ArchiveFormat::TarGz, params.into_inner()).await
+}
@@ -1,63 +1,108 @@
 use crate::{
-    cdn::cdnjs_script,
+    api::{
+        compile::{compile_cached, detect_deps},
+        error::ApiResult,
+        util,
+    },
+    cdn::{cdnjs_script, skypack_src, umd_scripts},
+    compile_service::CompileOptions,
+    csp,
     db::Db,
-    env::domain_frame,
-    http::Host,
+    forwarded::ClientInfo,
+    http,
     http_error::{ErrorMime, HttpError},
+    hub::{self, SessionEvent},
+    import_map::{DepsManifest, ImportMap},
+    imports::DetectedDeps,
     parser::{parse_html, HtmlPart},
-    state::FileKind,
+    rate_limit::{self, Budget},
+    run_token,
+    state::{FileKind, SessionMeta},
 };
-use actix_web::{get, web, HttpResponse};
+use actix_rt::time::delay_for;
+use actix_web::{dev::HttpServiceFactory, get, web, HttpResponse};
+use futures::{future, stream, StreamExt};
+use std::{collections::HashMap, time::Duration};
 
-async fn try_get_file(
+pub async fn try_get_file(
     db: Db,
     session_id: &str,
     err_mime: ErrorMime,
     file_kind: FileKind,
-) -> Result<(Db, String), HttpError> {
-    let (db, _session) = db
+) -> Result<(Db, SessionMeta, String), HttpError> {
+    let (db, session) = db
         .get_session(session_id)
         .await
         .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
 
-    let res = db
+    let (db, contents) = db
         .get_file(session_id, file_kind.to_default_name())
         .await
         .map_err(|_err| HttpError::file_not_found(err_mime).with_mime(err_mime))?;
 
-    Ok(res)
+    Ok((db, session, contents))
 }
 
-#[get("/session/{session_id}/page.js")]
-pub async fn r_get_session_page_js(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+/// Like [try_get_file], for a save that hasn't been deleted.
+pub async fn try_get_saved_file(
+    db: Db,
+    saved_id: &str,
+    err_mime: ErrorMime,
+    file_kind: FileKind,
+) -> Result<(Db, SessionMeta, String), HttpError> {
+    let db_err = |err| HttpError::db_error(err).with_mime(err_mime);
+    let (db, info) = db.get_saved_info(saved_id).await.map_err(db_err)?;
+    if info.deleted_at.is_some() {
+        return Err(HttpError::saved_gone().with_mime(err_mime));
+    }
+    let (db, meta) = db.get_saved(saved_id).await.map_err(db_err)?;
+
+    let (db, contents) = db
+        .get_file(saved_id, file_kind.to_default_name())
+        .await
+        .map_err(|_err| HttpError::file_not_found(err_mime).with_mime(err_mime))?;
+
+    Ok((db, meta, contents))
+}
+
+/// A file of the session, or of the save for the `/saved/…` routes.
+async fn get_page_file(
+    db: Db,
+    id: &str,
+    saved: bool,
+    err_mime: ErrorMime,
+    file_kind: FileKind,
+) -> Result<(Db, SessionMeta, String), HttpError> {
+    if saved {
+        try_get_saved_file(db, id, err_mime, file_kind).await
+    } else {
+        try_get_file(db, id, err_mime, file_kind).await
+    }
+}
+
+async fn page_js(id: &str, saved: bool, client: ClientInfo) -> Result<HttpResponse, HttpError> {
     let err_mime = ErrorMime::JavaScript;
     let db = Db::open_env()
         .await
         .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
-    let session_id = info.0;
-    let (_, code) = try_get_file(db, &session_id, err_mime, FileKind::JavaScript).await?;
-
-    let code = match crate::compile_service::babel_compile(&code).await {
-        Ok(code) => code,
-        Err(crate::compile_service::CompileError::Compile { err_id, message }) => {
-            if &err_id == "ject_compile::babel::compiler_error" {
-                return Err(HttpError::js_compile_fail(message).with_mime(err_mime));
-            } else {
-                let message = format!(
-                    "Unknown compiler err_id of {}.\nMessage: {}",
-                    err_id, message
-                );
-                return Err(HttpError::js_compile_fail(message).with_mime(err_mime));
-            }
-        }
-        Err(err) => {
-            return Err(HttpError::js_compile_fail(err).with_mime(err_mime));
-        }
-    };
+    let (db, meta, code) = get_page_file(db, id, saved, err_mime, FileKind::JavaScript).await?;
+
+    let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+    let file_name = FileKind::JavaScript.to_default_name();
+    let (_, code) = compile_cached(db, id, file_name, &code, options, client.ip, err_mime).await?;
 
     Ok(HttpResponse::Ok()
         .header("content-type", "application/javascript; charset=utf-8")
+        .header("x-content-type-options", "nosniff")
         .body(code))
+}
+
+#[get("/session/{session_id}/page.js", wrap = "http::FRAME_JS")]
+pub async fn r_get_session_page_js(
+    info: web::Path<String>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    page_js(&info.0, false, client).await
 
     // let session_id = info.0;
     // let err_mime = ErrorMime::JavaScript;
@@ -75,97 +120,348 @@ pub async fn r_get_session_page_js(info: web::Path<String>) -> Result<HttpRespon
     // })
 }
 
-#[get("/session/{session_id}/page.js.raw")]
-pub async fn r_get_session_page_js_raw(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+#[get("/saved/{saved_id}/page.js", wrap = "http::FRAME_JS")]
+pub async fn r_get_saved_page_js(
+    info: web::Path<String>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    page_js(&info.0, true, client).await
+}
+
+async fn page_js_raw(id: &str, saved: bool) -> Result<HttpResponse, HttpError> {
     let err_mime = ErrorMime::JavaScript;
     let db = Db::open_env()
         .await
         .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
-    let session_id = info.0;
-    let (_, code) = try_get_file(db, &session_id, err_mime, FileKind::JavaScript).await?;
+    let (_, _, code) = get_page_file(db, id, saved, err_mime, FileKind::JavaScript).await?;
 
     Ok(HttpResponse::Ok()
         .header("content-type", "application/javascript; charset=utf-8")
+        .header("x-content-type-options", "nosniff")
         .body(code))
 }
 
-#[get("/session/{session_id}/page.css")]
-pub async fn r_get_session_page_css(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+#[get("/session/{session_id}/page.js.raw", wrap = "http::FRAME_JS")]
+pub async fn r_get_session_page_js_raw(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_js_raw(&info.0, false).await
+}
+
+#[get("/saved/{saved_id}/page.js.raw", wrap = "http::FRAME_JS")]
+pub async fn r_get_saved_page_js_raw(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_js_raw(&info.0, true).await
+}
+
+async fn page_css(id: &str, saved: bool) -> Result<HttpResponse, HttpError> {
     let err_mime = ErrorMime::Css;
     let db = Db::open_env()
         .await
         .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
-    let session_id = info.0;
-    let (_, code) = try_get_file(db, &session_id, err_mime, FileKind::Css).await?;
+    let (_, _, code) = get_page_file(db, id, saved, err_mime, FileKind::Css).await?;
 
     Ok(HttpResponse::Ok()
         .header("content-type", "text/css; charset=utf-8")
+        .header("x-content-type-options", "nosniff")
         .body(code))
 }
 
-#[get("/session/{session_id}/page")]
-pub async fn r_get_session_page_html(
-    info: web::Path<String>,
-    host: Host,
-) -> Result<HttpResponse, HttpError> {
-    let err_mime = ErrorMime::Html;
-    let domain_frame = domain_frame();
-    if !host.matches(&domain_frame) {
-        return Err(HttpError::invalid_host(&domain_frame).with_mime(err_mime));
+#[get("/session/{session_id}/page.css", wrap = "http::FRAME_CSS")]
+pub async fn r_get_session_page_css(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_css(&info.0, false).await
+}
+
+#[get("/saved/{saved_id}/page.css", wrap = "http::FRAME_CSS")]
+pub async fn r_get_saved_page_css(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_css(&info.0, true).await
+}
+
+/// The import maps and UMD scripts page.html can inject, from deps.json and the packages
+/// page.js imports.
+pub struct PageDeps {
+    import_map: ImportMap,
+    auto_import_map: ImportMap,
+    auto_umd: Vec<&'static str>,
+}
+
+impl PageDeps {
+    /// Where the page's import map loads modules from, to allow in its CSP.
+    pub fn script_origins(&self) -> Vec<String> {
+        self.auto_import_map.origins()
     }
 
-    let session_id = info.0;
-    let db = Db::open_env()
-        .await
-        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
-    let (_, html) = try_get_file(db, &session_id, err_mime, FileKind::Html).await?;
+    /// The cdnjs scripts included by the page's `inject!(deps…)` directives.
+    pub fn umd_scripts(&self, parts: &[HtmlPart<'_>]) -> Vec<&'static str> {
+        let mut scripts = vec![];
+        for part in parts {
+            let found = match part {
+                HtmlPart::IncludePath(path) => match path[..] {
+                    ["deps", "auto"] => self.auto_umd.clone(),
+                    ["deps", name] => umd_scripts(name).unwrap_or_default().to_vec(),
+                    _ => vec![],
+                },
+                HtmlPart::Literal(_) => vec![],
+            };
+            for script in found {
+                if !scripts.contains(&script) {
+                    scripts.push(script);
+                }
+            }
+        }
+        scripts
+    }
+}
 
-    let parts = match parse_html(&html) {
-        Ok(parts) => parts,
-        Err(err) => return Err(HttpError::invalid_html(err).with_mime(err_mime)),
+/// Where the page assembled from page.html gets the compiled files and scripts from.
+pub struct PageLinks {
+    /// URLs for `inject!(editors.js)`, `inject!(editors.js.raw)` and `inject!(editors.css)`
+    pub js: String,
+    pub js_raw: String,
+    pub css: String,
+    /// URL of the compiled page.test.js, for `inject!(tests)`. Empty for saves, whose
+    /// tests only run in sessions.
+    pub tests: String,
+    /// Include `inject!(console)` and `inject!(tests)`, which only work in the frame as they
+    /// talk to the editor and the frame API
+    pub frame: bool,
+    /// Lets the console script store its output, for sessions, see [crate::run_token]
+    pub run_token: Option<String>,
+    /// Include `inject!(importmap)`, unless a bundler resolves the imports instead
+    pub import_maps: bool,
+    /// The contents of cdnjs scripts by path, inlined instead of linked to
+    pub vendored: HashMap<&'static str, String>,
+}
+
+/// Load what the `inject!` directives of a session's or save's page.html need.
+pub async fn load_page_deps(
+    db: Db,
+    session_or_saved_id: &str,
+    meta: &SessionMeta,
+    parts: &[HtmlPart<'_>],
+    err_mime: ErrorMime,
+) -> Result<(Db, PageDeps), HttpError> {
+    let uses = |prefix: &[&str]| {
+        parts.iter().any(|part| match part {
+            HtmlPart::IncludePath(path) => path.starts_with(prefix),
+            HtmlPart::Literal(_) => false,
+        })
     };
+    let uses_import_map = uses(&["importmap"]);
+    let uses_auto = uses(&["importmap", "auto"]) || uses(&["deps", "auto"]);
 
-    let page_url = |suffix: &str| format!("/api/session/{}/page{}", session_id, suffix);
+    let (db, detected) = if uses_auto && meta.file_kinds.contains(&FileKind::JavaScript) {
+        let (db, code) = db
+            .get_file(session_or_saved_id, FileKind::JavaScript.to_default_name())
+            .await
+            .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+        let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+        detect_deps(db, session_or_saved_id, &code, options)
+            .await
+            .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?
+    } else {
+        (db, DetectedDeps::default())
+    };
+
+    let (db, import_map) = if uses_import_map && meta.file_kinds.contains(&FileKind::Deps) {
+        let (db, deps) = db
+            .get_file(session_or_saved_id, FileKind::Deps.to_default_name())
+            .await
+            .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+        match DepsManifest::parse(&deps) {
+            Ok(deps) => (db, deps.to_import_map()),
+            Err(err) => return Err(HttpError::invalid_deps(err).with_mime(err_mime)),
+        }
+    } else {
+        (db, ImportMap::default())
+    };
+
+    // Detected packages missing from deps.json, resolved to the latest version on Skypack
+    let mut auto_import_map = import_map.clone();
+    for package in &detected.packages {
+        if !auto_import_map.imports.contains_key(package) {
+            let src = skypack_src(package);
+            auto_import_map
+                .imports
+                .insert(format!("{}/", package), format!("{}/", src));
+            auto_import_map.imports.insert(package.clone(), src);
+        }
+    }
+
+    let mut auto_umd: Vec<&str> = vec![];
+    for package in &detected.packages {
+        for &script in umd_scripts(package).unwrap_or_default() {
+            if !auto_umd.contains(&script) {
+                auto_umd.push(script);
+            }
+        }
+    }
+
+    let deps = PageDeps {
+        import_map,
+        auto_import_map,
+        auto_umd,
+    };
+    Ok((db, deps))
+}
+
+/// A script element with the code inline. A closing tag in the code, in any case, would end
+/// the element early, so `</` is escaped as `<\/` there, which means the same in strings.
+fn inline_script(code: &str) -> String {
+    let mut out = String::with_capacity(code.len() + 17);
+    out.push_str("<script>");
+    let mut rest = code;
+    while let Some(index) = rest.find("</") {
+        let (before, after) = rest.split_at(index);
+        out.push_str(before);
+        let tag = after.get(2..8).unwrap_or_default();
+        out.push_str(if tag.eq_ignore_ascii_case("script") {
+            "<\\/"
+        } else {
+            "</"
+        });
+        rest = &after[2..];
+    }
+    out.push_str(rest);
+    out.push_str("</script>");
+    out
+}
+
+/// Resolve the `inject!` directives of page.html.
+pub fn render_page(
+    parts: Vec<HtmlPart<'_>>,
+    deps: &PageDeps,
+    links: &PageLinks,
+) -> anyhow::Result<String> {
     let public_path = |path: &str| format!("/dist/{}", path);
-    let public_script = |path: &str| format!("<script src=\"{}\"></script>", public_path(path));
+    // Scripts that send the page's output to the server, with the token that lets them
+    let run_script = |src: &str| match &links.run_token {
+        Some(token) => format!(
+            "<script src=\"{}\" data-run-token=\"{}\"></script>",
+            src, token
+        ),
+        None => format!("<script src=\"{}\"></script>", src),
+    };
+    let umd_script = |path: &str| match links.vendored.get(path) {
+        Some(code) => inline_script(code),
+        None => cdnjs_script(path),
+    };
 
     // TODO: perform searches like https://api.cdnjs.com/libraries?search=jquery&limit=1 to allow arbitrary cdnjs deps
-    let html = parts
-        .into_iter()
-        .try_fold(String::with_capacity(html.len()), |mut out, part| {
-            match part {
-                HtmlPart::Literal(literal) => out.push_str(literal),
-                HtmlPart::IncludePath(path) => match &path[..] {
-                    &["console"] => out.push_str(&public_script("console.bundle.js")),
-                    &["editors", "js"] | &["editors", "js", "url"] => {
-                        out.push_str(&page_url(".js"))
+    parts.into_iter().try_fold(String::new(), |mut out, part| {
+        match part {
+            HtmlPart::Literal(literal) => out.push_str(literal),
+            HtmlPart::IncludePath(path) => match &path[..] {
+                &["console"] => {
+                    if links.frame {
+                        out.push_str(&run_script(&public_path("console.bundle.js")))
                     }
-                    &["editors", "js", "raw"] | &["editors", "js", "raw", "url"] => {
-                        out.push_str(&page_url(".js.raw"))
-                    }
-                    &["editors", "css"]
-                    | &["editors", "css", "url"]
-                    | &["editors", "css", "raw"]
-                    | &["editors", "css", "url", "raw"] => out.push_str(&page_url(".css")),
-                    &["deps", "react"] => {
-                        out.push_str(&cdnjs_script("react/17.0.2/umd/react.development.min.js"));
-                        out.push_str(&cdnjs_script(
-                            "react-dom/17.0.2/umd/react-dom.development.min.js",
+                }
+                &["tests"] => {
+                    if links.frame && !links.tests.is_empty() {
+                        out.push_str(&run_script("/api/tests.js"));
+                        out.push_str(&format!(
+                            "<script type=\"module\" src=\"{}\"></script>",
+                            links.tests
                         ));
                     }
-                    &["deps", "jquery"] => {
-                        out.push_str(&cdnjs_script("jquery/3.6.0/jquery.min.js"));
+                }
+                &["importmap"] => {
+                    if links.import_maps {
+                        out.push_str(&deps.import_map.to_script())
+                    }
+                }
+                &["importmap", "auto"] => {
+                    if links.import_maps {
+                        out.push_str(&deps.auto_import_map.to_script())
                     }
-                    &["editors", other, ..] => {
-                        anyhow::bail!("Unexpected second segment in inject(urls.{})", other)
+                }
+                &["editors", "js"] | &["editors", "js", "url"] => out.push_str(&links.js),
+                &["editors", "js", "raw"] | &["editors", "js", "raw", "url"] => {
+                    out.push_str(&links.js_raw)
+                }
+                &["editors", "css"]
+                | &["editors", "css", "url"]
+                | &["editors", "css", "raw"]
+                | &["editors", "css", "url", "raw"] => out.push_str(&links.css),
+                &["deps", "auto"] => {
+                    for script in &deps.auto_umd {
+                        out.push_str(&umd_script(script));
                     }
-                    &[other, ..] => anyhow::bail!("Unexpected command: inject!({}, …)", other),
-                    &[] => anyhow::bail!("Unexpected empty inject!()"),
+                }
+                &["deps", name] => match umd_scripts(name) {
+                    Some(scripts) => {
+                        for script in scripts {
+                            out.push_str(&umd_script(script));
+                        }
+                    }
+                    None => anyhow::bail!("Unknown dependency in inject!(deps.{})", name),
                 },
-            }
+                &["editors", other, ..] => {
+                    anyhow::bail!("Unexpected second segment in inject(urls.{})", other)
+                }
+                &[other, ..] => anyhow::bail!("Unexpected command: inject!({}, …)", other),
+                &[] => anyhow::bail!("Unexpected empty inject!()"),
+            },
+        }
 
-            Ok(out)
-        });
+        Ok(out)
+    })
+}
+
+async fn page_html(id: &str, saved: bool) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Html;
+    let db = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+    // Read before the files, so the page is never newer than this revision
+    let (db, revision) = if saved {
+        (db, None)
+    } else {
+        let (db, revision) = db
+            .get_session_revision(id)
+            .await
+            .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+        (db, Some(revision))
+    };
+    let (db, meta, html) = get_page_file(db, id, saved, err_mime, FileKind::Html).await?;
+
+    let parts = match parse_html(&html) {
+        Ok(parts) => parts,
+        Err(err) => return Err(HttpError::invalid_html(err).with_mime(err_mime)),
+    };
+    let (_, deps) = load_page_deps(db, id, &meta, &parts, err_mime).await?;
+    let script_origins = deps.script_origins();
+
+    let kind = if saved { "saved" } else { "session" };
+    let page_url = |suffix: &str| format!("/api/{}/{}/page{}", kind, id, suffix);
+    let links = PageLinks {
+        js: page_url(".js"),
+        js_raw: page_url(".js.raw"),
+        css: page_url(".css"),
+        tests: if saved {
+            String::new()
+        } else {
+            page_url(".test.js")
+        },
+        frame: true,
+        run_token: if saved {
+            None
+        } else {
+            Some(run_token::issue(id))
+        },
+        import_maps: true,
+        vendored: HashMap::new(),
+    };
+
+    // Reloads the page when the session is updated, see [r_get_session_events]. Saves
+    // don't change.
+    let html = render_page(parts, &deps, &links).map(|mut html| {
+        if let Some(revision) = revision {
+            html.push_str(&format!(
+                "<script src=\"/dist/live.bundle.js\" data-revision=\"{}\"></script>",
+                revision
+            ));
+        }
+        html
+    });
 
     match html {
         Ok(html) => Ok(HttpResponse::Ok()
@@ -173,10 +469,12 @@ pub async fn r_get_session_page_html(
             .header("content-type", "text/html; charset=utf-8")
             .header("cache-control", "max-age=0, private, must-revalidate")
             .header("referrer-policy", "strict-origin-when-cross-origin")
+            .header("content-security-policy", csp::frame_csp(&script_origins))
+            .header("permissions-policy", csp::frame_permissions_policy())
+            .header("x-content-type-options", "nosniff")
             // Other maybe useful headers from that response:
             // x-frame-options: ALLOWALL
             // x-xss-protection: 0
-            // x-content-type-options: nosniff
             // x-download-options: noopen
             // x-permitted-cross-domain-policies: none
             // set-cookie: csrftoken={long string}; path=/
@@ -186,3 +484,127 @@ pub async fn r_get_session_page_html(
         Err(err) => Err(HttpError::generate_html_fail(err).with_mime(err_mime)),
     }
 }
+
+#[get("/session/{session_id}/page", wrap = "http::FRAME_HTML")]
+pub async fn r_get_session_page_html(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_html(&info.0, false).await
+}
+
+/// A save's page, so it can be shown without forking it into a session first.
+#[get("/saved/{saved_id}/page", wrap = "http::FRAME_HTML")]
+pub async fn r_get_saved_page_html(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    page_html(&info.0, true).await
+}
+
+/// Comments sent on idle event streams, so proxies don't time them out.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(25);
+
+/// Server-sent events of the session's changes, see [crate::hub::SessionEvent].
+#[get("/session/{session_id}/events", wrap = "http::FRAME_API")]
+pub async fn r_get_session_events(info: web::Path<String>) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let session_id = info.0;
+    let subscription = hub::subscribe(&session_id);
+    // Read after subscribing, so no update is missed in between
+    let (_, revision) = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?
+        .get_session_revision(&session_id)
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+
+    let connected = stream::once(future::ready(SessionEvent::Connected { revision }));
+    let events = connected.chain(subscription).map(|event| {
+        let json = serde_json::to_string(&event).expect("ject: SessionEvent to json");
+        format!("data: {}\n\n", json)
+    });
+    let heartbeats = stream::unfold((), |()| async {
+        delay_for(HEARTBEAT_INTERVAL).await;
+        Some((":\n\n".to_owned(), ()))
+    })
+    .boxed_local();
+    let body = stream::select(events, heartbeats)
+        .map(|chunk| Ok::<_, actix_web::Error>(web::Bytes::from(chunk)));
+
+    Ok(HttpResponse::Ok()
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        // Stops nginx from buffering the stream
+        .header("x-accel-buffering", "no")
+        .streaming(body))
+}
+
+/// Most bytes accepted of a CSP report. Reports are a few hundred bytes.
+const MAX_CSP_REPORT: usize = 1024 * 8;
+
+/// Most bytes of a CSP report written to the log.
+const MAX_CSP_REPORT_LOG: usize = 2048;
+
+/// The CSP report route, with a payload limit of its own instead of the session one.
+pub fn csp_report_service() -> impl HttpServiceFactory {
+    web::resource("/csp-report")
+        .app_data(web::PayloadConfig::new(MAX_CSP_REPORT))
+        .wrap(http::FRAME_API)
+        .route(web::post().to(r_post_csp_report))
+}
+
+/// Receives CSP violation reports from the frame page (see [csp::frame_csp]) and logs them.
+async fn r_post_csp_report(body: web::Bytes, client: ClientInfo) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Logs, client.ip)?;
+
+    let ip = client
+        .ip
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    // Browsers send these as application/csp-report or application/reports+json, so the body
+    // is parsed manually rather than with web::Json
+    match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(report) => {
+            let report = util::truncate(report.to_string(), MAX_CSP_REPORT_LOG);
+            eprintln!("[csp-report] from {}: {}", ip, report);
+        }
+        Err(_) => {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            let body = util::truncate(body, MAX_CSP_REPORT_LOG);
+            eprintln!("[csp-report] unparsable report from {}: {:?}", ip, body);
+        }
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_script_escapes_closing_tags() {
+        assert_eq!(
+            inline_script("a('</SCRIPT>'); b('</div>');"),
+            "<script>a('<\\/SCRIPT>'); b('</div>');</script>"
+        );
+        assert_eq!(inline_script("x</"), "<script>x</</script>");
+    }
+
+    #[actix_rt::test]
+    async fn limits_csp_report_size() {
+        use actix_web::{http::StatusCode, test, App};
+
+        let mut app = test::init_service(App::new().service(csp_report_service())).await;
+        let report = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/csp-report")
+                .header("host", http::Domain::Frame.name())
+                .set_payload(body)
+                .to_request()
+        };
+
+        let body = br#"{"csp-report":{"blocked-uri":"inline"}}"#.to_vec();
+        let res = test::call_service(&mut app, report(body)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let res = test::call_service(&mut app, report(vec![b' '; MAX_CSP_REPORT + 1])).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    api::{
+        error::{ApiError, ApiResult},
+        export::{ManifestFile, MANIFEST_VERSION},
+        session::create_session,
+        util::check_quotas,
+    },
+    archive::{read_archive, ArchiveEntry, ArchiveLimits},
+    db::Db,
+    env,
+    forwarded::ClientInfo,
+    http, patch,
+    rate_limit::{self, Budget},
+    state::{File, FileKind, Session},
+};
+use actix_web::{post, web, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Most entries in an uploaded archive, counting directories and the files that aren't
+/// imported.
+const MAX_ENTRIES: usize = 64;
+
+/// Written to archives by [super::export], and not imported.
+const ARCHIVE_EXTRAS: &[&str] = &["manifest.json", "README.md"];
+
+/// What's needed of the manifest.json written by [super::export].
+#[derive(Debug, Deserialize)]
+struct ImportManifest {
+    version: u32,
+    files: Vec<ManifestFile>,
+}
+
+/// Archives are often of a folder, e.g. `ject-<id>/page.js`. If all the paths start with the
+/// same folder, it's removed.
+fn strip_folder(entries: &mut [ArchiveEntry]) {
+    let folder = match entries.first().and_then(|entry| entry.path.split_once('/')) {
+        Some((folder, _)) => format!("{}/", folder),
+        None => return,
+    };
+    if entries.iter().all(|entry| entry.path.starts_with(&folder)) {
+        for entry in entries {
+            entry.path.drain(..folder.len());
+        }
+    }
+}
+
+/// The session files in an archive, and the paths of the files that were left out. The
+/// kinds come from manifest.json if there is one, and from the file names otherwise.
+fn session_from_archive(mut entries: Vec<ArchiveEntry>) -> ApiResult<(Session, Vec<String>)> {
+    strip_folder(&mut entries);
+
+    let manifest = match entries.iter().find(|entry| entry.path == "manifest.json") {
+        Some(entry) => {
+            let manifest: ImportManifest = serde_json::from_slice(&entry.contents)
+                .map_err(|err| ApiError::InvalidArchive(format!("manifest.json: {}", err)))?;
+            if manifest.version > MANIFEST_VERSION {
+                return Err(ApiError::InvalidArchive(format!(
+                    "manifest.json is version {}, the newest supported is {}",
+                    manifest.version, MANIFEST_VERSION
+                )));
+            }
+            Some(manifest.files)
+        }
+        None => None,
+    };
+    let kind_of = |path: &str| match &manifest {
+        Some(files) => files
+            .iter()
+            .find(|file| file.name == path)
+            .map(|file| file.kind),
+        None => FileKind::from_default_name(path),
+    };
+
+    let mut files: Vec<File> = vec![];
+    let mut ignored = vec![];
+    for entry in entries {
+        let kind = match kind_of(&entry.path) {
+            Some(kind) => kind,
+            None => {
+                if !ARCHIVE_EXTRAS.contains(&entry.path.as_str()) {
+                    ignored.push(entry.path);
+                }
+                continue;
+            }
+        };
+        if files.iter().any(|file| file.kind == kind) {
+            return Err(ApiError::InvalidArchive(format!(
+                "More than one {} file in the archive",
+                kind.to_variant_name()
+            )));
+        }
+        let path = entry.path;
+        let contents = String::from_utf8(entry.contents)
+            .map_err(|_| ApiError::InvalidArchive(format!("{} isn't valid UTF-8", path)))?;
+        files.push(File::new(kind, contents));
+    }
+
+    if let Some(listed) = &manifest {
+        let found = |file: &&ManifestFile| files.iter().any(|f| f.kind == file.kind);
+        if let Some(missing) = listed.iter().find(|file| !found(file)) {
+            return Err(ApiError::InvalidArchive(format!(
+                "{} is listed in manifest.json, but missing from the archive",
+                missing.name
+            )));
+        }
+    }
+    if files.is_empty() {
+        return Err(ApiError::InvalidArchive(
+            "The archive has no files to import, like page.js or page.html".to_owned(),
+        ));
+    }
+
+    Ok((Session { files }, ignored))
+}
+
+/// Create a session from a zip, tar or tar.gz archive sent as the request body, like the
+/// ones from `GET /api/saved/{id}/export.zip`. Responds like [super::session::r_post_session_new]
+/// plus the files, and the paths of the files that weren't imported.
+#[post("/import", wrap = "http::MAIN_API")]
+pub async fn r_post_import(
+    mut payload: web::Payload,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Create, client.ip)?;
+
+    let max = env::max_payload_bytes();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| ApiError::InvalidArchive(err.to_string()))?;
+        if body.len() + chunk.len() > max {
+            return Err(ApiError::PayloadTooLarge { max });
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let limits = ArchiveLimits {
+        entries: MAX_ENTRIES,
+        entry_bytes: env::max_file_bytes() as u64,
+        total_bytes: max as u64,
+    };
+    let entries = web::block(move || read_archive(&body, limits))
+        .await
+        .map_err(|err| match err {
+            actix_web::error::BlockingError::Error(err) => ApiError::from(err),
+            actix_web::error::BlockingError::Canceled => {
+                ApiError::InvalidArchive("Reading the archive was canceled".to_owned())
+            }
+        })?;
+    let (session, ignored) = session_from_archive(entries)?;
+    check_quotas(&session)?;
+
+    let (_, session_id, edit_token) = create_session(Db::open_env().await?, &session).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "edit_token": edit_token,
+        "revision": 1,
+        "revisions": patch::revisions(&session.files),
+        "session": session,
+        "ignored": ignored,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, contents: &str) -> ArchiveEntry {
+        ArchiveEntry {
+            path: path.to_owned(),
+            contents: contents.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn reads_files_by_manifest_or_name() {
+        let (session, ignored) = session_from_archive(vec![
+            entry("ject-a/index.js", "let a;"),
+            entry("ject-a/page.html", "<p></p>"),
+            entry("ject-a/README.md", "# a"),
+            entry("ject-a/other.txt", ""),
+            entry(
+                "ject-a/manifest.json",
+                r#"{"version":1,"files":[{"name":"index.js","kind":"JavaScript"}]}"#,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(session.file_kinds(), [FileKind::JavaScript]);
+        assert_eq!(session.files[0].contents, "let a;");
+        assert_eq!(ignored, ["page.html", "other.txt"]);
+
+        let (session, ignored) =
+            session_from_archive(vec![entry("page.css", "a{}"), entry("page.js", "")]).unwrap();
+        assert_eq!(session.file_kinds(), [FileKind::Css, FileKind::JavaScript]);
+        assert!(ignored.is_empty());
+
+        let missing = vec![
+            entry("page.js", ""),
+            entry(
+                "manifest.json",
+                r#"{"version":1,"files":[{"name":"page.css","kind":"Css"}]}"#,
+            ),
+        ];
+        assert!(session_from_archive(missing).is_err());
+        assert!(session_from_archive(vec![entry("README.md", "")]).is_err());
+    }
+}
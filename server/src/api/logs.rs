@@ -0,0 +1,111 @@
+use crate::{
+    api::{
+        error::{ApiError, ApiResult},
+        util::truncate,
+    },
+    db::{unix_now, Db},
+    env,
+    forwarded::ClientInfo,
+    http,
+    rate_limit::{self, Budget},
+    run_token,
+    state::LogEntry,
+};
+use actix_web::{get, post, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+/// Most entries the frame may send in one request.
+const MAX_BATCH: usize = 100;
+
+/// Most bytes kept of each console argument. The console script already shortens them to
+/// this, so only made up batches lose anything.
+const MAX_ARG: usize = 1024 * 32;
+
+#[derive(Debug, Deserialize)]
+pub struct LogBatch {
+    /// The run token page.html was served with, see [run_token]
+    run_token: String,
+    entries: Vec<LogEntry>,
+}
+
+/// Stores console output of a frame page, sent in batches by its console script when the
+/// frame was opened with `?capture`. Each load of the page is a separate run, with its own
+/// run token; only the newest few runs of a session are kept.
+#[post("/session/{session_id}/logs", wrap = "http::FRAME_API")]
+pub async fn r_post_session_logs(
+    info: web::Path<String>,
+    web::Json(batch): web::Json<LogBatch>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Logs, client.ip)?;
+
+    let session_id = info.0;
+    let LogBatch {
+        run_token,
+        mut entries,
+    } = batch;
+    let run_id = run_token::verify(&run_token, &session_id).ok_or(ApiError::InvalidRunToken)?;
+    if entries.len() > MAX_BATCH {
+        return Err(ApiError::InvalidLogBatch(format!(
+            "{} entries were sent, the limit per request is {}",
+            entries.len(),
+            MAX_BATCH
+        )));
+    }
+    for entry in &mut entries {
+        let args = std::mem::take(&mut entry.args);
+        entry.args = args.into_iter().map(|arg| truncate(arg, MAX_ARG)).collect();
+    }
+
+    let (db, _) = Db::open_env().await?.get_session(&session_id).await?;
+    let (_, stored) = db
+        .put_log_entries(
+            &session_id,
+            &run_id,
+            unix_now(),
+            entries,
+            env::max_log_entries(),
+            env::log_runs(),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "stored": stored })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogsParams {
+    /// How many of the newest runs to return, all of the kept ones by default
+    runs: Option<u32>,
+}
+
+/// The captured console output of the session's newest runs, newest first.
+#[get("/session/{session_id}/logs", wrap = "http::MAIN_API")]
+pub async fn r_get_session_logs(
+    info: web::Path<String>,
+    params: web::Query<LogsParams>,
+) -> ApiResult<HttpResponse> {
+    let session_id = info.0;
+    let kept = env::log_runs().max(1) as u32;
+    let runs = params.runs.unwrap_or(kept).clamp(1, kept);
+
+    let (db, _) = Db::open_env().await?.get_session(&session_id).await?;
+    let (_, runs) = db.get_log_runs(&session_id, runs).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "runs": runs })))
+}
+
+/// The last known output of a save: the newest run of the session it was saved from, if
+/// its console was captured.
+#[get("/saved/{saved_id}/logs", wrap = "http::MAIN_API")]
+pub async fn r_get_saved_logs(info: web::Path<String>) -> ApiResult<HttpResponse> {
+    let saved_id = info.0;
+
+    let (db, saved) = Db::open_env().await?.get_saved_info(&saved_id).await?;
+    if saved.deleted_at.is_some() {
+        return Err(ApiError::SavedGone);
+    }
+    let (_, runs) = db.get_log_runs(&saved_id, 1).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "runs": runs })))
+}
@@ -0,0 +1,72 @@
+use crate::{
+    api::{compile::compile_cached, frame::try_get_file},
+    compile_service::CompileOptions,
+    db::Db,
+    forwarded::ClientInfo,
+    headless::{self, RunLimits, RunOutput, UncaughtError},
+    http,
+    http_error::{ErrorMime, HttpError},
+    rate_limit::{self, Budget},
+    state::FileKind,
+};
+use actix_web::{post, web, HttpResponse};
+use serde_json::json;
+
+/// Runs the session's page.js on the server, without a browser, and responds with its console
+/// output and uncaught errors (see [crate::headless]). Code that uses the DOM or imports
+/// packages fails with an uncaught error, as neither is available.
+#[post("/session/{session_id}/run", wrap = "http::MAIN_API")]
+pub async fn r_post_session_run(
+    info: web::Path<String>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let db_err = |err| HttpError::db_error(err).with_mime(err_mime);
+    if let Err(limited) = rate_limit::check(Budget::Run, client.ip) {
+        return Err(HttpError::rate_limited(limited).with_mime(err_mime));
+    }
+
+    let session_id = info.0;
+    let db = Db::open_env().await.map_err(db_err)?;
+    let (db, meta, code) = try_get_file(db, &session_id, err_mime, FileKind::JavaScript).await?;
+    let (db, revision) = db.get_session_revision(&session_id).await.map_err(db_err)?;
+
+    let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+    let file_name = FileKind::JavaScript.to_default_name();
+    let output = match compile_cached(
+        db,
+        &session_id,
+        file_name,
+        &code,
+        options,
+        client.ip,
+        err_mime,
+    )
+    .await
+    {
+        Ok((_, compiled)) => {
+            let limits = RunLimits::from_env();
+            headless::run(&compiled, limits)
+                .await
+                .map_err(|err| HttpError::headless_run_fail(err).with_mime(err_mime))?
+        }
+        // Reported as an error of the page, as the frame does
+        Err(err) if err.code == "js_compile_fail" => RunOutput {
+            errors: vec![UncaughtError {
+                name: "CompileError".to_owned(),
+                message: err.message.trim_start_matches("Reason:\n").to_owned(),
+                stack: None,
+            }],
+            ..RunOutput::default()
+        },
+        Err(err) => return Err(err),
+    };
+
+    Ok(HttpResponse::Ok().json(json!({
+        "revision": revision,
+        "entries": output.entries,
+        "errors": output.errors,
+        "timed_out": output.timed_out,
+        "truncated": output.truncated,
+    })))
+}
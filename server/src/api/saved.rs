@@ -1,55 +1,488 @@
 use crate::{
-    db::{
-        Db, {self},
+    api::{
+        account::CurrentUser,
+        error::{ApiError, ApiResult},
+    },
+    db::{unix_now, Db},
+    env,
+    forwarded::ClientInfo,
+    hash::sha1_hex,
+    http, ids, patch,
+    rate_limit::{self, Budget},
+    state::{
+        FileKind, SavedCursor, SavedDetails, SavedFilter, SavedInfo, SavedSort, SavedSummary,
+        Session, SessionMeta, User,
     },
-    ids,
-    state::{File, FileKind, Session, SessionMeta},
 };
-use actix_web::{get, post, web, HttpResponse};
-use db::DbError;
-use serde::Deserialize;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize)]
 pub struct Save {
     session: Session,
+    /// The session being saved, so a save of a fork records its parent and the save keeps
+    /// its last console output. Requires the session's edit token in `x-edit-token`.
+    session_id: Option<String>,
+    #[serde(flatten)]
+    details: SavedDetails,
+}
+
+#[derive(Debug, Serialize)]
+struct SavedResponse {
+    #[serde(flatten)]
+    session: Session,
+    #[serde(flatten)]
+    details: SavedDetails,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+}
+
+const DEFAULT_LIST_LIMIT: u32 = 20;
+const MAX_LIST_LIMIT: u32 = 100;
+
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 10_000;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_CHARS: usize = 32;
+
+/// Trim the details and normalize tags to lowercase, rejecting any that are too long.
+fn clean_details(details: SavedDetails) -> ApiResult<SavedDetails> {
+    let invalid = |message: String| Err(ApiError::InvalidDetails(message));
+
+    let title = details.title.trim().to_owned();
+    if title.chars().count() > MAX_TITLE_CHARS {
+        return invalid(format!(
+            "Titles are limited to {} characters",
+            MAX_TITLE_CHARS
+        ));
+    }
+
+    let description = details.description.trim().to_owned();
+    if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return invalid(format!(
+            "Descriptions are limited to {} characters",
+            MAX_DESCRIPTION_CHARS
+        ));
+    }
+
+    let mut tags: Vec<String> = vec![];
+    for tag in details.tags {
+        let tag = tag.trim().to_lowercase();
+        let valid_chars = tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.+#".contains(c));
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_CHARS || !valid_chars {
+            return invalid(format!(
+                "Invalid tag {:?}, tags are up to {} letters, digits or -_.+#",
+                tag, MAX_TAG_CHARS
+            ));
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_TAGS {
+        return invalid(format!("Saves are limited to {} tags", MAX_TAGS));
+    }
+
+    Ok(SavedDetails {
+        title,
+        description,
+        tags,
+    })
+}
+
+/// Changing a save requires being logged in as its owner, or the `edit_token` returned by
+/// [r_post_save] in the `x-edit-token` header.
+fn authorize(req: &HttpRequest, user: &Option<User>, saved: &SavedInfo) -> ApiResult<()> {
+    if saved.deleted_at.is_some() {
+        return Err(ApiError::SavedGone);
+    }
+
+    let edit_token = super::util::edit_token_header(req);
+    let is_owner = match (user, &saved.owner_id) {
+        (Some(user), Some(owner_id)) => &user.user_id == owner_id,
+        _ => false,
+    };
+    let has_token = match (edit_token, &saved.edit_token_hash) {
+        (Some(token), Some(hash)) => &sha1_hex(token) == hash,
+        _ => false,
+    };
+
+    if is_owner || has_token {
+        Ok(())
+    } else if edit_token.is_some() {
+        Err(ApiError::InvalidEditToken)
+    } else if user.is_some() {
+        Err(ApiError::SavedForbidden)
+    } else {
+        Err(ApiError::Unauthenticated)
+    }
 }
 
-#[get("/saved/{save_id}")]
-pub async fn r_get_saved(info: web::Path<String>) -> Result<HttpResponse, DbError> {
+#[get("/saved/{save_id}", wrap = "http::MAIN_API")]
+pub async fn r_get_saved(info: web::Path<String>) -> ApiResult<HttpResponse> {
     let db = Db::open_env().await?;
     let save_id = info.0.as_str();
 
-    let (mut db, meta) = db.get_saved(&save_id).await?;
+    let (db, saved) = db.get_saved_info(save_id).await?;
+    if saved.deleted_at.is_some() {
+        return Err(ApiError::SavedGone);
+    }
+
+    let (db, meta) = db.get_saved(save_id).await?;
+    let (db, files) = super::util::get_files(db, save_id, &meta).await?;
+    let (db, details) = db.get_saved_details(save_id).await?;
+    db.incr_saved_views(save_id).await?;
+
+    Ok(HttpResponse::Ok().json(SavedResponse {
+        session: Session { files },
+        details,
+        created_at: saved.created_at,
+        updated_at: saved.updated_at,
+    }))
+}
 
-    let mut files = vec![];
-    for file_kind in &meta.file_kinds {
-        let file_name = file_kind.to_default_name();
-        let (db2, contents) = db.get_file(&save_id, file_name).await?;
-        db = db2;
-        let file = File::new(*file_kind, contents);
-        files.push(file);
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    sort: SavedSort,
+    cursor: Option<String>,
+    limit: Option<u32>,
+    owner: Option<String>,
+    tag: Option<String>,
+    kind: Option<FileKind>,
+    has_parent: Option<bool>,
+}
+
+/// The `cursor` of a listing, which must be for the listing's sort.
+fn parse_cursor(cursor: Option<&str>, sort: SavedSort) -> ApiResult<Option<SavedCursor>> {
+    let cursor = match cursor {
+        Some(cursor) => cursor
+            .parse::<SavedCursor>()
+            .map_err(|_| ApiError::InvalidQuery(format!("Invalid cursor {:?}", cursor)))?,
+        None => return Ok(None),
+    };
+    // The keys of one sort mean nothing to another, so pages would be skipped or repeated
+    if cursor.sort != sort {
+        return Err(ApiError::InvalidQuery(format!(
+            "The cursor is for sort={}, not sort={}",
+            cursor.sort.name(),
+            sort.name()
+        )));
     }
+    Ok(Some(cursor))
+}
 
-    let session = Session { files };
+/// List saves without their files. Pass the `next_cursor` of a response as `cursor` to get
+/// the next page.
+#[get("/saved", wrap = "http::MAIN_API")]
+pub async fn r_list_saved(params: web::Query<ListParams>) -> ApiResult<HttpResponse> {
+    let params = params.into_inner();
+    let sort = params.sort;
+    let cursor = parse_cursor(params.cursor.as_deref(), sort)?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let filter = SavedFilter {
+        tag: params.tag.map(|tag| tag.trim().to_lowercase()),
+        owner: params.owner,
+        file_kind: params.kind,
+        has_parent: params.has_parent,
+    };
+
+    // One extra row tells us whether there's another page
+    let (_, mut saves) = Db::open_env()
+        .await?
+        .list_saved(filter, sort, cursor, limit + 1)
+        .await?;
+    let next_cursor = if saves.len() > limit as usize {
+        saves.truncate(limit as usize);
+        saves.last().map(|(_, cursor)| cursor.to_string())
+    } else {
+        None
+    };
 
-    Ok(HttpResponse::Ok().json(session))
+    let results: Vec<SavedSummary> = saves.into_iter().map(|(saved, _)| saved).collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "results": results,
+        "next_cursor": next_cursor,
+    })))
 }
 
-#[post("/save")]
+#[post("/save", wrap = "http::MAIN_API")]
 pub async fn r_post_save(
-    web::Json(Save { session }): web::Json<Save>,
-) -> Result<HttpResponse, DbError> {
-    let save_id = ids::make_save_id();
+    req: HttpRequest,
+    web::Json(Save {
+        session,
+        session_id,
+        details,
+    }): web::Json<Save>,
+    client: ClientInfo,
+    user: CurrentUser,
+) -> Result<HttpResponse, ApiError> {
+    rate_limit::check(Budget::Save, client.ip)?;
+    super::util::check_quotas(&session)?;
+    let details = clean_details(details)?;
+
     let mut db = Db::open_env().await?;
+    // Only whoever can edit the session may give its console output to a save
+    if let Some(session_id) = &session_id {
+        let edit_token = super::util::edit_token_header(&req).ok_or(ApiError::MissingEditToken)?;
+        db = super::util::check_edit_token(db, session_id, edit_token).await?;
+    }
 
+    let save_id = ids::make_save_id();
     db = super::util::put_files(db, &save_id, &session).await?;
 
     let meta = SessionMeta {
-        file_kinds: vec![FileKind::JavaScript, FileKind::Css, FileKind::Html],
+        file_kinds: session.file_kinds(),
     };
 
-    db.put_saved(&save_id, meta).await?;
+    let edit_token = ids::make_edit_token();
+    db = db
+        .put_saved(&save_id, meta)
+        .await?
+        .put_saved_edit_token_hash(&save_id, &sha1_hex(&edit_token))
+        .await?
+        .put_saved_details(&save_id, details)
+        .await?;
+    if let Some(session_id) = session_id {
+        db = db
+            .put_saved_forked_from_session(&save_id, &session_id)
+            .await?
+            .copy_last_log_run(&session_id, &save_id)
+            .await?;
+    }
+    if let Some(user) = user.0 {
+        db.put_saved_owner(&save_id, &user.user_id).await?;
+    }
 
-    Ok(HttpResponse::Ok().json(json!({ "save_id": save_id })))
+    Ok(HttpResponse::Ok().json(json!({ "save_id": save_id, "edit_token": edit_token })))
+}
+
+/// Start a new session from a save's files. Responds like [super::session::r_post_session_new]
+/// plus the files, so the editor can load them without fetching the save again.
+#[post("/saved/{save_id}/fork", wrap = "http::MAIN_API")]
+pub async fn r_post_fork(info: web::Path<String>, client: ClientInfo) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Create, client.ip)?;
+
+    let save_id = info.0;
+    let (db, saved) = Db::open_env().await?.get_saved_info(&save_id).await?;
+    if saved.deleted_at.is_some() {
+        return Err(ApiError::SavedGone);
+    }
+
+    let session_id = ids::make_session_id();
+    let edit_token = ids::make_edit_token();
+
+    let (db, meta) = db
+        .fork_saved(&save_id, &session_id, &sha1_hex(&edit_token))
+        .await?;
+    // Deleted since it was checked above
+    let meta = meta.ok_or(ApiError::SavedGone)?;
+    // Only claim a slot, evicting its session, once the fork exists
+    let (db, session_index) = db.incr_session_counter(super::SESSION_LIMIT).await?;
+    let db = db.put_session_index(session_index, &session_id).await?;
+
+    let (_, files) = super::util::get_files(db, &session_id, &meta).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "edit_token": edit_token,
+        "forked_from": save_id,
+        "revision": 1,
+        "revisions": patch::revisions(&files),
+        "session": Session { files },
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SavedPatch {
+    title: Option<String>,
+    description: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+/// Update the title, description and/or tags of a save. See [authorize].
+#[patch("/saved/{save_id}", wrap = "http::MAIN_API")]
+pub async fn r_patch_saved(
+    req: HttpRequest,
+    info: web::Path<String>,
+    web::Json(patch): web::Json<SavedPatch>,
+    client: ClientInfo,
+    user: CurrentUser,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Update, client.ip)?;
+
+    let save_id = info.0;
+    let (db, saved) = Db::open_env().await?.get_saved_info(&save_id).await?;
+    authorize(&req, &user.0, &saved)?;
+
+    let (db, current) = db.get_saved_details(&save_id).await?;
+    let details = clean_details(SavedDetails {
+        title: patch.title.unwrap_or(current.title),
+        description: patch.description.unwrap_or(current.description),
+        tags: patch.tags.unwrap_or(current.tags),
+    })?;
+    db.put_saved_details(&save_id, details.clone()).await?;
+
+    Ok(HttpResponse::Ok().json(details))
+}
+
+/// Soft-delete a save. See [authorize].
+#[delete("/saved/{save_id}", wrap = "http::MAIN_API")]
+pub async fn r_delete_saved(
+    req: HttpRequest,
+    info: web::Path<String>,
+    user: CurrentUser,
+) -> ApiResult<HttpResponse> {
+    let save_id = info.0;
+    let (db, saved) = Db::open_env().await?.get_saved_info(&save_id).await?;
+    authorize(&req, &user.0, &saved)?;
+
+    db.delete_saved(&save_id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Roughly convert the markdown description to a short line of plain text for a preview.
+fn summarize(description: &str, max_chars: usize) -> String {
+    let text = description
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c| "#*`>_~".contains(c)))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.chars().count() > max_chars {
+        let mut short: String = text.chars().take(max_chars - 1).collect();
+        short.push('…');
+        short
+    } else {
+        text
+    }
+}
+
+/// The `<title>` text and OpenGraph/Twitter `<meta>` tags of a save's page, so links to it
+/// unfurl in chat. None if the save doesn't exist or was deleted.
+pub async fn unfurl_head(save_id: &str) -> Option<(String, String)> {
+    let (db, saved) = Db::open_env()
+        .await
+        .ok()?
+        .get_saved_info(save_id)
+        .await
+        .ok()?;
+    if saved.deleted_at.is_some() {
+        return None;
+    }
+    let (_, details) = db.get_saved_details(save_id).await.ok()?;
+
+    let title = if details.title.is_empty() {
+        "Untitled snippet".to_owned()
+    } else {
+        details.title
+    };
+    let mut description = summarize(&details.description, 200);
+    if description.is_empty() {
+        description = "A snippet on ject.dev".to_owned();
+    }
+    if !details.tags.is_empty() {
+        description = format!("{} ({})", description, details.tags.join(", "));
+    }
+    let scheme = if env::is_production() {
+        "https"
+    } else {
+        "http"
+    };
+    let url = format!("{}://{}/saved/{}", scheme, env::domain_main(), save_id);
+
+    let meta = |attr: &str, name: &str, content: &str| {
+        format!(
+            "<meta {}=\"{}\" content=\"{}\" />",
+            attr,
+            name,
+            html_escape::encode_double_quoted_attribute(content)
+        )
+    };
+    let tags = [
+        meta("property", "og:type", "website"),
+        meta("property", "og:site_name", "ject.dev"),
+        meta("property", "og:title", &title),
+        meta("property", "og:description", &description),
+        meta("property", "og:url", &url),
+        meta("name", "twitter:card", "summary"),
+        meta("name", "twitter:title", &title),
+        meta("name", "twitter:description", &description),
+        meta("name", "description", &description),
+    ]
+    .join("\n        ");
+
+    Some((format!("{} · ject.dev", title), tags))
+}
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Hourly, remove the files of saves deleted more than $JECT_PURGE_GRACE_SECS ago.
+pub async fn purge_deleted_job() {
+    let mut interval = actix_rt::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let deleted_before = unix_now() - env::purge_grace_secs() as i64;
+        let purged = async {
+            Db::open_env()
+                .await?
+                .purge_deleted_saves(deleted_before)
+                .await
+        };
+        match purged.await {
+            Ok((_, 0)) => {}
+            Ok((_, files)) => println!("Purged {} file(s) of deleted saves", files),
+            Err(err) => eprintln!("[purge_deleted_job]: {:?}", anyhow::Error::from(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cursors_for_the_sort() {
+        let cursor = parse_cursor(Some("views.3.42"), SavedSort::Views).unwrap();
+        assert_eq!(
+            cursor,
+            Some(SavedCursor {
+                sort: SavedSort::Views,
+                key: 3,
+                rowid: 42
+            })
+        );
+        assert_eq!(cursor.unwrap().to_string(), "views.3.42");
+        assert_eq!(parse_cursor(None, SavedSort::Newest).unwrap(), None);
+
+        for malformed in &[
+            "",
+            "views",
+            "views.3",
+            "views.x.42",
+            "views.3.42.1",
+            "top.3.42",
+        ] {
+            let parsed = parse_cursor(Some(malformed), SavedSort::Views);
+            assert!(
+                matches!(parsed, Err(ApiError::InvalidQuery(_))),
+                "{}",
+                malformed
+            );
+        }
+        assert!(matches!(
+            parse_cursor(Some("newest.1600000000.42"), SavedSort::Views),
+            Err(ApiError::InvalidQuery(message)) if message.contains("sort=newest")
+        ));
+    }
 }
@@ -0,0 +1,281 @@
+use crate::{
+    api::{
+        export::Export,
+        frame::{load_page_deps, render_page, PageLinks},
+    },
+    cdn::umd_scripts,
+    db::Db,
+    http_error::{ErrorMime, HttpError},
+    import_map::DepsManifest,
+    parser::parse_html,
+    state::FileKind,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+const VITE_VERSION: &str = "^5.4.0";
+const VITEST_VERSION: &str = "^2.1.0";
+const JSDOM_VERSION: &str = "^25.0.0";
+
+/// Where a session file goes in the npm project, relative to its folder. page.html becomes
+/// index.html with its directives resolved, and deps.json is merged into package.json.
+pub fn npm_path(kind: FileKind) -> Option<&'static str> {
+    match kind {
+        FileKind::JavaScript => Some("src/page.js"),
+        FileKind::Css => Some("src/page.css"),
+        FileKind::Tests => Some("src/page.test.js"),
+        FileKind::Text => Some("page.txt"),
+        FileKind::Html | FileKind::Deps => None,
+    }
+}
+
+/// A package.json name from a save's title, e.g. `my-counter` for "My Counter!".
+fn package_name(title: Option<&str>, id: &str) -> String {
+    let mut name = String::new();
+    for c in title.unwrap_or_default().chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        format!("ject-{}", id.to_ascii_lowercase())
+    } else {
+        name.chars()
+            .take(64)
+            .collect::<String>()
+            .trim_end_matches('-')
+            .to_owned()
+    }
+}
+
+/// The version of a package to install: from deps.json, or the one `inject!(deps.…)` loads
+/// from cdnjs, or the latest.
+fn package_version(package: &str, declared: Option<&DepsManifest>) -> String {
+    let declared = declared.and_then(|deps| deps.versions().find(|(name, _)| *name == package));
+    if let Some((_, version)) = declared {
+        return version.to_owned();
+    }
+    let prefix = format!("{}/", package);
+    let umd = umd_scripts(package).unwrap_or_default();
+    let version = umd
+        .iter()
+        .filter_map(|path| path.strip_prefix(&prefix))
+        .find_map(|rest| rest.split('/').next());
+    match version {
+        Some(version) => format!("^{}", version),
+        None => "latest".to_owned(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PackageJson {
+    name: String,
+    private: bool,
+    version: &'static str,
+    #[serde(rename = "type")]
+    module_type: &'static str,
+    scripts: BTreeMap<&'static str, &'static str>,
+    dependencies: BTreeMap<String, String>,
+    dev_dependencies: BTreeMap<&'static str, &'static str>,
+}
+
+fn package_json(
+    export: &Export,
+    detected: &[String],
+    declared: Option<&DepsManifest>,
+    tests: bool,
+) -> String {
+    let title = export
+        .details
+        .as_ref()
+        .map(|details| details.title.as_str());
+    let urls: Vec<&str> = declared
+        .map(|deps| deps.urls().map(|(name, _)| name).collect())
+        .unwrap_or_default();
+
+    let mut dependencies = BTreeMap::new();
+    let declared_names = declared.into_iter().flat_map(|deps| deps.versions());
+    let names = detected.iter().map(String::as_str);
+    for package in names.chain(declared_names.map(|(name, _)| name)) {
+        if !urls.contains(&package) {
+            let version = package_version(package, declared);
+            dependencies.insert(package.to_owned(), version);
+        }
+    }
+
+    let mut scripts = BTreeMap::new();
+    scripts.insert("dev", "vite");
+    scripts.insert("build", "vite build");
+    scripts.insert("preview", "vite preview");
+    let mut dev_dependencies = BTreeMap::new();
+    dev_dependencies.insert("vite", VITE_VERSION);
+    if tests {
+        scripts.insert("test", "vitest run");
+        dev_dependencies.insert("vitest", VITEST_VERSION);
+        dev_dependencies.insert("jsdom", JSDOM_VERSION);
+    }
+
+    let package = PackageJson {
+        name: package_name(title, &export.id),
+        private: true,
+        version: "0.0.0",
+        module_type: "module",
+        scripts,
+        dependencies,
+        dev_dependencies,
+    };
+    let mut json = serde_json::to_string_pretty(&package).expect("ject: package.json to json");
+    json.push('\n');
+    json
+}
+
+/// ject compiles page.js with Babel's React and TypeScript presets, so Vite is told to
+/// allow JSX and types in `.js` files too. Packages that deps.json gives by URL are aliased
+/// to it, as there's nothing to install.
+fn vite_config(declared: Option<&DepsManifest>, tests: bool) -> String {
+    let mut config = String::from(concat!(
+        "import { defineConfig } from 'vite';\n",
+        "\n",
+        "export default defineConfig({\n",
+        "  // Like on ject, page.js may use JSX and TypeScript\n",
+        "  esbuild: { loader: 'tsx', include: /src\\/.*\\.js$/, exclude: [] },\n",
+        "  optimizeDeps: { esbuildOptions: { loader: { '.js': 'tsx' } } },\n",
+    ));
+    let aliases: BTreeMap<&str, &str> = declared
+        .map(|deps| deps.urls().collect())
+        .unwrap_or_default();
+    if !aliases.is_empty() {
+        let aliases = serde_json::to_string(&aliases).expect("ject: aliases to json");
+        config.push_str(&format!("  resolve: {{ alias: {} }},\n", aliases));
+    }
+    if tests {
+        config.push_str("  test: { globals: true, environment: 'jsdom' },\n");
+    }
+    config.push_str("});\n");
+    config
+}
+
+fn default_index_html(export: &Export) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n  <head>\n");
+    html.push_str("    <meta charset=\"utf-8\" />\n");
+    if export.file(FileKind::Css).is_some() {
+        html.push_str("    <link rel=\"stylesheet\" href=\"/src/page.css\" />\n");
+    }
+    html.push_str("  </head>\n  <body>\n");
+    if export.file(FileKind::JavaScript).is_some() {
+        html.push_str("    <script type=\"module\" src=\"/src/page.js\"></script>\n");
+    }
+    html.push_str("  </body>\n</html>\n");
+    html
+}
+
+fn npm_readme(export: &Export, tests: bool) -> String {
+    let title = export
+        .details
+        .as_ref()
+        .map(|details| details.title.as_str());
+    let mut readme = format!(
+        "# {}\n\n",
+        title.filter(|t| !t.is_empty()).unwrap_or("ject")
+    );
+    readme.push_str(&format!("Exported from ject ({}).\n\n", export.id));
+    readme.push_str("```sh\nnpm install\nnpm run dev\n```\n\n");
+    readme.push_str(concat!(
+        "`npm run build` bundles the page into `dist/`. The code is in `src/`, and\n",
+        "`index.html` is the page with its `inject!(…)` directives replaced.\n",
+    ));
+    if tests {
+        readme.push_str("\n`npm test` runs `src/page.test.js` with Vitest.\n");
+    }
+    readme
+}
+
+/// The generated files of an npm project for a session or save, by path in its folder:
+/// package.json with the packages page.js imports, index.html, a Vite config and a README.
+/// The session's own files go in as they are, see [npm_path].
+pub async fn npm_project(
+    db: Db,
+    export: &Export,
+    detected: &[String],
+    declared: Option<&DepsManifest>,
+    err_mime: ErrorMime,
+) -> Result<(Db, Vec<(String, String)>), HttpError> {
+    let (db, index_html) = match export.file(FileKind::Html) {
+        Some(html) => {
+            let parts = match parse_html(html) {
+                Ok(parts) => parts,
+                Err(err) => return Err(HttpError::invalid_html(err).with_mime(err_mime)),
+            };
+            let (db, deps) = load_page_deps(db, &export.id, &export.meta, &parts, err_mime).await?;
+            let links = PageLinks {
+                js: "/src/page.js".to_owned(),
+                js_raw: "/src/page.js".to_owned(),
+                css: "/src/page.css".to_owned(),
+                tests: String::new(),
+                frame: false,
+                run_token: None,
+                import_maps: false,
+                vendored: HashMap::new(),
+            };
+            let html = render_page(parts, &deps, &links)
+                .map_err(|err| HttpError::generate_html_fail(err).with_mime(err_mime))?;
+            (db, html)
+        }
+        None => (db, default_index_html(export)),
+    };
+
+    let tests = export.file(FileKind::Tests).is_some();
+    let files = vec![
+        (
+            "package.json".to_owned(),
+            package_json(export, detected, declared, tests),
+        ),
+        ("index.html".to_owned(), index_html),
+        ("vite.config.js".to_owned(), vite_config(declared, tests)),
+        ("README.md".to_owned(), npm_readme(export, tests)),
+    ];
+    Ok((db, files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{File, SessionMeta};
+
+    #[test]
+    fn builds_package_json() {
+        assert_eq!(package_name(Some("My Counter!"), "a"), "my-counter");
+        assert_eq!(package_name(Some("  "), "AbC"), "ject-abc");
+
+        let export = Export {
+            id: "AbC".to_owned(),
+            meta: SessionMeta {
+                file_kinds: vec![FileKind::JavaScript],
+            },
+            files: vec![File::new(FileKind::JavaScript, String::new())],
+            details: None,
+        };
+        let declared =
+            DepsManifest::parse(r#"{ "lodash": "4.17.21", "preact": "https://esm.sh/preact@10" }"#)
+                .unwrap();
+        let detected = ["react".to_owned(), "uuid".to_owned(), "preact".to_owned()];
+        let json = package_json(&export, &detected, Some(&declared), false);
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(json["name"], "ject-abc");
+        assert_eq!(
+            json["dependencies"],
+            serde_json::json!({ "lodash": "4.17.21", "react": "^17.0.2", "uuid": "latest" })
+        );
+        assert_eq!(json["devDependencies"]["vite"], VITE_VERSION);
+        assert!(json["scripts"].get("test").is_none());
+
+        let config = vite_config(Some(&declared), true);
+        assert!(config.contains(r#"alias: {"preact":"https://esm.sh/preact@10"}"#));
+        assert!(config.contains("environment: 'jsdom'"));
+    }
+}
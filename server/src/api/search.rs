@@ -0,0 +1,131 @@
+use crate::{
+    api::error::{ApiError, ApiResult},
+    db::Db,
+    http,
+    state::{FileKind, SavedFilter, SavedSummary, SearchHit},
+};
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+    tag: Option<String>,
+    owner: Option<String>,
+    kind: Option<FileKind>,
+    has_parent: Option<bool>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResult {
+    #[serde(flatten)]
+    saved: SavedSummary,
+    /// HTML-escaped title with the matched terms in `<mark>`
+    title_html: String,
+    /// HTML-escaped excerpt of the files with the matched terms in `<mark>`
+    snippet_html: String,
+}
+
+impl From<SearchHit> for SearchResult {
+    fn from(hit: SearchHit) -> Self {
+        Self {
+            saved: hit.saved,
+            title_html: highlight_html(&hit.title_highlight),
+            snippet_html: highlight_html(&hit.snippet),
+        }
+    }
+}
+
+/// Escape the text from sqlite's highlight/snippet functions, then turn the `\u{1}`/`\u{2}`
+/// delimiters into `<mark>` tags.
+fn highlight_html(text: &str) -> String {
+    html_escape::encode_text(text)
+        .replace('\u{1}', "<mark>")
+        .replace('\u{2}', "</mark>")
+}
+
+/// Convert user input to an FTS5 query that matches saves containing every word, with the
+/// last word also matching as a prefix. Each word is quoted, so operators and punctuation in
+/// the input are searched for literally rather than being a syntax error.
+fn fts_query(input: &str) -> Option<String> {
+    let words: Vec<String> = input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+
+    match words.split_last() {
+        Some((last, rest)) => {
+            let mut query = rest.join(" ");
+            if !query.is_empty() {
+                query.push(' ');
+            }
+            query.push_str(last);
+            query.push('*');
+            Some(query)
+        }
+        None => None,
+    }
+}
+
+#[get("/search", wrap = "http::MAIN_API")]
+pub async fn r_get_search(params: web::Query<SearchParams>) -> ApiResult<HttpResponse> {
+    let params = params.into_inner();
+    let query = fts_query(&params.q)
+        .ok_or_else(|| ApiError::InvalidQuery("The search query q is empty".to_owned()))?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+    let filter = SavedFilter {
+        tag: params.tag.map(|tag| tag.trim().to_lowercase()),
+        owner: params.owner,
+        file_kind: params.kind,
+        has_parent: params.has_parent,
+    };
+
+    // One extra row tells us whether there's another page
+    let (_, mut hits) = Db::open_env()
+        .await?
+        .search_saved(&query, filter, limit + 1, offset)
+        .await?;
+    let next_offset = if hits.len() > limit as usize {
+        hits.truncate(limit as usize);
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    let results: Vec<SearchResult> = hits.into_iter().map(SearchResult::from).collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "results": results,
+        "next_offset": next_offset,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_words_and_prefixes_the_last() {
+        assert_eq!(fts_query("  "), None);
+        assert_eq!(fts_query("react"), Some("\"react\"*".to_owned()));
+        assert_eq!(
+            fts_query("use-state OR \"x"),
+            Some("\"use-state\" \"OR\" \"\"\"x\"*".to_owned())
+        );
+    }
+
+    #[test]
+    fn escapes_highlights() {
+        assert_eq!(
+            highlight_html("<b>\u{1}hook\u{2}</b>"),
+            "&lt;b&gt;<mark>hook</mark>&lt;/b&gt;"
+        );
+    }
+}
@@ -1,55 +1,205 @@
 use crate::{
+    api::{
+        compile::{compile_cached, detect_deps},
+        error::{ApiError, ApiResult},
+    },
+    collab,
+    compile_service::CompileOptions,
     db::{
-        Db, {self},
+        Db, FileWrites, SessionWrite, {self},
     },
+    forwarded::ClientInfo,
+    hash::sha1_hex,
+    http,
+    http_error::{ErrorMime, HttpError},
+    hub::{self, SessionEvent},
     ids,
-    state::{FileKind, Session, SessionMeta},
+    imports::DetectedDeps,
+    patch::{self, FilePatch},
+    rate_limit::{self, Budget},
+    state::{File, FileKind, Session, SessionMeta, SessionState},
 };
-use actix_web::{post, put, web, HttpResponse};
-use db::DbError;
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse};
 use serde::Deserialize;
 use serde_json::json;
+use std::net::IpAddr;
 
 #[derive(Debug, Deserialize)]
 pub struct SessionNew {
     pub session: Session,
 }
 
-#[post("/session/new")]
-pub async fn r_post_session_new(
-    web::Json(SessionNew { session }): web::Json<SessionNew>,
-) -> Result<HttpResponse, DbError> {
-    let db = Db::open_env().await?;
+/// Store a new session with these files, which must be within the quotas. Returns its id
+/// and edit token.
+pub async fn create_session(db: Db, session: &Session) -> ApiResult<(Db, String, String)> {
     let session_id = ids::make_session_id();
+    let edit_token = ids::make_edit_token();
 
     let (db, session_index) = db.incr_session_counter(super::SESSION_LIMIT).await?;
 
     let db = db.put_session_index(session_index, &session_id).await?;
-    let db = super::util::put_files(db, &session_id, &session).await?;
+    let db = super::util::put_files(db, &session_id, session).await?;
 
     let meta = SessionMeta {
-        file_kinds: vec![FileKind::JavaScript, FileKind::Css, FileKind::Html],
+        file_kinds: session.file_kinds(),
+    };
+    let db = db
+        .put_session(&session_id, meta)
+        .await?
+        .put_edit_token_hash(&session_id, &sha1_hex(&edit_token))
+        .await?;
+
+    Ok((db, session_id, edit_token))
+}
+
+#[post("/session/new", wrap = "http::MAIN_API")]
+pub async fn r_post_session_new(
+    web::Json(SessionNew { session }): web::Json<SessionNew>,
+    client: ClientInfo,
+) -> Result<HttpResponse, ApiError> {
+    rate_limit::check(Budget::Create, client.ip)?;
+    super::util::check_quotas(&session)?;
+
+    let (_, session_id, edit_token) = create_session(Db::open_env().await?, &session).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "edit_token": edit_token,
+        "revision": 1,
+        "revisions": patch::revisions(&session.files),
+    })))
+}
+
+/// The stored files and revision of a session.
+async fn session_state(db: Db, session_id: &str) -> ApiResult<(Db, SessionState)> {
+    let (db, meta) = db.get_session(session_id).await?;
+    let (db, files) = super::util::get_files(db, session_id, &meta).await?;
+    let (db, revision) = db.get_session_revision(session_id).await?;
+    let (db, forked_from) = db.get_session_forked_from(session_id).await?;
+
+    let state = SessionState {
+        session_id: session_id.to_owned(),
+        revision,
+        revisions: patch::revisions(&files),
+        session: Session { files },
+        forked_from,
+    };
+    Ok((db, state))
+}
+
+/// The session revision from an `If-Match` header, which may be quoted like an ETag. None if
+/// there's no header or it's `*`.
+fn if_match_header(req: &HttpRequest) -> ApiResult<Option<i64>> {
+    let value = match req.headers().get("if-match") {
+        Some(value) => value,
+        None => return Ok(None),
     };
-    db.put_session(&session_id, meta).await?;
+    let invalid = || ApiError::InvalidIfMatch(String::from_utf8_lossy(value.as_bytes()).into());
 
-    Ok(HttpResponse::Ok().json(json!({ "session_id": session_id })))
+    let value = value.to_str().map_err(|_| invalid())?.trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    let value = value.strip_prefix("W/").unwrap_or(value).trim_matches('"');
+    value.parse().map(Some).map_err(|_| invalid())
+}
+
+fn etag(revision: i64) -> String {
+    format!("\"{}\"", revision)
+}
+
+#[get("/session/{session_id}", wrap = "http::MAIN_API")]
+pub async fn r_get_session(info: web::Path<String>) -> ApiResult<HttpResponse> {
+    let session_id = info.0;
+    let (_, state) = session_state(Db::open_env().await?, &session_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .header("etag", etag(state.revision))
+        .json(state))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SessionUpdate {
     session_id: String,
-    session: Session,
+    #[serde(flatten)]
+    change: SessionChange,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SessionChange {
+    /// Replace the files
+    Full { session: Session },
+    /// Change some of the existing files, see [crate::patch]
+    Patch { patches: Vec<FilePatch> },
+}
+
+/// Apply the patches to the session's current files. Returns the (file name, current
+/// contents, patched contents) of each, and the patched files.
+async fn apply_patches(
+    mut db: Db,
+    session_id: &str,
+    patches: &[FilePatch],
+) -> ApiResult<(Db, Vec<(String, String, String)>, Vec<File>)> {
+    let mut replacements = vec![];
+    let mut files = vec![];
+    for FilePatch { kind, base, change } in patches {
+        let file_name = kind.to_default_name();
+        let current = match db.get_file(session_id, file_name).await {
+            Ok((db2, current)) => {
+                db = db2;
+                current
+            }
+            Err(db::DbError::GetFile { .. }) => {
+                return Err(ApiError::PatchConflict { kind: *kind });
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if &patch::revision(&current) != base {
+            return Err(ApiError::PatchConflict { kind: *kind });
+        }
+
+        let contents = change
+            .apply(&current)
+            .map_err(|source| ApiError::InvalidPatch {
+                kind: *kind,
+                source,
+            })?;
+        replacements.push((file_name.to_owned(), current, contents.clone()));
+        files.push(File::new(*kind, contents));
+    }
+
+    Ok((db, replacements, files))
 }
 
-#[put("/session")]
-pub async fn r_put_session(info: web::Json<SessionUpdate>) -> db::DbResult<HttpResponse> {
+/// Requires the `edit_token` returned by [r_post_session_new] in the `x-edit-token` header.
+///
+/// Either replaces the files with `session`, or applies `patches` to the stored files,
+/// responding 409 if a patch's base revision is out of date. Responds with the new revisions
+/// of the changed files.
+///
+/// Responds 409 while the session is being edited collaboratively, see
+/// [super::collab::r_get_session_collab].
+///
+/// With an `If-Match: <revision>` header, responds 409 with the current state of the session
+/// instead if another update happened since that revision. Responds with the new revision.
+#[put("/session", wrap = "http::MAIN_API")]
+pub async fn r_put_session(
+    req: HttpRequest,
+    info: web::Json<SessionUpdate>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Update, client.ip)?;
+
+    let SessionUpdate { session_id, change } = info.0;
+    if let SessionChange::Full { session } = &change {
+        super::util::check_quotas(session)?;
+    }
+    let edit_token = super::util::edit_token_header(&req).ok_or(ApiError::MissingEditToken)?;
+
     let db = Db::open_env().await?;
-    let SessionUpdate {
-        session_id,
-        session,
-    } = info.0;
 
-    let (mut db, session_meta) = match db.get_session(&session_id).await {
+    let (db, session_meta) = match db.get_session(&session_id).await {
         Ok(r) => r,
         Err(err) => {
             eprintln!(
@@ -63,22 +213,179 @@ pub async fn r_put_session(info: web::Json<SessionUpdate>) -> db::DbResult<HttpR
         }
     };
 
-    let mut kinds = vec![];
-    for file in &session.files {
-        let file_name = file.kind.to_default_name();
-        db = db.put_file(&session_id, file_name, &file.contents).await?;
-        if !kinds.contains(&file.kind) {
-            kinds.push(file.kind);
+    let db = super::util::check_edit_token(db, &session_id, edit_token).await?;
+    // The collab room would overwrite the change with its copy of the files
+    if collab::has_editor(&session_id) {
+        return Err(ApiError::CollabActive);
+    }
+
+    // Check patches before taking a revision, so an invalid patch doesn't use one up
+    let (db, replacements, session) = match change {
+        SessionChange::Full { session } => (db, None, session),
+        SessionChange::Patch { patches } => {
+            let (db, replacements, files) = apply_patches(db, &session_id, &patches).await?;
+            let session = Session { files };
+            super::util::check_quotas(&session)?;
+            (db, Some(replacements), session)
+        }
+    };
+
+    let if_match = if_match_header(&req)?;
+    let (writes, file_kinds) = match replacements {
+        None => {
+            let files = session.files.iter();
+            let files = files
+                .map(|file| {
+                    (
+                        file.kind.to_default_name().to_owned(),
+                        file.contents.clone(),
+                    )
+                })
+                .collect();
+            let kinds = session.file_kinds();
+            let changed_kinds =
+                Some(kinds.clone()).filter(|kinds| *kinds != session_meta.file_kinds);
+            let writes = FileWrites::Full {
+                files,
+                file_kinds: changed_kinds,
+            };
+            (writes, kinds)
         }
+        Some(replacements) => (FileWrites::Replace(replacements), session_meta.file_kinds),
+    };
+
+    let (db, written) = db
+        .write_session_files(&session_id, if_match, writes)
+        .await?;
+    let revision = match written {
+        SessionWrite::Written(revision) => revision,
+        SessionWrite::RevisionConflict => {
+            let (_, current) = session_state(db, &session_id).await?;
+            return Err(ApiError::RevisionConflict {
+                expected: if_match.unwrap_or_default(),
+                current: Box::new(current),
+            });
+        }
+        // Another request changed the file after it was read above
+        SessionWrite::FileConflict(index) => {
+            return Err(ApiError::PatchConflict {
+                kind: session.files[index].kind,
+            });
+        }
+    };
+
+    // Anyone watching over the collab socket sees the change too
+    collab::overwrite(&session_id, &session.files);
+    publish_files_changed(&session_id, file_kinds, revision, client.ip);
+
+    Ok(HttpResponse::Ok()
+        .header("etag", etag(revision))
+        .json(json!({
+            "revision": revision,
+            "revisions": patch::revisions(&session.files),
+        })))
+}
+
+/// Tell the session's open frames about a new revision. They reload once page.js is
+/// compiled, which only happens here if any are open.
+pub fn publish_files_changed(
+    session_id: &str,
+    file_kinds: Vec<FileKind>,
+    revision: i64,
+    client_ip: Option<IpAddr>,
+) {
+    if !hub::is_subscribed(session_id) {
+        return;
     }
 
-    if session_meta.file_kinds != kinds {
-        let new_meta = SessionMeta {
-            file_kinds: kinds,
-            ..session_meta
-        };
-        db.put_session(&session_id, new_meta).await?;
+    let compiling = file_kinds.contains(&FileKind::JavaScript);
+    hub::publish(
+        session_id,
+        SessionEvent::FilesChanged {
+            revision,
+            compiling,
+        },
+    );
+    if compiling {
+        actix_rt::spawn(precompile(
+            session_id.to_owned(),
+            file_kinds,
+            revision,
+            client_ip,
+        ));
     }
+}
+
+/// Compile page.js for the frames that will reload for this revision, then tell them it's
+/// ready. The result is cached, so their requests for it are quick.
+async fn precompile(
+    session_id: String,
+    file_kinds: Vec<FileKind>,
+    revision: i64,
+    client_ip: Option<IpAddr>,
+) {
+    let compiled = async {
+        let db_err = |err| HttpError::db_error(err).with_mime(ErrorMime::Json);
+        let db = Db::open_env().await.map_err(db_err)?;
+        let file_name = FileKind::JavaScript.to_default_name();
+        let (db, code) = db.get_file(&session_id, file_name).await.map_err(db_err)?;
+        let options = CompileOptions::for_file_kinds(&file_kinds);
+        compile_cached(
+            db,
+            &session_id,
+            file_name,
+            &code,
+            options,
+            client_ip,
+            ErrorMime::Json,
+        )
+        .await
+    };
+
+    let error = compiled.await.err().map(|err| err.message.into_owned());
+    hub::publish(
+        &session_id,
+        SessionEvent::CompileFinished { revision, error },
+    );
+}
+
+/// Detect the imports of the session's page.js, using the compile cache when possible.
+async fn session_deps(
+    db: Db,
+    session_id: &str,
+    meta: &SessionMeta,
+) -> db::DbResult<(Db, DetectedDeps)> {
+    if !meta.file_kinds.contains(&FileKind::JavaScript) {
+        return Ok((db, DetectedDeps::default()));
+    }
+
+    let (db, code) = db
+        .get_file(session_id, FileKind::JavaScript.to_default_name())
+        .await?;
+    let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+    detect_deps(db, session_id, &code, options).await
+}
+
+#[get("/session/{session_id}/deps", wrap = "http::MAIN_API")]
+pub async fn r_get_session_deps(info: web::Path<String>) -> db::DbResult<HttpResponse> {
+    let session_id = info.0;
+    let (db, meta) = Db::open_env().await?.get_session(&session_id).await?;
+    let (_, deps) = session_deps(db, &session_id, &meta).await?;
+
+    Ok(HttpResponse::Ok().json(deps))
+}
+
+#[get("/session/{session_id}/meta", wrap = "http::MAIN_API")]
+pub async fn r_get_session_meta(info: web::Path<String>) -> db::DbResult<HttpResponse> {
+    let session_id = info.0;
+    let (db, meta) = Db::open_env().await?.get_session(&session_id).await?;
+    let (db, deps) = session_deps(db, &session_id, &meta).await?;
+    let (_, forked_from) = db.get_session_forked_from(&session_id).await?;
 
-    Ok(HttpResponse::Ok().json(json!({})))
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "file_kinds": meta.file_kinds,
+        "deps": deps,
+        "forked_from": forked_from,
+    })))
 }
@@ -0,0 +1,176 @@
+use crate::{
+    api::{
+        account::CurrentUser,
+        error::{ApiError, ApiResult},
+        session::create_session,
+        util::{check_quotas, get_files},
+    },
+    db::Db,
+    forwarded::ClientInfo,
+    http, patch,
+    rate_limit::{self, Budget},
+    state::{Session, SessionMeta, TemplateSummary},
+    templates::{self, BUILTIN},
+};
+use actix_web::{delete, dev::RequestHead, get, post, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_DESCRIPTION_CHARS: usize = 1000;
+
+/// A built-in template, or one promoted from a save, with its files.
+async fn template_session(db: Db, name: &str) -> ApiResult<(Db, TemplateSummary, Session)> {
+    if let Some(template) = templates::builtin(name) {
+        return Ok((db, template.summary(), template.session()));
+    }
+
+    let (db, template) = db.get_template(name).await?;
+    let template = template.ok_or(ApiError::TemplateNotFound)?;
+    let meta = SessionMeta {
+        file_kinds: template.file_kinds.clone(),
+    };
+    let (db, files) = get_files(db, &templates::files_id(name), &meta).await?;
+    Ok((db, template, Session { files }))
+}
+
+/// The built-in templates, then the ones promoted from saves.
+#[get("/templates", wrap = "http::MAIN_API")]
+pub async fn r_get_templates() -> ApiResult<HttpResponse> {
+    let (_, promoted) = Db::open_env().await?.list_templates().await?;
+    let builtin = BUILTIN.iter().map(|template| template.summary());
+
+    let templates: Vec<TemplateSummary> = builtin.chain(promoted).collect();
+    Ok(HttpResponse::Ok().json(json!({ "templates": templates })))
+}
+
+#[get("/templates/{name}", wrap = "http::MAIN_API")]
+pub async fn r_get_template(info: web::Path<String>) -> ApiResult<HttpResponse> {
+    let (_, template, session) = template_session(Db::open_env().await?, &info.0).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "template": template, "session": session })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemplatePromote {
+    name: String,
+    /// Defaults to the save's title
+    title: Option<String>,
+    /// Defaults to the save's description
+    description: Option<String>,
+}
+
+fn trim_limited(value: Option<String>, what: &str, max: usize) -> ApiResult<Option<String>> {
+    let value = value.map(|value| value.trim().to_owned());
+    if value
+        .as_ref()
+        .is_some_and(|value| value.chars().count() > max)
+    {
+        return Err(ApiError::InvalidTemplate(format!(
+            "Template {} are limited to {} characters",
+            what, max
+        )));
+    }
+    Ok(value)
+}
+
+/// Make a template of a save, or replace the one of the same name, for admins only. The
+/// save's files are copied, so later changes to it don't affect the template.
+#[post("/saved/{save_id}/template", wrap = "http::MAIN_API")]
+pub async fn r_post_saved_template(
+    info: web::Path<String>,
+    web::Json(promote): web::Json<TemplatePromote>,
+    user: CurrentUser,
+) -> ApiResult<HttpResponse> {
+    let user = user.require_admin()?;
+
+    let name = promote.name.trim().to_owned();
+    if !templates::is_valid_name(&name) {
+        return Err(ApiError::InvalidTemplate(
+            "Template names are 1 to 32 lowercase letters, digits or '-'".to_owned(),
+        ));
+    }
+    if templates::builtin(&name).is_some() {
+        return Err(ApiError::InvalidTemplate(format!(
+            "{} is a built-in template",
+            name
+        )));
+    }
+    let title = trim_limited(promote.title, "titles", MAX_TITLE_CHARS)?;
+    let description = trim_limited(promote.description, "descriptions", MAX_DESCRIPTION_CHARS)?;
+
+    let save_id = info.0;
+    let (db, saved) = Db::open_env().await?.get_saved_info(&save_id).await?;
+    if saved.deleted_at.is_some() {
+        return Err(ApiError::SavedGone);
+    }
+    let (_, template) = db
+        .promote_saved(&save_id, &name, title, description, &user.user_id)
+        .await?;
+    // Deleted since it was checked above
+    let template = template.ok_or(ApiError::SavedGone)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "template": template })))
+}
+
+/// Delete a template promoted from a save, for admins only. Built-in ones can't be deleted.
+#[delete("/templates/{name}", wrap = "http::MAIN_API")]
+pub async fn r_delete_template(
+    info: web::Path<String>,
+    user: CurrentUser,
+) -> ApiResult<HttpResponse> {
+    user.require_admin()?;
+
+    let name = info.0;
+    if templates::builtin(&name).is_some() {
+        return Err(ApiError::InvalidTemplate(format!(
+            "{} is a built-in template",
+            name
+        )));
+    }
+    let (_, deleted) = Db::open_env().await?.delete_template(&name).await?;
+
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiError::TemplateNotFound)
+    }
+}
+
+/// Routes `POST /session/new?template=…` here rather than to [super::session::r_post_session_new].
+fn has_template_query(head: &RequestHead) -> bool {
+    let query = head.uri.query().unwrap_or_default();
+    query
+        .split('&')
+        .any(|pair| pair.split('=').next() == Some("template"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateQuery {
+    template: String,
+}
+
+/// Start a new session from a template. Responds like [super::session::r_post_session_new]
+/// plus the files and the template.
+#[post("/session/new", wrap = "http::MAIN_API", guard = "has_template_query")]
+pub async fn r_post_session_new_template(
+    query: web::Query<TemplateQuery>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    rate_limit::check(Budget::Create, client.ip)?;
+
+    let db = Db::open_env().await?;
+    let (db, template, session) = template_session(db, &query.template).await?;
+    check_quotas(&session)?;
+
+    let (_, session_id, edit_token) = create_session(db, &session).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "session_id": session_id,
+        "edit_token": edit_token,
+        "revision": 1,
+        "revisions": patch::revisions(&session.files),
+        "session": session,
+        "template": template,
+    })))
+}
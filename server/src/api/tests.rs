@@ -0,0 +1,275 @@
+use crate::{
+    api::{
+        compile::compile_cached,
+        error::{ApiError, ApiResult},
+        frame::try_get_file,
+        util,
+    },
+    compile_service::CompileOptions,
+    db::{unix_now, Db},
+    forwarded::ClientInfo,
+    headless::{self, RunLimits},
+    http,
+    http_error::{ErrorMime, HttpError},
+    ids::make_test_run_id,
+    junit::to_junit_xml,
+    rate_limit::{self, Budget},
+    run_token,
+    state::{FileKind, TestResult, TestRun, TestSource},
+};
+use actix_web::{get, post, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Instant;
+
+/// What `inject!(tests)` loads before page.test.js: describe/it/expect, then the script that
+/// runs the tests once the page has loaded and reports them with [r_post_session_tests].
+const FRAME_TESTS_JS: &str = concat!(
+    include_str!("../test_runner.js"),
+    include_str!("../test_frame.js")
+);
+
+/// Most results the frame may report for one run.
+const MAX_RESULTS: usize = 1000;
+
+/// Most bytes kept of a test's name, suites or failure, or the error of a run.
+const MAX_TEXT: usize = 1024 * 16;
+
+fn truncate(text: String) -> String {
+    util::truncate(text, MAX_TEXT)
+}
+
+#[get("/tests.js", wrap = "http::FRAME_JS")]
+pub async fn r_get_tests_js() -> HttpResponse {
+    HttpResponse::Ok()
+        .header("content-type", "application/javascript; charset=utf-8")
+        .header("x-content-type-options", "nosniff")
+        .body(FRAME_TESTS_JS)
+}
+
+#[get("/session/{session_id}/page.test.js", wrap = "http::FRAME_JS")]
+pub async fn r_get_session_page_test_js(
+    info: web::Path<String>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::JavaScript;
+    let db = Db::open_env()
+        .await
+        .map_err(|err| HttpError::db_error(err).with_mime(err_mime))?;
+    let session_id = info.0;
+    let (db, meta, code) = try_get_file(db, &session_id, err_mime, FileKind::Tests).await?;
+
+    let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+    let file_name = FileKind::Tests.to_default_name();
+    let (_, code) = compile_cached(
+        db,
+        &session_id,
+        file_name,
+        &code,
+        options,
+        client.ip,
+        err_mime,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .header("content-type", "application/javascript; charset=utf-8")
+        .header("x-content-type-options", "nosniff")
+        .body(code))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FrameTestRun {
+    /// The run token page.html was served with, like for its console output, see [run_token]
+    run_token: String,
+    /// Milliseconds
+    duration: f64,
+    error: Option<String>,
+    results: Vec<TestResult>,
+}
+
+/// Stores the results of page.test.js run in the frame by `inject!(tests)`, replacing the
+/// session's previous test run.
+#[post("/session/{session_id}/tests", wrap = "http::FRAME_API")]
+pub async fn r_post_session_tests(
+    info: web::Path<String>,
+    web::Json(run): web::Json<FrameTestRun>,
+    client: ClientInfo,
+) -> ApiResult<HttpResponse> {
+    // Sent by the frame once per load, like its console output
+    rate_limit::check(Budget::Logs, client.ip)?;
+
+    let session_id = info.0;
+    let FrameTestRun {
+        run_token,
+        duration,
+        error,
+        mut results,
+    } = run;
+    let run_id = run_token::verify(&run_token, &session_id).ok_or(ApiError::InvalidRunToken)?;
+    if results.len() > MAX_RESULTS {
+        return Err(ApiError::InvalidTestRun(format!(
+            "{} results were sent, the limit is {}",
+            results.len(),
+            MAX_RESULTS
+        )));
+    }
+    for result in &mut results {
+        result.name = truncate(std::mem::take(&mut result.name));
+        result.failure = result.failure.take().map(truncate);
+        for suite in &mut result.suite {
+            *suite = truncate(std::mem::take(suite));
+        }
+    }
+
+    let (db, _) = Db::open_env().await?.get_session(&session_id).await?;
+    let run = TestRun::new(
+        run_id,
+        TestSource::Frame,
+        unix_now(),
+        duration,
+        error.map(truncate),
+        results,
+    );
+    db.put_test_run(&session_id, run).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The session's newest test run, from the frame or the server, or null if there's none.
+#[get("/session/{session_id}/tests", wrap = "http::MAIN_API")]
+pub async fn r_get_session_tests(info: web::Path<String>) -> ApiResult<HttpResponse> {
+    let session_id = info.0;
+    let (db, _) = Db::open_env().await?.get_session(&session_id).await?;
+    let (_, run) = db.get_test_run(&session_id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "run": run })))
+}
+
+/// The session's newest test run as JUnit XML, see [crate::junit].
+#[get("/session/{session_id}/tests.xml", wrap = "http::MAIN_API")]
+pub async fn r_get_session_tests_xml(info: web::Path<String>) -> ApiResult<HttpResponse> {
+    let session_id = info.0;
+    let (db, _) = Db::open_env().await?.get_session(&session_id).await?;
+    let (_, run) = db.get_test_run(&session_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .header("content-type", "application/xml; charset=utf-8")
+        .body(to_junit_xml(run.as_ref())))
+}
+
+/// Runs the tests of the session's page.test.js on the server (see [crate::headless]) and
+/// stores the results as its newest test run. Responds with the run and the console output.
+#[post("/session/{session_id}/tests/run", wrap = "http::MAIN_API")]
+pub async fn r_post_session_tests_run(
+    info: web::Path<String>,
+    client: ClientInfo,
+) -> Result<HttpResponse, HttpError> {
+    let err_mime = ErrorMime::Json;
+    let db_err = |err| HttpError::db_error(err).with_mime(err_mime);
+    if let Err(limited) = rate_limit::check(Budget::Run, client.ip) {
+        return Err(HttpError::rate_limited(limited).with_mime(err_mime));
+    }
+
+    let session_id = info.0;
+    let db = Db::open_env().await.map_err(db_err)?;
+    let (db, meta, tests) = try_get_file(db, &session_id, err_mime, FileKind::Tests).await?;
+    let (db, revision) = db.get_session_revision(&session_id).await.map_err(db_err)?;
+    let options = CompileOptions::for_file_kinds(&meta.file_kinds);
+
+    let compiled = async {
+        let file_name = FileKind::Tests.to_default_name();
+        let (db, tests) = compile_cached(
+            db,
+            &session_id,
+            file_name,
+            &tests,
+            options,
+            client.ip,
+            err_mime,
+        )
+        .await?;
+        if !meta.file_kinds.contains(&FileKind::JavaScript) {
+            return Ok::<_, HttpError>((String::new(), tests));
+        }
+        let file_name = FileKind::JavaScript.to_default_name();
+        let (db, page) = db.get_file(&session_id, file_name).await.map_err(db_err)?;
+        let (_, page) = compile_cached(
+            db,
+            &session_id,
+            file_name,
+            &page,
+            options,
+            client.ip,
+            err_mime,
+        )
+        .await?;
+        Ok((page, tests))
+    };
+
+    let started_at = unix_now();
+    let started = Instant::now();
+    let limits = RunLimits::from_env();
+    let (mut output, error) = match compiled.await {
+        Ok((page, tests)) => {
+            let output = headless::run_tests(&page, &tests, limits)
+                .await
+                .map_err(|err| HttpError::headless_run_fail(err).with_mime(err_mime))?;
+            let error = if output.timed_out {
+                Some(format!("Timed out after {} ms", limits.time.as_millis()))
+            } else if output.tests.is_none() {
+                Some("The tests stopped before reporting their results".to_owned())
+            } else {
+                None
+            };
+            (output, error)
+        }
+        // Reported as the error of the run, rather than of the request
+        Err(err) if err.code == "js_compile_fail" => {
+            let message = err.message.trim_start_matches("Reason:\n");
+            (
+                Default::default(),
+                Some(format!("CompileError: {}", message)),
+            )
+        }
+        Err(err) => return Err(err),
+    };
+
+    let report = output.tests.take().unwrap_or_default();
+    // Errors that kept page.test.js from defining its tests, like a failed import
+    let error = error.or_else(|| match output.errors.first() {
+        Some(first) if report.results.is_empty() => {
+            Some(format!("{}: {}", first.name, first.message))
+        }
+        _ => None,
+    });
+    let duration = if report.duration > 0.0 {
+        report.duration
+    } else {
+        started.elapsed().as_secs_f64() * 1000.0
+    };
+    let mut run = TestRun::new(
+        make_test_run_id(),
+        TestSource::Headless,
+        started_at,
+        duration,
+        error.map(truncate),
+        report.results,
+    );
+    run.revision = Some(revision);
+
+    Db::open_env()
+        .await
+        .map_err(db_err)?
+        .put_test_run(&session_id, run.clone())
+        .await
+        .map_err(db_err)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "revision": revision,
+        "run": run,
+        "entries": output.entries,
+        "errors": output.errors,
+        "truncated": output.truncated,
+    })))
+}
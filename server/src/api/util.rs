@@ -1,7 +1,52 @@
 use crate::{
+    api::error::{ApiError, ApiResult},
     db::{Db, DbResult},
-    state::Session,
+    env,
+    hash::sha1_hex,
+    state::{File, Session, SessionMeta},
 };
+use actix_web::HttpRequest;
+
+/// The `x-edit-token` header, which authorizes changes to a session or save.
+pub fn edit_token_header(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("x-edit-token")
+        .and_then(|token| token.to_str().ok())
+}
+
+/// Check an edit token against the session's. Sessions created before edit tokens have
+/// none, so nobody can prove they made them; they're read-only, and can be saved and
+/// forked to keep editing.
+pub async fn check_edit_token(db: Db, session_id: &str, edit_token: &str) -> ApiResult<Db> {
+    let (db, stored) = db.get_edit_token_hash(session_id).await?;
+    match stored {
+        Some(stored) if stored == sha1_hex(edit_token) => Ok(db),
+        Some(_) => Err(ApiError::InvalidEditToken),
+        None => Err(ApiError::SessionReadOnly),
+    }
+}
+
+/// Enforce the file count and per-file size limits, before anything is stored. The total
+/// size is limited by the API's `JsonConfig`.
+pub fn check_quotas(session: &Session) -> ApiResult<()> {
+    let max = env::max_files();
+    if session.files.len() > max {
+        return Err(ApiError::TooManyFiles {
+            count: session.files.len(),
+            max,
+        });
+    }
+
+    let max = env::max_file_bytes();
+    match session.files.iter().find(|file| file.contents.len() > max) {
+        Some(file) => Err(ApiError::FileTooLarge {
+            kind: file.kind,
+            size: file.contents.len(),
+            max,
+        }),
+        None => Ok(()),
+    }
+}
 
 /// Store the session/saved files in sqlite.
 pub async fn put_files(mut db: Db, session_id: &str, session: &Session) -> DbResult<Db> {
@@ -12,3 +57,61 @@ pub async fn put_files(mut db: Db, session_id: &str, session: &Session) -> DbRes
 
     Ok(db)
 }
+
+/// Load the session/saved files listed in its meta.
+pub async fn get_files(
+    mut db: Db,
+    session_or_saved_id: &str,
+    meta: &SessionMeta,
+) -> DbResult<(Db, Vec<File>)> {
+    let mut files = vec![];
+    for file_kind in &meta.file_kinds {
+        let file_name = file_kind.to_default_name();
+        let (db2, contents) = db.get_file(session_or_saved_id, file_name).await?;
+        db = db2;
+        files.push(File::new(*file_kind, contents));
+    }
+    Ok((db, files))
+}
+
+/// Shorten text sent by a frame to at most `max` bytes.
+pub fn truncate(mut text: String, max: usize) -> String {
+    if text.len() > max {
+        let mut end = max;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::session::create_session, db::TempDb, state::FileKind};
+
+    #[actix_rt::test]
+    async fn sessions_without_edit_tokens_are_read_only() {
+        let temp = TempDb::new().await;
+        let session = Session {
+            files: vec![File::new(FileKind::JavaScript, "// JavaScript".to_owned())],
+        };
+        let (db, session_id, edit_token) = create_session(temp.open(), &session).await.unwrap();
+        check_edit_token(db, &session_id, &edit_token)
+            .await
+            .unwrap();
+        let wrong = check_edit_token(temp.open(), &session_id, "x").await;
+        assert!(matches!(wrong, Err(ApiError::InvalidEditToken)));
+
+        // As stored before edit tokens. Presenting one doesn't claim the session.
+        let meta = SessionMeta {
+            file_kinds: session.file_kinds(),
+        };
+        temp.open().put_session("old", meta).await.unwrap();
+        for _ in 0..2 {
+            let claimed = check_edit_token(temp.open(), "old", &edit_token).await;
+            assert!(matches!(claimed, Err(ApiError::SessionReadOnly)));
+        }
+    }
+}
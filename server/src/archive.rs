@@ -0,0 +1,411 @@
+//! Zip and tar.gz archives of a session's files, for export and import.
+//!
+//! Archives come from anyone, so reading is bounded: at most [ArchiveLimits::entries]
+//! entries, and each one is decompressed only up to its byte limit whatever sizes the headers
+//! claim, which defuses zip bombs. Entry paths are checked before anything else: absolute
+//! paths, `..`, backslashes and drive letters are rejected, as are symlinks and other special
+//! entries. Nothing is ever written to disk.
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::io::{self, Cursor, Read, Write};
+use thiserror::Error;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Longest path accepted in an archive.
+const MAX_PATH: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveLimits {
+    /// Most entries, including directories
+    pub entries: usize,
+    /// Most bytes of one file, decompressed
+    pub entry_bytes: u64,
+    /// Most bytes of all the files together, decompressed
+    pub total_bytes: u64,
+}
+
+/// A file read from an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Relative, `/`-separated and without `.` or empty segments, see [clean_path]
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("Expected a zip, tar or tar.gz archive")]
+    UnknownFormat,
+
+    #[error("Invalid zip archive: {source}")]
+    Zip { source: zip::result::ZipError },
+
+    #[error("Invalid tar archive: {source}")]
+    Tar { source: io::Error },
+
+    #[error("Invalid path {path:?} in the archive")]
+    InvalidPath { path: String },
+
+    #[error("{path:?} in the archive is a {what}, only files and directories are allowed")]
+    Unsupported { path: String, what: &'static str },
+
+    #[error("{path:?} appears twice in the archive")]
+    Duplicate { path: String },
+
+    #[error("The archive has more than {max} entries")]
+    TooManyEntries { max: usize },
+
+    #[error("{path:?} in the archive is larger than the limit of {max} bytes")]
+    EntryTooLarge { path: String, max: u64 },
+
+    #[error("The files in the archive are larger than the limit of {max} bytes together")]
+    TooLarge { max: u64 },
+}
+
+/// Write the files, given by path, into an archive.
+pub fn write_archive(format: ArchiveFormat, files: &[(String, &[u8])]) -> io::Result<Vec<u8>> {
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipWriter::new(Cursor::new(vec![]));
+            let options = FileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .unix_permissions(0o644);
+            for (path, contents) in files {
+                zip.start_file(path, options)?;
+                zip.write_all(contents)?;
+            }
+            Ok(zip.finish()?.into_inner())
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+            for (path, contents) in files {
+                let mut header = tar::Header::new_ustar();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(crate::db::unix_now() as u64);
+                header.set_entry_type(tar::EntryType::Regular);
+                tar.append_data(&mut header, path, *contents)?;
+            }
+            tar.into_inner()?.finish()
+        }
+    }
+}
+
+/// Check a path from an archive, and normalize `./a//b` to `a/b`. Directories end up without
+/// their trailing `/`.
+pub fn clean_path(raw: &[u8]) -> Result<String, ArchiveError> {
+    let invalid = || ArchiveError::InvalidPath {
+        path: String::from_utf8_lossy(raw).into_owned(),
+    };
+    let path = std::str::from_utf8(raw).map_err(|_| invalid())?;
+    if path.len() > MAX_PATH
+        || path.starts_with('/')
+        || path.contains(|c: char| c == '\\' || c == ':' || c.is_control())
+    {
+        return Err(invalid());
+    }
+
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(invalid()),
+            segment => segments.push(segment),
+        }
+    }
+    if segments.is_empty() {
+        return Err(invalid());
+    }
+    Ok(segments.join("/"))
+}
+
+/// Read the files of a zip, tar or tar.gz archive, recognized by its first bytes.
+/// Directories are skipped.
+pub fn read_archive(
+    bytes: &[u8],
+    limits: ArchiveLimits,
+) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+    let mut reader = EntryReader {
+        limits,
+        entries: 0,
+        total_bytes: 0,
+        files: vec![],
+    };
+    if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+        reader.read_zip(bytes)?;
+    } else if bytes.starts_with(&[0x1f, 0x8b]) {
+        reader.read_tar(GzDecoder::new(bytes))?;
+    } else if bytes.get(257..262) == Some(b"ustar") {
+        reader.read_tar(bytes)?;
+    } else {
+        return Err(ArchiveError::UnknownFormat);
+    }
+    Ok(reader.files)
+}
+
+struct EntryReader {
+    limits: ArchiveLimits,
+    entries: usize,
+    total_bytes: u64,
+    files: Vec<ArchiveEntry>,
+}
+
+impl EntryReader {
+    /// Count an entry, and check its path. None for directories.
+    fn start(&mut self, raw_path: &[u8], is_dir: bool) -> Result<Option<String>, ArchiveError> {
+        self.entries += 1;
+        if self.entries > self.limits.entries {
+            return Err(ArchiveError::TooManyEntries {
+                max: self.limits.entries,
+            });
+        }
+        let path = clean_path(raw_path)?;
+        if is_dir {
+            return Ok(None);
+        }
+        if self.files.iter().any(|file| file.path == path) {
+            return Err(ArchiveError::Duplicate { path });
+        }
+        Ok(Some(path))
+    }
+
+    /// Read a file's contents, without trusting the size in its header.
+    fn read(
+        &mut self,
+        path: String,
+        contents: impl Read,
+        io_err: fn(io::Error) -> ArchiveError,
+    ) -> Result<(), ArchiveError> {
+        let entry_max = self.limits.entry_bytes;
+        let max = entry_max.min(self.limits.total_bytes - self.total_bytes);
+        let mut buf = vec![];
+        contents
+            .take(max + 1)
+            .read_to_end(&mut buf)
+            .map_err(io_err)?;
+
+        let size = buf.len() as u64;
+        if size > entry_max {
+            return Err(ArchiveError::EntryTooLarge {
+                path,
+                max: entry_max,
+            });
+        }
+        if size > max {
+            return Err(ArchiveError::TooLarge {
+                max: self.limits.total_bytes,
+            });
+        }
+        self.total_bytes += size;
+        self.files.push(ArchiveEntry {
+            path,
+            contents: buf,
+        });
+        Ok(())
+    }
+
+    fn read_zip(&mut self, bytes: &[u8]) -> Result<(), ArchiveError> {
+        fn zip_err(source: zip::result::ZipError) -> ArchiveError {
+            ArchiveError::Zip { source }
+        }
+        let mut zip = ZipArchive::new(Cursor::new(bytes)).map_err(zip_err)?;
+        // Checked up front, as the count comes from the end of the archive
+        if zip.len() > self.limits.entries {
+            return Err(ArchiveError::TooManyEntries {
+                max: self.limits.entries,
+            });
+        }
+
+        for index in 0..zip.len() {
+            let file = zip.by_index(index).map_err(zip_err)?;
+            let is_symlink = file
+                .unix_mode()
+                .is_some_and(|mode| mode & 0o170000 == 0o120000);
+            if is_symlink {
+                return Err(ArchiveError::Unsupported {
+                    path: String::from_utf8_lossy(file.name_raw()).into_owned(),
+                    what: "symlink",
+                });
+            }
+            if let Some(path) = self.start(file.name_raw(), file.is_dir())? {
+                self.read(path, file, |err| zip_err(err.into()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_tar(&mut self, bytes: impl Read) -> Result<(), ArchiveError> {
+        fn tar_err(source: io::Error) -> ArchiveError {
+            ArchiveError::Tar { source }
+        }
+        // Bounds the decompressed stream too, as the data of entries that aren't read (like a
+        // directory with a size) is skipped by decompressing it
+        let headers = (self.limits.entries as u64 + 1) * 4096;
+        let mut tar = tar::Archive::new(bytes.take(self.limits.total_bytes + headers));
+
+        for entry in tar.entries().map_err(tar_err)? {
+            let entry = entry.map_err(tar_err)?;
+            let kind = entry.header().entry_type();
+            let is_dir = match kind {
+                tar::EntryType::Regular | tar::EntryType::Continuous => false,
+                tar::EntryType::Directory => true,
+                // Metadata, like the commit id `git archive` puts at the start
+                tar::EntryType::XGlobalHeader | tar::EntryType::XHeader => continue,
+                other => {
+                    let what = match other {
+                        tar::EntryType::Symlink => "symlink",
+                        tar::EntryType::Link => "hard link",
+                        _ => "special file",
+                    };
+                    return Err(ArchiveError::Unsupported {
+                        path: String::from_utf8_lossy(&entry.path_bytes()).into_owned(),
+                        what,
+                    });
+                }
+            };
+            let raw_path = entry.path_bytes().into_owned();
+            if let Some(path) = self.start(&raw_path, is_dir)? {
+                self.read(path, entry, tar_err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        entries: 8,
+        entry_bytes: 64,
+        total_bytes: 100,
+    };
+
+    fn files(entries: &[(&str, &str)]) -> Vec<(String, Vec<u8>)> {
+        let to_bytes =
+            |(path, contents): &(&str, &str)| (path.to_string(), contents.as_bytes().to_vec());
+        entries.iter().map(to_bytes).collect()
+    }
+
+    fn write(format: ArchiveFormat, entries: &[(&str, &str)]) -> Vec<u8> {
+        let files = files(entries);
+        let files: Vec<_> = files.iter().map(|(p, c)| (p.clone(), &c[..])).collect();
+        write_archive(format, &files).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let entries = [("ject-a/page.js", "let a = 1;\n"), ("ject-a/page.css", "")];
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz] {
+            let read = read_archive(&write(format, &entries), LIMITS).unwrap();
+            let paths: Vec<_> = read.iter().map(|entry| entry.path.as_str()).collect();
+            assert_eq!(paths, ["ject-a/page.js", "ject-a/page.css"]);
+            assert_eq!(read[0].contents, b"let a = 1;\n");
+        }
+    }
+
+    #[test]
+    fn cleans_and_rejects_paths() {
+        assert_eq!(clean_path(b"./a//b.js").unwrap(), "a/b.js");
+        assert_eq!(clean_path(b"a/").unwrap(), "a");
+        for path in [
+            &b"../a"[..],
+            b"a/../../b",
+            b"/etc/passwd",
+            b"C:/a",
+            b"a\\..\\b",
+            b"a\0b",
+            b"./",
+            b"\xff",
+        ] {
+            assert!(clean_path(path).is_err(), "{:?}", path);
+        }
+
+        let zip = write(ArchiveFormat::Zip, &[("../evil.js", "")]);
+        let err = read_archive(&zip, LIMITS).unwrap_err();
+        assert!(matches!(err, ArchiveError::InvalidPath { .. }), "{}", err);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let big = "x".repeat(65);
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz] {
+            let err = read_archive(&write(format, &[("a", &big)]), LIMITS).unwrap_err();
+            assert!(matches!(err, ArchiveError::EntryTooLarge { .. }), "{}", err);
+
+            let half = "x".repeat(60);
+            let archive = write(format, &[("a", &half), ("b", &half)]);
+            let err = read_archive(&archive, LIMITS).unwrap_err();
+            assert!(matches!(err, ArchiveError::TooLarge { .. }), "{}", err);
+
+            let many: Vec<_> = (0..9).map(|i| (i.to_string(), "")).collect();
+            let many: Vec<_> = many.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+            let err = read_archive(&write(format, &many), LIMITS).unwrap_err();
+            assert!(
+                matches!(err, ArchiveError::TooManyEntries { .. }),
+                "{}",
+                err
+            );
+        }
+
+        // Compresses to a few KiB, but is only read up to the limit
+        let bomb = "0".repeat(64 * 1024 * 1024);
+        let zip = write(ArchiveFormat::Zip, &[("bomb", &bomb)]);
+        assert!(zip.len() < 1024 * 1024);
+        let err = read_archive(&zip, LIMITS).unwrap_err();
+        assert!(matches!(err, ArchiveError::EntryTooLarge { .. }), "{}", err);
+
+        assert!(matches!(
+            read_archive(b"not an archive", LIMITS),
+            Err(ArchiveError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn skips_pax_headers() {
+        // Like `git archive`, which starts with a global header holding the commit id
+        let mut tar = tar::Builder::new(vec![]);
+        let comment = b"52 comment=0123456789abcdef0123456789abcdef01234567\n";
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XGlobalHeader);
+        header.set_path("pax_global_header").unwrap();
+        header.set_size(comment.len() as u64);
+        header.set_cksum();
+        tar.append(&header, &comment[..]).unwrap();
+        tar.append_pax_extensions([("mtime", &b"1700000000.5"[..])])
+            .unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(3);
+        header.set_cksum();
+        tar.append_data(&mut header, "ject-a/page.js", &b"1;\n"[..])
+            .unwrap();
+
+        let read = read_archive(&tar.into_inner().unwrap(), LIMITS).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].path, "ject-a/page.js");
+        assert_eq!(read[0].contents, b"1;\n");
+    }
+}
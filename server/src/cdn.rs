@@ -1,9 +1,5 @@
 pub fn cdnjs_src(suffix: &str) -> String {
-    let suffix = if suffix.starts_with("/") {
-        &suffix[1..]
-    } else {
-        suffix
-    };
+    let suffix = suffix.strip_prefix('/').unwrap_or(suffix);
     format!("https://cdnjs.cloudflare.com/ajax/libs/{}", suffix)
 }
 
@@ -13,3 +9,21 @@ pub fn cdnjs_script(suffix: &str) -> String {
         cdnjs_src(suffix)
     )
 }
+
+/// cdnjs paths of the UMD builds for a package, used by `inject!(deps.<name>)` and
+/// `inject!(deps.auto)`
+pub fn umd_scripts(package: &str) -> Option<&'static [&'static str]> {
+    match package {
+        "react" | "react-dom" => Some(&[
+            "react/17.0.2/umd/react.development.min.js",
+            "react-dom/17.0.2/umd/react-dom.development.min.js",
+        ]),
+        "jquery" => Some(&["jquery/3.6.0/jquery.min.js"]),
+        _ => None,
+    }
+}
+
+/// URL of an ES module build of a package on Skypack, e.g. `react@17.0.2`
+pub fn skypack_src(package: &str) -> String {
+    format!("https://cdn.skypack.dev/{}", package)
+}
@@ -0,0 +1,678 @@
+//! Collaborative editing of sessions. While anyone has a session open in collaborative mode
+//! (see [crate::api::collab::r_get_session_collab]), a [Room] holds the authoritative text
+//! of each of its files as a [Document], and every editor sends [ot] operations against a
+//! document revision. Operations made concurrently are transformed against the ones that
+//! were applied first, then broadcast to the other peers along with their cursors.
+//!
+//! Rooms live in memory; the files are written back to the database by the api module.
+
+use crate::{
+    env,
+    ot::{self, Operation},
+    state::{File, FileKind},
+};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+pub type PeerId = u64;
+
+/// Operations kept per document to transform late operations against. A peer that falls
+/// further behind has to rejoin.
+const HISTORY_LEN: usize = 1000;
+
+/// Sender of the operations that didn't come from a peer, see [Room::overwrite]. Peer ids
+/// start at 1.
+pub const SERVER_PEER_ID: PeerId = 0;
+
+/// Peer colors, assigned in order to the peers in a room.
+const COLORS: [&str; 8] = [
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4", "#42d4f4", "#f032e6", "#9a6324",
+];
+
+/// A peer's selection in a file. Offsets are in UTF-16 code units, and `head` is where the
+/// caret is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub file: FileKind,
+    pub anchor: usize,
+    pub head: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    pub name: String,
+    pub color: &'static str,
+    pub can_edit: bool,
+    pub cursors: Vec<Cursor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DocumentState {
+    pub kind: FileKind,
+    pub contents: String,
+    pub revision: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// An edit made against `revision` of the file.
+    Op {
+        file: FileKind,
+        revision: u64,
+        op: Operation,
+    },
+    /// The peer's selection moved. Only the latest one per file is kept.
+    Cursor(Cursor),
+    /// Write the files to the database now and reload the session's frames, i.e. run.
+    Save,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message to a peer, with the documents to edit and who else is there.
+    Joined {
+        peer_id: PeerId,
+        can_edit: bool,
+        files: Vec<DocumentState>,
+        peers: Vec<PeerInfo>,
+    },
+    /// The peer's operation was applied as `revision` of the file.
+    Ack {
+        file: FileKind,
+        revision: u64,
+    },
+    /// Another peer's operation, applied as `revision` of the file.
+    Op {
+        file: FileKind,
+        revision: u64,
+        op: Operation,
+        peer_id: PeerId,
+    },
+    /// A peer joined or moved their cursors.
+    Presence(PeerInfo),
+    Left {
+        peer_id: PeerId,
+    },
+    /// The files were saved as this session revision, and the frames are reloading.
+    Saved {
+        revision: i64,
+        peer_id: PeerId,
+    },
+    Error {
+        code: &'static str,
+        message: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CollabError {
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+
+    #[error("You need the edit token to change this session")]
+    ReadOnly,
+
+    #[error("The session has no {}", .0.to_default_name())]
+    UnknownFile(FileKind),
+
+    #[error("Revision {revision} of {} is too old or doesn't exist, rejoin to catch up", kind.to_default_name())]
+    InvalidRevision { kind: FileKind, revision: u64 },
+
+    #[error("Can't apply the operation to {}: {source}", kind.to_default_name())]
+    InvalidOperation { kind: FileKind, source: ot::OtError },
+
+    #[error("{} would be larger than the limit of {max} bytes", kind.to_default_name())]
+    FileTooLarge { kind: FileKind, max: usize },
+}
+
+impl CollabError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CollabError::InvalidMessage(_) => "invalid_message",
+            CollabError::ReadOnly => "read_only",
+            CollabError::UnknownFile(_) => "unknown_file",
+            CollabError::InvalidRevision { .. } => "invalid_revision",
+            CollabError::InvalidOperation { .. } => "invalid_operation",
+            CollabError::FileTooLarge { .. } => "file_too_large",
+        }
+    }
+}
+
+impl From<&CollabError> for ServerMessage {
+    fn from(err: &CollabError) -> Self {
+        ServerMessage::Error {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// The text of a file, with the operations that made its most recent revisions.
+#[derive(Debug)]
+struct Document {
+    kind: FileKind,
+    text: String,
+    revision: u64,
+    /// Revision last written to the database
+    saved_revision: u64,
+    history: VecDeque<Operation>,
+}
+
+impl Document {
+    fn new(file: File) -> Self {
+        Self {
+            kind: file.kind,
+            text: file.contents,
+            revision: 0,
+            saved_revision: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Transform an operation made against `base` past the ones applied since, then apply
+    /// it. Returns the operation as applied.
+    fn receive(&mut self, base: u64, mut op: Operation) -> Result<Operation, CollabError> {
+        let invalid_revision = || CollabError::InvalidRevision {
+            kind: self.kind,
+            revision: base,
+        };
+        let behind = self
+            .revision
+            .checked_sub(base)
+            .ok_or_else(invalid_revision)? as usize;
+        if behind > self.history.len() {
+            return Err(invalid_revision());
+        }
+
+        let invalid = |source| CollabError::InvalidOperation {
+            kind: self.kind,
+            source,
+        };
+        for concurrent in self.history.range(self.history.len() - behind..) {
+            op = Operation::transform(&op, concurrent).map_err(invalid)?.0;
+        }
+        let text = op.apply(&self.text).map_err(invalid)?;
+
+        let max = env::max_file_bytes();
+        if text.len() > max {
+            return Err(CollabError::FileTooLarge {
+                kind: self.kind,
+                max,
+            });
+        }
+
+        self.text = text;
+        self.revision += 1;
+        self.history.push_back(op.clone());
+        if self.history.len() > HISTORY_LEN {
+            self.history.pop_front();
+        }
+        Ok(op)
+    }
+
+    fn state(&self) -> DocumentState {
+        DocumentState {
+            kind: self.kind,
+            contents: self.text.clone(),
+            revision: self.revision,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    info: PeerInfo,
+    sender: UnboundedSender<ServerMessage>,
+}
+
+/// The documents of a session being edited, and the peers editing them.
+#[derive(Debug)]
+pub struct Room {
+    session_id: String,
+    docs: Vec<Document>,
+    peers: BTreeMap<PeerId, Peer>,
+    next_peer_id: PeerId,
+    /// Held while writing the files, so an older write can't finish after a newer one
+    write_lock: Arc<futures::lock::Mutex<()>>,
+}
+
+static ROOMS: Lazy<Mutex<HashMap<String, Arc<Mutex<Room>>>>> = Lazy::new(Default::default);
+
+fn rooms() -> std::sync::MutexGuard<'static, HashMap<String, Arc<Mutex<Room>>>> {
+    ROOMS.lock().expect("collab rooms should never be poisoned")
+}
+
+/// A peer's place in a room. Their messages arrive on `receiver` until they leave.
+pub struct Joined {
+    pub room: Arc<Mutex<Room>>,
+    pub peer_id: PeerId,
+    pub receiver: UnboundedReceiver<ServerMessage>,
+}
+
+pub fn lock(room: &Arc<Mutex<Room>>) -> std::sync::MutexGuard<'_, Room> {
+    room.lock().expect("collab room should never be poisoned")
+}
+
+/// Whether anyone who can edit the session is in its room. It can't be changed any other way
+/// then, as the room would write its own copy of the files over the change.
+pub fn has_editor(session_id: &str) -> bool {
+    match rooms().get(session_id) {
+        Some(room) => lock(room).peers.values().any(|peer| peer.info.can_edit),
+        None => false,
+    }
+}
+
+/// Give the session's room, if it's open, files that were written to the database some
+/// other way. See [Room::overwrite].
+pub fn overwrite(session_id: &str, files: &[File]) {
+    let room = rooms().get(session_id).cloned();
+    if let Some(room) = room {
+        lock(&room).overwrite(files);
+    }
+}
+
+/// Join the session's room, if it's open. See [Room::join].
+pub fn join(session_id: &str, name: Option<String>, can_edit: bool) -> Option<Joined> {
+    let rooms = rooms();
+    let room = rooms.get(session_id)?;
+    let (peer_id, receiver) = lock(room).join(name, can_edit);
+    Some(Joined {
+        room: room.clone(),
+        peer_id,
+        receiver,
+    })
+}
+
+/// Join the session's room, opening it with `files` if it isn't open. The files must be the
+/// session's current ones.
+pub fn open_and_join(
+    session_id: &str,
+    files: Vec<File>,
+    name: Option<String>,
+    can_edit: bool,
+) -> Joined {
+    let mut rooms = rooms();
+    let room = rooms.entry(session_id.to_owned()).or_insert_with(|| {
+        Arc::new(Mutex::new(Room {
+            session_id: session_id.to_owned(),
+            docs: files.into_iter().map(Document::new).collect(),
+            peers: BTreeMap::new(),
+            next_peer_id: 1,
+            write_lock: Default::default(),
+        }))
+    });
+    let (peer_id, receiver) = lock(room).join(name, can_edit);
+    Joined {
+        room: room.clone(),
+        peer_id,
+        receiver,
+    }
+}
+
+/// Every open room, e.g. to write them to the database.
+pub fn all_rooms() -> Vec<Arc<Mutex<Room>>> {
+    rooms().values().cloned().collect()
+}
+
+/// Close the session's room if nobody is in it. Returns whether it was closed.
+pub fn close_if_empty(session_id: &str) -> bool {
+    let mut rooms = rooms();
+    let is_empty = match rooms.get(session_id) {
+        Some(room) => lock(room).is_empty(),
+        None => false,
+    };
+    if is_empty {
+        rooms.remove(session_id);
+    }
+    is_empty
+}
+
+impl Room {
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn write_lock(&self) -> Arc<futures::lock::Mutex<()>> {
+        self.write_lock.clone()
+    }
+
+    /// Add a peer, who first receives a [ServerMessage::Joined]. The others are told about
+    /// them with a [ServerMessage::Presence].
+    fn join(
+        &mut self,
+        name: Option<String>,
+        can_edit: bool,
+    ) -> (PeerId, UnboundedReceiver<ServerMessage>) {
+        let peer_id = self.next_peer_id;
+        self.next_peer_id += 1;
+
+        let color = COLORS
+            .iter()
+            .copied()
+            .find(|color| self.peers.values().all(|peer| peer.info.color != *color))
+            .unwrap_or(COLORS[peer_id as usize % COLORS.len()]);
+        let info = PeerInfo {
+            peer_id,
+            name: name.unwrap_or_else(|| format!("Guest {}", peer_id)),
+            color,
+            can_edit,
+            cursors: vec![],
+        };
+
+        let (sender, receiver) = unbounded();
+        let _ = sender.unbounded_send(ServerMessage::Joined {
+            peer_id,
+            can_edit,
+            files: self.docs.iter().map(Document::state).collect(),
+            peers: self.peers.values().map(|peer| peer.info.clone()).collect(),
+        });
+        self.broadcast(peer_id, ServerMessage::Presence(info.clone()));
+        self.peers.insert(peer_id, Peer { info, sender });
+
+        (peer_id, receiver)
+    }
+
+    /// Remove a peer, ending their stream of messages.
+    pub fn leave(&mut self, peer_id: PeerId) {
+        if self.peers.remove(&peer_id).is_some() {
+            self.broadcast(peer_id, ServerMessage::Left { peer_id });
+        }
+    }
+
+    /// Send a message to one peer.
+    pub fn send(&self, peer_id: PeerId, message: ServerMessage) {
+        if let Some(peer) = self.peers.get(&peer_id) {
+            let _ = peer.sender.unbounded_send(message);
+        }
+    }
+
+    /// Send a message to every peer except `from`.
+    pub fn broadcast(&self, from: PeerId, message: ServerMessage) {
+        for (peer_id, peer) in &self.peers {
+            if *peer_id != from {
+                let _ = peer.sender.unbounded_send(message.clone());
+            }
+        }
+    }
+
+    /// Apply an operation or cursor move from a peer. [ClientMessage::Save] is only checked,
+    /// and left to the caller since it writes to the database.
+    pub fn handle(&mut self, peer_id: PeerId, message: ClientMessage) -> Result<(), CollabError> {
+        match message {
+            ClientMessage::Op { file, revision, op } => {
+                self.receive_op(peer_id, file, revision, op)
+            }
+            ClientMessage::Cursor(cursor) => self.move_cursor(peer_id, cursor),
+            ClientMessage::Save => self.check_can_edit(peer_id),
+        }
+    }
+
+    /// Watchers without the edit token can't change the files, or save them.
+    fn check_can_edit(&self, peer_id: PeerId) -> Result<(), CollabError> {
+        match self.peers.get(&peer_id) {
+            Some(peer) if peer.info.can_edit => Ok(()),
+            _ => Err(CollabError::ReadOnly),
+        }
+    }
+
+    fn receive_op(
+        &mut self,
+        peer_id: PeerId,
+        file: FileKind,
+        revision: u64,
+        op: Operation,
+    ) -> Result<(), CollabError> {
+        self.check_can_edit(peer_id)?;
+        let doc = self
+            .docs
+            .iter_mut()
+            .find(|doc| doc.kind == file)
+            .ok_or(CollabError::UnknownFile(file))?;
+
+        let op = doc.receive(revision, op)?;
+        let revision = doc.revision;
+        self.move_cursors(file, &op);
+
+        self.send(peer_id, ServerMessage::Ack { file, revision });
+        self.broadcast(
+            peer_id,
+            ServerMessage::Op {
+                file,
+                revision,
+                op,
+                peer_id,
+            },
+        );
+        Ok(())
+    }
+
+    /// Keep the peers' cursors on the same text after an operation.
+    fn move_cursors(&mut self, file: FileKind, op: &Operation) {
+        for peer in self.peers.values_mut() {
+            for cursor in peer.info.cursors.iter_mut().filter(|c| c.file == file) {
+                cursor.anchor = op.transform_index(cursor.anchor);
+                cursor.head = op.transform_index(cursor.head);
+            }
+        }
+    }
+
+    /// Change the documents to files that were written to the database while nobody in the
+    /// room could edit, as operations from [SERVER_PEER_ID]. Only files the room has are
+    /// changed, and they count as saved.
+    pub fn overwrite(&mut self, files: &[File]) {
+        for file in files {
+            let doc = match self.docs.iter_mut().find(|doc| doc.kind == file.kind) {
+                Some(doc) if doc.text != file.contents => doc,
+                _ => continue,
+            };
+            let op = Operation::diff(&doc.text, &file.contents);
+            let op = match doc.receive(doc.revision, op) {
+                Ok(op) => op,
+                Err(err) => {
+                    eprintln!("[collab] overwriting {}: {}", self.session_id, err);
+                    continue;
+                }
+            };
+            let revision = doc.revision;
+            doc.saved_revision = revision;
+            self.move_cursors(file.kind, &op);
+
+            self.broadcast(
+                SERVER_PEER_ID,
+                ServerMessage::Op {
+                    file: file.kind,
+                    revision,
+                    op,
+                    peer_id: SERVER_PEER_ID,
+                },
+            );
+        }
+    }
+
+    fn move_cursor(&mut self, peer_id: PeerId, cursor: Cursor) -> Result<(), CollabError> {
+        let doc = self
+            .docs
+            .iter()
+            .find(|doc| doc.kind == cursor.file)
+            .ok_or(CollabError::UnknownFile(cursor.file))?;
+        let len = doc.text.chars().map(char::len_utf16).sum();
+        let cursor = Cursor {
+            anchor: cursor.anchor.min(len),
+            head: cursor.head.min(len),
+            ..cursor
+        };
+
+        let info = match self.peers.get_mut(&peer_id) {
+            Some(peer) => {
+                peer.info.cursors.retain(|c| c.file != cursor.file);
+                peer.info.cursors.push(cursor);
+                peer.info.clone()
+            }
+            None => return Ok(()),
+        };
+        self.broadcast(peer_id, ServerMessage::Presence(info));
+        Ok(())
+    }
+
+    /// Files changed since they were last written, with the revision to pass to
+    /// [Room::mark_saved] once they are.
+    pub fn unsaved_files(&self) -> Vec<(File, u64)> {
+        self.docs
+            .iter()
+            .filter(|doc| doc.revision != doc.saved_revision)
+            .map(|doc| (File::new(doc.kind, doc.text.clone()), doc.revision))
+            .collect()
+    }
+
+    pub fn mark_saved(&mut self, kind: FileKind, revision: u64) {
+        if let Some(doc) = self.docs.iter_mut().find(|doc| doc.kind == kind) {
+            doc.saved_revision = doc.saved_revision.max(revision);
+        }
+    }
+
+    pub fn file_kinds(&self) -> Vec<FileKind> {
+        self.docs.iter().map(|doc| doc.kind).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(json: &str) -> Operation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn room() -> Room {
+        Room {
+            session_id: "collab-test".to_owned(),
+            docs: vec![Document::new(File::new(
+                FileKind::Html,
+                "<p>hi</p>".to_owned(),
+            ))],
+            peers: BTreeMap::new(),
+            next_peer_id: 1,
+            write_lock: Default::default(),
+        }
+    }
+
+    #[test]
+    fn transforms_concurrent_operations() {
+        let mut room = room();
+        let (alice, mut alice_rx) = room.join(Some("alice".to_owned()), true);
+        let (bob, mut bob_rx) = room.join(None, true);
+        let (_, mut reader_rx) = room.join(None, false);
+        assert!(matches!(
+            alice_rx.try_next(),
+            Ok(Some(ServerMessage::Joined { .. }))
+        ));
+
+        // Both edit revision 0
+        let edit = |revision, json| ClientMessage::Op {
+            file: FileKind::Html,
+            revision,
+            op: op(json),
+        };
+        room.handle(alice, edit(0, r#"[3, "oh ", 6]"#)).unwrap();
+        room.handle(bob, edit(0, r#"[5, "!", 4]"#)).unwrap();
+        assert_eq!(room.docs[0].text, "<p>oh hi!</p>");
+        assert_eq!(room.docs[0].revision, 2);
+
+        let messages: Vec<_> = std::iter::from_fn(|| bob_rx.try_next().ok().flatten()).collect();
+        assert!(messages.contains(&ServerMessage::Ack {
+            file: FileKind::Html,
+            revision: 2
+        }));
+        assert!(messages.contains(&ServerMessage::Op {
+            file: FileKind::Html,
+            revision: 1,
+            op: op(r#"[3, "oh ", 6]"#),
+            peer_id: alice,
+        }));
+
+        let reader = 3;
+        assert_eq!(
+            room.handle(reader, edit(2, r#"["x", 13]"#)),
+            Err(CollabError::ReadOnly)
+        );
+        assert_eq!(
+            room.handle(reader, ClientMessage::Save),
+            Err(CollabError::ReadOnly)
+        );
+        assert_eq!(room.handle(alice, ClientMessage::Save), Ok(()));
+        assert!(matches!(
+            room.handle(alice, edit(3, "[13]")),
+            Err(CollabError::InvalidRevision { .. })
+        ));
+        assert_eq!(room.unsaved_files()[0].1, 2);
+
+        room.leave(bob);
+        assert!(std::iter::from_fn(|| reader_rx.try_next().ok().flatten())
+            .any(|message| message == ServerMessage::Left { peer_id: bob }));
+    }
+
+    #[test]
+    fn keeps_cursors_on_the_same_text() {
+        let mut room = room();
+        let (alice, _alice_rx) = room.join(None, true);
+        let (bob, _bob_rx) = room.join(None, true);
+        assert_ne!(room.peers[&alice].info.color, room.peers[&bob].info.color);
+
+        let cursor = Cursor {
+            file: FileKind::Html,
+            anchor: 3,
+            head: 5,
+        };
+        room.handle(bob, ClientMessage::Cursor(cursor)).unwrap();
+        room.handle(
+            alice,
+            ClientMessage::Op {
+                file: FileKind::Html,
+                revision: 0,
+                op: op(r#"[3, "oh ", 6]"#),
+            },
+        )
+        .unwrap();
+
+        let moved = &room.peers[&bob].info.cursors[0];
+        // Text inserted at a cursor goes after it
+        assert_eq!((moved.anchor, moved.head), (3, 8));
+    }
+
+    #[test]
+    fn overwrites_documents_for_readers() {
+        let mut room = room();
+        let (_, mut reader_rx) = room.join(None, false);
+        reader_rx.try_next().unwrap();
+
+        let files = [File::new(FileKind::Html, "<p>hello</p>".to_owned())];
+        room.overwrite(&files);
+        assert_eq!(room.docs[0].text, "<p>hello</p>");
+        assert!(room.unsaved_files().is_empty());
+        assert_eq!(
+            reader_rx.try_next().unwrap(),
+            Some(ServerMessage::Op {
+                file: FileKind::Html,
+                revision: 1,
+                op: op(r#"[4, "ello", -1, 4]"#),
+                peer_id: SERVER_PEER_ID,
+            })
+        );
+
+        // Unchanged files aren't sent again
+        room.overwrite(&files);
+        assert!(reader_rx.try_next().is_err());
+    }
+}
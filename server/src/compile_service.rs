@@ -1,5 +1,5 @@
+use crate::state::FileKind;
 use actix_web::client::Client;
-use serde_json::Value;
 use thiserror::Error;
 
 #[derive(Debug, serde::Deserialize)]
@@ -26,12 +26,35 @@ impl CompileError {
     }
 }
 
-pub async fn babel_compile(code: &str) -> Result<String, CompileError> {
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct CompileOptions {
+    /// Rewrite bare imports like `import 'react'` to Skypack URLs. Disabled when the page
+    /// provides an import map instead.
+    pub skypack: bool,
+}
+
+impl CompileOptions {
+    /// With a deps.json, bare imports are left for the page's import map to resolve
+    pub fn for_file_kinds(file_kinds: &[FileKind]) -> Self {
+        Self {
+            skypack: !file_kinds.contains(&FileKind::Deps),
+        }
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self { skypack: true }
+    }
+}
+
+pub async fn babel_compile(code: &str, options: CompileOptions) -> Result<String, CompileError> {
     let client = Client::default();
     let mut res = client
         .post("http://localhost:1951/api/babel")
         .send_json(&serde_json::json!({
-            "code": code.to_owned()
+            "code": code.to_owned(),
+            "skypack": options.skypack,
         }))
         .await
         .map_err(CompileError::compile_http)?;
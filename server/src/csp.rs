@@ -0,0 +1,58 @@
+use crate::env;
+
+/// CDNs that `inject!` directives and the compiler's import rewriting point at.
+static CDN_ORIGINS: &[&str] = &["https://cdnjs.cloudflare.com", "https://cdn.skypack.dev"];
+
+pub static CSP_REPORT_PATH: &str = "/api/csp-report";
+
+/// The Content-Security-Policy for the frame page.
+///
+/// Scripts may only come from the frame origin (which serves the compile output), the CDNs,
+/// any configured mirrors and the `extra_origins` the page's import map points at. Inline
+/// scripts and eval stay allowed since they're common in snippets. Other resources, like
+/// stylesheets, fonts and iframes, may come from anywhere. The sandbox keeps user code from
+/// navigating the editor's window.
+pub fn frame_csp(extra_origins: &[String]) -> String {
+    if let Some(csp) = env::frame_csp() {
+        return csp;
+    }
+
+    let mut sources = CDN_ORIGINS
+        .iter()
+        .map(|origin| origin.to_string())
+        .collect::<Vec<_>>();
+    sources.extend(env::cdn_mirrors());
+    for origin in extra_origins {
+        if !sources.contains(origin) {
+            sources.push(origin.clone());
+        }
+    }
+    let sources = sources.join(" ");
+
+    let directives = [
+        format!(
+            "script-src 'self' 'unsafe-inline' 'unsafe-eval' blob: {}",
+            sources
+        ),
+        "style-src * 'unsafe-inline' data:".to_owned(),
+        "img-src * data: blob:".to_owned(),
+        "font-src * data:".to_owned(),
+        "media-src * data: blob:".to_owned(),
+        "frame-src * data: blob:".to_owned(),
+        "connect-src *".to_owned(),
+        "object-src 'none'".to_owned(),
+        "base-uri 'self'".to_owned(),
+        format!("frame-ancestors 'self' {}:*", env::domain_main()),
+        "sandbox allow-scripts allow-same-origin allow-forms allow-modals allow-popups allow-pointer-lock allow-downloads".to_owned(),
+        format!("report-uri {}", CSP_REPORT_PATH),
+    ];
+
+    directives.join("; ")
+}
+
+/// Features user code has no business asking for from inside the frame.
+pub fn frame_permissions_policy() -> String {
+    env::frame_permissions_policy().unwrap_or_else(|| {
+        "camera=(), microphone=(), geolocation=(), payment=(), usb=(), serial=(), bluetooth=(), interest-cohort=()".to_owned()
+    })
+}
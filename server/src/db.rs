@@ -1,14 +1,32 @@
-use crate::{env::open_sqlite_env, state::SessionMeta};
+use crate::{
+    env::open_sqlite_env,
+    state::{
+        ApiToken, CompileCache, FileKind, LogEntry, LogMethod, LogRun, SavedCursor, SavedDetails,
+        SavedFilter, SavedInfo, SavedSort, SavedSummary, SearchHit, SessionMeta, TemplateSummary,
+        TestResult, TestRun, TestSource, TestStatus, User,
+    },
+};
 use actix_rt::blocking::BlockingError;
 use actix_web::{http::StatusCode, web::block, HttpResponse, ResponseError};
-use rusqlite::{params, Connection, Result, Row};
-use serde::{Deserialize, Serialize};
+use rusqlite::{named_params, params, Connection, OptionalExtension, Result, Row};
 use serde_json::json;
-use std::{borrow::Cow, fmt::Debug, sync::Mutex};
+use std::{
+    fmt::Debug,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use thiserror::Error;
 
 pub type DbResult<T, E = DbError> = Result<T, E>;
 
+/// The current time as unix seconds, the format of all timestamp columns.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Error)]
 pub enum DbError {
     #[error("Failed to open database")]
@@ -32,6 +50,13 @@ pub enum DbError {
         sql: String,
     },
 
+    #[error("Failed to add column {}.{}", table, column)]
+    AddColumn {
+        source: rusqlite::Error,
+        table: &'static str,
+        column: &'static str,
+    },
+
     #[error("Failed to deserialize file_kinds")]
     DeFileKinds { source: serde_json::Error },
 
@@ -83,6 +108,92 @@ pub enum DbError {
         session_id: String,
     },
 
+    #[error(
+        "Failed to store the compile cache for session/saved {}",
+        session_or_saved_id
+    )]
+    PutCompileCache {
+        source: rusqlite::Error,
+        session_or_saved_id: String,
+    },
+
+    #[error(
+        "Unable to get the compile cache for session/saved {}",
+        session_or_saved_id
+    )]
+    GetCompileCache {
+        source: rusqlite::Error,
+        session_or_saved_id: String,
+    },
+
+    #[error("Failed to store the edit token of session {}", session_id)]
+    PutEditToken {
+        source: rusqlite::Error,
+        session_id: String,
+    },
+
+    #[error("Failed to update saved {}. Action: {}", saved_id, action)]
+    Saved {
+        source: rusqlite::Error,
+        saved_id: String,
+        action: &'static str,
+    },
+
+    #[error("Failed to update the search index")]
+    SearchIndex { source: rusqlite::Error },
+
+    #[error("Failed to search or list saves")]
+    Search { source: rusqlite::Error },
+
+    #[error("Failed to purge deleted saves")]
+    PurgeSaved { source: rusqlite::Error },
+
+    #[error("Failed to write a revision of session {}", session_id)]
+    SessionRevision {
+        source: rusqlite::Error,
+        session_id: String,
+    },
+
+    #[error("Failed to fork saved {} into session {}", saved_id, session_id)]
+    Fork {
+        source: rusqlite::Error,
+        saved_id: String,
+        session_id: String,
+    },
+
+    #[error("Failed to {} console logs of {}", action, id)]
+    Logs {
+        source: rusqlite::Error,
+        id: String,
+        action: &'static str,
+    },
+
+    #[error("Failed to {} test results of {}", action, id)]
+    Tests {
+        source: rusqlite::Error,
+        id: String,
+        action: &'static str,
+    },
+
+    #[error("Failed to {} template {}", action, name)]
+    Template {
+        source: rusqlite::Error,
+        name: String,
+        action: &'static str,
+    },
+
+    #[error("Failed to update accounts. Action: {}", action)]
+    Account {
+        source: rusqlite::Error,
+        action: &'static str,
+    },
+
+    #[error("Failed to deserialize the tags of a save")]
+    DeTags { source: serde_json::Error },
+
+    #[error("Failed to deserialize the cached deps")]
+    DeCompileCache { source: serde_json::Error },
+
     #[error("The blocking operation was canceled")]
     BlockCanceled {},
 }
@@ -120,6 +231,7 @@ impl DbError {
         match self {
             DbError::Open { .. } => "db_open",
             DbError::CreateTable { .. } => "db_create_table",
+            DbError::AddColumn { .. } => "db_add_column",
             DbError::BlockCanceled { .. } => "db_block_canceled",
             DbError::DeFileKinds { .. } => "db_de_file_kinds",
             DbError::PutFile { .. } => "db_put_file",
@@ -129,13 +241,25 @@ impl DbError {
             DbError::GetSaved { .. } => "db_get_saved",
             DbError::GetSession { .. } => "db_get_session",
             DbError::SessionCounter { .. } => "db_session_counter",
+            DbError::PutCompileCache { .. } => "db_put_compile_cache",
+            DbError::GetCompileCache { .. } => "db_get_compile_cache",
+            DbError::DeCompileCache { .. } => "db_de_compile_cache",
+            DbError::DeTags { .. } => "db_de_tags",
+            DbError::PutEditToken { .. } => "db_put_edit_token",
+            DbError::Account { .. } => "db_account",
+            DbError::Logs { .. } => "db_logs",
+            DbError::Tests { .. } => "db_tests",
+            DbError::Template { .. } => "db_template",
+            DbError::Saved { .. } => "db_saved",
+            DbError::PurgeSaved { .. } => "db_purge_saved",
+            DbError::Fork { .. } => "db_fork",
+            DbError::SessionRevision { .. } => "db_session_revision",
+            DbError::SearchIndex { .. } => "db_search_index",
+            DbError::Search { .. } => "db_search",
             DbError::NotFound { .. } => "db_row_not_found",
             DbError::QueryRowOther { .. } => "db_row_other_error",
         }
     }
-    pub fn to_response(&self) -> HttpResponse {
-        self.error_response()
-    }
 }
 
 static TABLES: &[&str] = &[
@@ -152,17 +276,34 @@ CREATE TABLE IF NOT EXISTS file (
     name TEXT NOT NULL,
     contents TEXT
 )
+"#,
+    r#"
+CREATE INDEX IF NOT EXISTS file_session_or_saved_id ON file (session_or_saved_id)
 "#,
     r#"
 CREATE TABLE IF NOT EXISTS saved (
     saved_id TEXT PRIMARY KEY,
-    file_kinds TEXT
+    file_kinds TEXT,
+    owner_id TEXT,
+    edit_token_hash TEXT,
+    deleted_at INTEGER,
+    title TEXT NOT NULL DEFAULT '',
+    description TEXT NOT NULL DEFAULT '',
+    tags TEXT NOT NULL DEFAULT '[]',
+    created_at INTEGER,
+    updated_at INTEGER,
+    views INTEGER NOT NULL DEFAULT 0,
+    forked_from TEXT,
+    forks INTEGER NOT NULL DEFAULT 0
 )
 "#,
     r#"
 CREATE TABLE IF NOT EXISTS session (
     session_id TEXT PRIMARY KEY,
-    file_kinds TEXT
+    file_kinds TEXT,
+    edit_token_hash TEXT,
+    forked_from TEXT,
+    revision INTEGER NOT NULL DEFAULT 1
 )
 "#,
     r#"
@@ -176,104 +317,169 @@ CREATE TABLE IF NOT EXISTS session_counter (
     id INTEGER PRIMARY KEY CHECK (id = 0),
     count INTEGER NON NULL
 )
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS compile_cache (
+    session_or_saved_id TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    source_hash TEXT NOT NULL,
+    code TEXT,
+    deps TEXT NOT NULL,
+    PRIMARY KEY (session_or_saved_id, file_name)
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS user (
+    user_id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS login (
+    token_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS api_token (
+    token_id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER
+)
+"#,
+    // Console output captured from frames, see [crate::api::logs]. Saves keep a copy of their
+    // session's last run.
+    r#"
+CREATE TABLE IF NOT EXISTS log_run (
+    session_or_saved_id TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    revision INTEGER,
+    started_at INTEGER NOT NULL,
+    PRIMARY KEY (session_or_saved_id, run_id)
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS log_entry (
+    session_or_saved_id TEXT NOT NULL,
+    run_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    args TEXT NOT NULL,
+    time REAL NOT NULL,
+    PRIMARY KEY (session_or_saved_id, run_id, seq)
+)
+"#,
+    // The newest test run of each session, see [crate::api::tests]
+    r#"
+CREATE TABLE IF NOT EXISTS test_run (
+    session_or_saved_id TEXT PRIMARY KEY,
+    run_id TEXT NOT NULL,
+    source TEXT NOT NULL,
+    revision INTEGER,
+    started_at INTEGER NOT NULL,
+    duration REAL NOT NULL,
+    error TEXT
+)
+"#,
+    r#"
+CREATE TABLE IF NOT EXISTS test_result (
+    session_or_saved_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    suite TEXT NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    duration REAL NOT NULL,
+    failure TEXT,
+    PRIMARY KEY (session_or_saved_id, seq)
+)
+"#,
+    // Templates promoted from saves. Their files are in `file` under [crate::templates::files_id].
+    r#"
+CREATE TABLE IF NOT EXISTS template (
+    name TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    file_kinds TEXT NOT NULL,
+    saved_id TEXT NOT NULL,
+    created_by TEXT,
+    created_at INTEGER NOT NULL
+)
+"#,
+    // Full-text index of saves, kept in sync by [Db::reindex_saved]
+    r#"
+CREATE VIRTUAL TABLE IF NOT EXISTS saved_search USING fts5(
+    title,
+    description,
+    tags,
+    contents,
+    saved_id UNINDEXED,
+    tokenize = 'unicode61'
+)
 "#,
 ];
 
-/// Represents a key in the rocksdb database. Each is serialized to JSON using serde_json.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Key<'a> {
-    /// The single session index cursor
-    SessionCounter,
+/// The columns of a [SavedSummary], for a query on `saved s LEFT JOIN user u`.
+const SAVED_SUMMARY_COLUMNS: &str = "s.saved_id, s.title, s.description, s.tags, s.file_kinds, \
+    u.username AS owner, s.created_at, s.updated_at, s.views, s.forked_from, s.forks, (
+        SELECT json_group_object(f.name, length(CAST(f.contents AS BLOB))) FROM file f
+        WHERE f.session_or_saved_id = s.saved_id
+    ) AS sizes";
 
-    /// A session in the list of current sessions
-    /// Value is a session_id string
-    SessionIndex { index: u32 },
+/// Conditions for a [SavedFilter] on `saved s LEFT JOIN user u`, with named parameters
+/// `:tag`, `:owner`, `:kind` and `:has_parent`. Excludes deleted saves.
+const SAVED_FILTER_SQL: &str = "s.deleted_at IS NULL
+    AND (:tag IS NULL OR EXISTS (SELECT 1 FROM json_each(s.tags) WHERE value = :tag))
+    AND (:owner IS NULL OR u.username = :owner)
+    AND (:kind IS NULL OR EXISTS (SELECT 1 FROM json_each(s.file_kinds) WHERE value = :kind))
+    AND (:has_parent IS NULL OR (s.forked_from IS NOT NULL) = :has_parent)";
 
-    /// A session (containing all relevant data). These can be recycled when SessionIndex wraps around.
-    Session { session_id: Cow<'a, str> },
+/// Columns added to a table after it was created, as (table, column, definition). These are
+/// added to existing databases by [Db::create_tables], and should also be in [TABLES].
+static COLUMNS: &[(&str, &str, &str)] = &[
+    ("session", "edit_token_hash", "TEXT"),
+    ("session", "forked_from", "TEXT"),
+    ("session", "revision", "INTEGER NOT NULL DEFAULT 1"),
+    ("saved", "owner_id", "TEXT"),
+    ("saved", "edit_token_hash", "TEXT"),
+    ("saved", "deleted_at", "INTEGER"),
+    ("saved", "title", "TEXT NOT NULL DEFAULT ''"),
+    ("saved", "description", "TEXT NOT NULL DEFAULT ''"),
+    ("saved", "tags", "TEXT NOT NULL DEFAULT '[]'"),
+    ("saved", "created_at", "INTEGER"),
+    ("saved", "updated_at", "INTEGER"),
+    ("saved", "views", "INTEGER NOT NULL DEFAULT 0"),
+    ("saved", "forked_from", "TEXT"),
+    ("saved", "forks", "INTEGER NOT NULL DEFAULT 0"),
+];
 
-    /// A file in a session or saved
-    File {
-        session_or_saved_id: Cow<'a, str>,
-        name: Cow<'a, str>,
+/// The files [Db::write_session_files] writes.
+#[derive(Debug)]
+pub enum FileWrites {
+    /// (file name, contents) of every file, and the session's file kinds if they changed
+    Full {
+        files: Vec<(String, String)>,
+        file_kinds: Option<Vec<FileKind>>,
     },
-
-    /// A persistently saved snapshot of a session
-    Saved { id: Cow<'a, str> },
-    //
-    // /// An IPv4 or IPv6 address of a user.
-    // IpSession {ip_address:String},
-}
-
-impl<'a> Key<'a> {
-    fn render(&self) -> KeyRendered {
-        KeyRendered {
-            json: self.render_key(),
-        }
-    }
-
-    pub fn session(session_id: &'a str) -> Self {
-        Self::Session {
-            session_id: Cow::Borrowed(session_id),
-        }
-    }
-
-    pub fn file(id: &'a str, name: &'a str) -> Self {
-        Self::File {
-            session_or_saved_id: Cow::Borrowed(id),
-            name: Cow::Borrowed(name),
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct KeyRendered<'a> {
-    json: Cow<'a, [u8]>,
-}
-
-pub trait KeyLike: Debug {
-    fn render_key(&self) -> Cow<'_, [u8]>;
-
-    fn dbg(&self) -> String {
-        format!("{:?}", String::from_utf8_lossy(&self.render_key()))
-    }
-}
-
-impl KeyLike for Key<'_> {
-    fn render_key(&self) -> Cow<'_, [u8]> {
-        use std::io::Write;
-        let mut out = vec![];
-
-        match self {
-            Key::SessionCounter => {
-                write!(&mut out, "SessionCounter").unwrap();
-            }
-            Key::SessionIndex { index } => {
-                write!(&mut out, "SessionIndex::").unwrap();
-                out.write_all(&index.to_be_bytes()).unwrap();
-            }
-            Key::Session { session_id } => {
-                write!(&mut out, "Session::{}", session_id).unwrap();
-            }
-            Key::File {
-                session_or_saved_id,
-                name,
-            } => {
-                write!(&mut out, "File::{}::{}", session_or_saved_id, name).unwrap();
-            }
-            Key::Saved { id } => {
-                write!(&mut out, "Saved::{}", id).unwrap();
-            }
-        }
-
-        out.into()
-    }
+    /// (file name, expected current contents, new contents) of the changed files
+    Replace(Vec<(String, String, String)>),
 }
 
-impl KeyLike for KeyRendered<'_> {
-    fn render_key(&self) -> Cow<'_, [u8]> {
-        Cow::Borrowed(&self.json)
-    }
+/// What came of [Db::write_session_files].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionWrite {
+    /// The files were written as this revision
+    Written(i64),
+    /// The session isn't at the expected revision, or doesn't exist
+    RevisionConflict,
+    /// The file at this index of [FileWrites::Replace] was changed or removed in the meantime
+    FileConflict(usize),
 }
 
 /// An open rocksdb database.
@@ -318,10 +524,10 @@ impl Db {
             })
     }
 
-    /// Creates all tables if they don't already exist
+    /// Creates all tables if they don't already exist, and adds any missing [COLUMNS]
     pub async fn create_tables(self) -> DbResult<Self> {
         let self2 = block(move || {
-            for create_table in TABLES.into_iter().copied() {
+            for create_table in TABLES.iter().copied() {
                 self.db
                     .execute(create_table, [])
                     .map_err(|source| DbError::CreateTable {
@@ -329,6 +535,46 @@ impl Db {
                         source,
                     })?;
             }
+
+            for &(table, column, definition) in COLUMNS {
+                let add_column_err = |source| DbError::AddColumn {
+                    source,
+                    table,
+                    column,
+                };
+                let exists: bool = self
+                    .db
+                    .query_row(
+                        r#"SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2"#,
+                        params![table, column],
+                        |row| row.get(0),
+                    )
+                    .map_err(add_column_err)?;
+                if !exists {
+                    self.db
+                        .execute(
+                            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                            [],
+                        )
+                        .map_err(add_column_err)?;
+                }
+            }
+
+            // Index saves made before the search index existed
+            self.db
+                .execute(
+                    r#"INSERT INTO saved_search (title, description, tags, contents, saved_id)
+                        SELECT s.title, s.description, s.tags, (
+                            SELECT COALESCE(group_concat(f.contents, char(10)), '') FROM file f
+                            WHERE f.session_or_saved_id = s.saved_id
+                        ), s.saved_id
+                        FROM saved s
+                        WHERE s.deleted_at IS NULL
+                            AND s.saved_id NOT IN (SELECT saved_id FROM saved_search)"#,
+                    [],
+                )
+                .map_err(|source| DbError::SearchIndex { source })?;
+
             Ok(self)
         })
         .await?;
@@ -336,6 +582,26 @@ impl Db {
         Ok(self2)
     }
 
+    /// Replace the search index entry of a save with its current details and files, or remove
+    /// it if the save was deleted.
+    fn reindex_saved(db: &Connection, saved_id: &str) -> rusqlite::Result<()> {
+        db.execute(
+            r#"DELETE FROM saved_search WHERE saved_id = ?"#,
+            params![saved_id],
+        )?;
+        db.execute(
+            r#"INSERT INTO saved_search (title, description, tags, contents, saved_id)
+                SELECT s.title, s.description, s.tags, (
+                    SELECT COALESCE(group_concat(f.contents, char(10)), '') FROM file f
+                    WHERE f.session_or_saved_id = s.saved_id
+                ), s.saved_id
+                FROM saved s
+                WHERE s.saved_id = ? AND s.deleted_at IS NULL"#,
+            params![saved_id],
+        )?;
+        Ok(())
+    }
+
     /// Store an entry in the 'file' table, associated with a 'saved' or 'session'.
     pub async fn put_file(
         self,
@@ -374,8 +640,9 @@ impl Db {
         let self2 = block(move || {
             self.db
                 .execute(
-                    r#"INSERT INTO saved (saved_id, file_kinds) VALUES (?1, ?2) ON CONFLICT(saved_id) DO UPDATE SET file_kinds=?2"#,
-                    params![saved_id, file_kinds],
+                    r#"INSERT INTO saved (saved_id, file_kinds, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)
+                        ON CONFLICT(saved_id) DO UPDATE SET file_kinds=?2, updated_at=?3"#,
+                    params![saved_id, file_kinds, unix_now()],
                 )
                 .map(|_| self)
                 .map_err(|source| DbError::PutSaved {
@@ -413,6 +680,70 @@ impl Db {
         Ok(self2)
     }
 
+    /// Set the hash of the session's edit token, see [crate::hash::sha1_hex].
+    pub async fn put_edit_token_hash(self, session_id: &str, hash: &str) -> DbResult<Self> {
+        let session_id = session_id.to_owned();
+        let hash = hash.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE session SET edit_token_hash = ?2 WHERE session_id = ?1"#,
+                    params![session_id, hash],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::PutEditToken { source, session_id })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The hash of the session's edit token. None for sessions created before edit tokens,
+    /// which are read-only.
+    pub async fn get_edit_token_hash(self, session_id: &str) -> DbResult<(Self, Option<String>)> {
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            self.query_row(
+                r#"SELECT edit_token_hash FROM session WHERE session_id = ?"#,
+                params![session_id],
+                |row| row.get(0),
+            )
+            .map_err(|source| DbError::GetSession {
+                source: Box::new(source),
+                session_id,
+            })
+            .map(|hash| (self, hash))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Set the hash of the token that authorizes deleting a save.
+    pub async fn put_saved_edit_token_hash(self, saved_id: &str, hash: &str) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+        let hash = hash.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE saved SET edit_token_hash = ?2 WHERE saved_id = ?1"#,
+                    params![saved_id, hash],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Saved {
+                    source,
+                    saved_id,
+                    action: "put edit token",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
     /// Store an entry in the 'session_index' table.
     pub async fn put_session_index(self, index: u32, session_id: &str) -> DbResult<Self> {
         let session_id = session_id.to_owned();
@@ -426,7 +757,7 @@ impl Db {
                 .map(|_| self)
                 .map_err(|source| DbError::PutSessionIndex {
                     source,
-                    session_id: session_id,
+                    session_id,
                 })
         })
         .await?;
@@ -509,39 +840,1696 @@ impl Db {
         Ok(self2)
     }
 
-    pub async fn incr_session_counter(self, max_value: u32) -> DbResult<(Self, u32)> {
-        static SESSION_LOCK: once_cell::sync::Lazy<Mutex<()>> =
-            once_cell::sync::Lazy::new(|| Default::default());
+    /// The owner, edit token and deletion state of a save. [DbError::NotFound] if it never
+    /// existed.
+    pub async fn get_saved_info(self, saved_id: &str) -> DbResult<(Self, SavedInfo)> {
+        let saved_id = saved_id.to_owned();
 
-        let r =        block(move ||{
-            let _lock = SESSION_LOCK.lock().expect("session_counter_lock should never be poisoned");
+        let self2 = block(move || {
+            self.query_row(
+                r#"SELECT owner_id, edit_token_hash, created_at, updated_at, deleted_at FROM saved WHERE saved_id = ?"#,
+                params![saved_id],
+                |row| {
+                    Ok(SavedInfo {
+                        owner_id: row.get(0)?,
+                        edit_token_hash: row.get(1)?,
+                        created_at: row.get(2)?,
+                        updated_at: row.get(3)?,
+                        deleted_at: row.get(4)?,
+                    })
+                },
+            )
+            .map(|info| (self, info))
+        })
+        .await?;
 
-            let current: u32 = self.db.query_row(
-                r#"SELECT count FROM session_counter LIMIT 1"#,
-                [],
-                |row| row.get(0),
-            ).ok().unwrap_or(0);
-            let next = current.wrapping_add(1) % max_value;
-            println!("Session counter current: {}, next: {}", current, next);
+        Ok(self2)
+    }
+
+    /// Set the title, description and tags of a save, and index it for search with its files.
+    /// Saves are written with this last, once their files are stored.
+    pub async fn put_saved_details(self, saved_id: &str, details: SavedDetails) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+        let tags = serde_json::to_string(&details.tags).expect("ject: tags to json");
+
+        let self2 = block(move || {
             self.db
                 .execute(
-                    r#"INSERT INTO session_counter (id, count) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET count = ?1"#,
-                    params![next],
-                )
-                .map_err(|source| DbError::SessionCounter { source, action: "upsert" } )?;
-            let deleted = self.db
-                .execute(
-                    r#"DELETE FROM file WHERE file_id IN (
-                            SELECT file_id FROM file a INNER JOIN session_index b ON (
-                                b.session_id = a.session_or_saved_id
-                            ) WHERE b.idx = ?
-                        )"#,
-                    [next],
+                    r#"UPDATE saved SET title = ?2, description = ?3, tags = ?4, updated_at = ?5 WHERE saved_id = ?1"#,
+                    params![saved_id, details.title, details.description, tags, unix_now()],
                 )
-                .map_err(|source| DbError::SessionCounter { source, action: "delete" } )?;
-            println!("Deleted {} row(s) for old session with same index", deleted);
-            Ok((self, next))
-        }).await?;
-        Ok(r)
+                .and_then(|_| Self::reindex_saved(&self.db, &saved_id))
+                .map(|_| self)
+                .map_err(|source| DbError::Saved {
+                    source,
+                    saved_id,
+                    action: "put details",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn get_saved_details(self, saved_id: &str) -> DbResult<(Self, SavedDetails)> {
+        let saved_id = saved_id.to_owned();
+
+        let self2 = block(move || {
+            let (title, description, tags): (String, String, String) = self.query_row(
+                r#"SELECT title, description, tags FROM saved WHERE saved_id = ?"#,
+                params![saved_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            let tags = serde_json::from_str(&tags).map_err(|source| DbError::DeTags { source })?;
+
+            Ok((
+                self,
+                SavedDetails {
+                    title,
+                    description,
+                    tags,
+                },
+            ))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Read a JSON column, reporting parse errors like any other column conversion.
+    fn json_column<T: serde::de::DeserializeOwned>(
+        row: &Row<'_>,
+        idx: &str,
+    ) -> rusqlite::Result<T> {
+        let json: String = row.get(idx)?;
+        serde_json::from_str(&json).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+        })
+    }
+
+    /// Expects the columns selected by [SAVED_SUMMARY_COLUMNS].
+    fn saved_summary_from_row(row: &Row<'_>) -> rusqlite::Result<SavedSummary> {
+        Ok(SavedSummary {
+            save_id: row.get("saved_id")?,
+            details: SavedDetails {
+                title: row.get("title")?,
+                description: row.get("description")?,
+                tags: Self::json_column(row, "tags")?,
+            },
+            file_kinds: Self::json_column(row, "file_kinds")?,
+            owner: row.get("owner")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+            views: row.get("views")?,
+            forked_from: row.get("forked_from")?,
+            forks: row.get("forks")?,
+            sizes: match row.get::<_, Option<String>>("sizes")? {
+                Some(_) => Self::json_column(row, "sizes")?,
+                None => Default::default(),
+            },
+        })
+    }
+
+    /// Full-text search of undeleted saves, best matches first. `query` is in FTS5 syntax, see
+    /// [crate::api::search]. Highlights are delimited by `\u{1}` and `\u{2}`.
+    pub async fn search_saved(
+        self,
+        query: &str,
+        filter: SavedFilter,
+        limit: u32,
+        offset: u32,
+    ) -> DbResult<(Self, Vec<SearchHit>)> {
+        let query = query.to_owned();
+        let kind = filter.file_kind.map(FileKind::to_variant_name);
+
+        let self2 = block(move || {
+            let sql = format!(
+                r#"SELECT {},
+                    highlight(saved_search, 0, char(1), char(2)) AS title_highlight,
+                    snippet(saved_search, 3, char(1), char(2), '…', 16) AS snippet
+                FROM saved_search
+                INNER JOIN saved s ON (s.saved_id = saved_search.saved_id)
+                LEFT JOIN user u ON (u.user_id = s.owner_id)
+                WHERE saved_search MATCH :query AND {}
+                ORDER BY bm25(saved_search, 10.0, 5.0, 5.0, 1.0)
+                LIMIT :limit OFFSET :offset"#,
+                SAVED_SUMMARY_COLUMNS, SAVED_FILTER_SQL
+            );
+            let hits = self
+                .db
+                .prepare(&sql)
+                .and_then(|mut stmt| {
+                    stmt.query_map(
+                        named_params! {
+                            ":query": query,
+                            ":tag": filter.tag,
+                            ":owner": filter.owner,
+                            ":kind": kind,
+                            ":has_parent": filter.has_parent,
+                            ":limit": limit,
+                            ":offset": offset,
+                        },
+                        |row| {
+                            Ok(SearchHit {
+                                saved: Self::saved_summary_from_row(row)?,
+                                title_highlight: row.get("title_highlight")?,
+                                snippet: row.get("snippet")?,
+                            })
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .map_err(|source| DbError::Search { source })?;
+
+            Ok((self, hits))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// A page of undeleted saves in the given order, each with the cursor to continue after it.
+    pub async fn list_saved(
+        self,
+        filter: SavedFilter,
+        sort: SavedSort,
+        after: Option<SavedCursor>,
+        limit: u32,
+    ) -> DbResult<(Self, Vec<(SavedSummary, SavedCursor)>)> {
+        let kind = filter.file_kind.map(FileKind::to_variant_name);
+        let sort_key = match sort {
+            SavedSort::Newest => "COALESCE(s.created_at, 0)",
+            SavedSort::Updated => "COALESCE(s.updated_at, s.created_at, 0)",
+            SavedSort::Views => "s.views",
+        };
+
+        let self2 = block(move || {
+            // Keyset pagination, with the rowid breaking ties
+            let sql = format!(
+                r#"SELECT {columns}, {key} AS sort_key, s.rowid AS row_id
+                FROM saved s
+                LEFT JOIN user u ON (u.user_id = s.owner_id)
+                WHERE {filter}
+                    AND (:after_key IS NULL OR {key} < :after_key
+                        OR ({key} = :after_key AND s.rowid < :after_rowid))
+                ORDER BY sort_key DESC, s.rowid DESC
+                LIMIT :limit"#,
+                columns = SAVED_SUMMARY_COLUMNS,
+                key = sort_key,
+                filter = SAVED_FILTER_SQL,
+            );
+            let saves = self
+                .db
+                .prepare(&sql)
+                .and_then(|mut stmt| {
+                    stmt.query_map(
+                        named_params! {
+                            ":tag": filter.tag,
+                            ":owner": filter.owner,
+                            ":kind": kind,
+                            ":has_parent": filter.has_parent,
+                            ":after_key": after.map(|c| c.key),
+                            ":after_rowid": after.map(|c| c.rowid),
+                            ":limit": limit,
+                        },
+                        |row| {
+                            let cursor = SavedCursor {
+                                sort,
+                                key: row.get("sort_key")?,
+                                rowid: row.get("row_id")?,
+                            };
+                            Ok((Self::saved_summary_from_row(row)?, cursor))
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .map_err(|source| DbError::Search { source })?;
+
+            Ok((self, saves))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Count a view of a save.
+    pub async fn incr_saved_views(self, saved_id: &str) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE saved SET views = views + 1 WHERE saved_id = ?"#,
+                    params![saved_id],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Saved {
+                    source,
+                    saved_id,
+                    action: "count view",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Mark a save as deleted. The row is kept as a tombstone, while its files are removed
+    /// later by [Db::purge_deleted_saves].
+    pub async fn delete_saved(self, saved_id: &str) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE saved SET deleted_at = ?2 WHERE saved_id = ?1 AND deleted_at IS NULL"#,
+                    params![saved_id, unix_now()],
+                )
+                .and_then(|_| Self::reindex_saved(&self.db, &saved_id))
+                .map(|_| self)
+                .map_err(|source| DbError::Saved {
+                    source,
+                    saved_id,
+                    action: "delete",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Copy the files of an undeleted save into a new session with the given edit token hash,
+    /// recording where it came from and counting the fork on the save. Returns None, changing
+    /// nothing, if the save doesn't exist or was deleted.
+    pub async fn fork_saved(
+        mut self,
+        saved_id: &str,
+        session_id: &str,
+        edit_token_hash: &str,
+    ) -> DbResult<(Self, Option<SessionMeta>)> {
+        let saved_id = saved_id.to_owned();
+        let session_id = session_id.to_owned();
+        let edit_token_hash = edit_token_hash.to_owned();
+
+        let self2 = block(move || {
+            let forked = (|| {
+                let tx = self.db.transaction()?;
+                let file_kinds: Option<String> = tx
+                    .query_row(
+                        r#"SELECT file_kinds FROM saved WHERE saved_id = ? AND deleted_at IS NULL"#,
+                        params![saved_id],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let file_kinds = match file_kinds {
+                    Some(file_kinds) => file_kinds,
+                    // Dropping the transaction rolls it back
+                    None => return Ok(None),
+                };
+
+                tx.execute(
+                    r#"INSERT INTO session (session_id, file_kinds, edit_token_hash, forked_from) VALUES (?1, ?2, ?3, ?4)"#,
+                    params![session_id, file_kinds, edit_token_hash, saved_id],
+                )?;
+                tx.execute(
+                    r#"INSERT INTO file (file_id, session_or_saved_id, name, contents)
+                        SELECT ?2 || '::' || name, ?2, name, contents FROM file
+                        WHERE session_or_saved_id = ?1"#,
+                    params![saved_id, session_id],
+                )?;
+                tx.execute(
+                    r#"UPDATE saved SET forks = forks + 1 WHERE saved_id = ?"#,
+                    params![saved_id],
+                )?;
+                tx.commit()?;
+                Ok(Some(file_kinds))
+            })()
+            .map_err(|source| DbError::Fork {
+                source,
+                saved_id,
+                session_id,
+            })?;
+
+            let meta = forked.map(Self::parse_meta).transpose()?;
+            Ok((self, meta))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Write files of a session as its next revision. With `expected`, only if the session
+    /// is still at that revision. The check, the revision and the files are one transaction,
+    /// so nothing is written on a conflict.
+    pub async fn write_session_files(
+        mut self,
+        session_id: &str,
+        expected: Option<i64>,
+        writes: FileWrites,
+    ) -> DbResult<(Self, SessionWrite)> {
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            let written = (|| {
+                let tx = self.db.transaction()?;
+                let revision: Option<i64> = tx
+                    .query_row(
+                        r#"UPDATE session SET revision = revision + 1
+                            WHERE session_id = ?1 AND (?2 IS NULL OR revision = ?2)
+                            RETURNING revision"#,
+                        params![session_id, expected],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let revision = match revision {
+                    Some(revision) => revision,
+                    None => return Ok(SessionWrite::RevisionConflict),
+                };
+
+                match &writes {
+                    FileWrites::Full { files, file_kinds } => {
+                        for (file_name, contents) in files {
+                            let file_id = format!("{}::{}", session_id, file_name);
+                            tx.execute(
+                                r#"INSERT INTO file (file_id, session_or_saved_id, name, contents) VALUES (?1, ?2, ?3, ?4)
+                                    ON CONFLICT(file_id) DO UPDATE SET contents=?4"#,
+                                params![file_id, session_id, file_name, contents],
+                            )?;
+                        }
+                        if let Some(file_kinds) = file_kinds {
+                            let file_kinds = serde_json::to_string(file_kinds)
+                                .expect("ject: SessionMeta to json");
+                            tx.execute(
+                                r#"UPDATE session SET file_kinds = ?2 WHERE session_id = ?1"#,
+                                params![session_id, file_kinds],
+                            )?;
+                        }
+                    }
+                    FileWrites::Replace(files) => {
+                        for (index, (file_name, expected, contents)) in files.iter().enumerate() {
+                            let changed = tx.execute(
+                                r#"UPDATE file SET contents = ?3
+                                    WHERE session_or_saved_id = ?1 AND name = ?2 AND contents = ?4"#,
+                                params![session_id, file_name, contents, expected],
+                            )?;
+                            if changed == 0 {
+                                // Dropping the transaction rolls it back, revision included
+                                return Ok(SessionWrite::FileConflict(index));
+                            }
+                        }
+                    }
+                }
+                tx.commit()?;
+                Ok(SessionWrite::Written(revision))
+            })()
+            .map_err(|source| DbError::SessionRevision { source, session_id })?;
+
+            Ok((self, written))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The current revision of a session, see [Db::write_session_files].
+    pub async fn get_session_revision(self, session_id: &str) -> DbResult<(Self, i64)> {
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            self.query_row(
+                r#"SELECT revision FROM session WHERE session_id = ?"#,
+                params![session_id],
+                |row| row.get(0),
+            )
+            .map_err(|source| DbError::GetSession {
+                source: Box::new(source),
+                session_id,
+            })
+            .map(|revision| (self, revision))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The save a session was forked from, if any.
+    pub async fn get_session_forked_from(
+        self,
+        session_id: &str,
+    ) -> DbResult<(Self, Option<String>)> {
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            self.query_row(
+                r#"SELECT forked_from FROM session WHERE session_id = ?"#,
+                params![session_id],
+                |row| row.get(0),
+            )
+            .map_err(|source| DbError::GetSession {
+                source: Box::new(source),
+                session_id,
+            })
+            .map(|forked_from| (self, forked_from))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Carry the `forked_from` of the session a save was made from over to the save.
+    pub async fn put_saved_forked_from_session(
+        self,
+        saved_id: &str,
+        session_id: &str,
+    ) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE saved SET forked_from = (
+                            SELECT forked_from FROM session WHERE session_id = ?2
+                        ) WHERE saved_id = ?1"#,
+                    params![saved_id, session_id],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Saved {
+                    source,
+                    saved_id,
+                    action: "put forked_from",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Remove the files and compile cache of saves deleted before the given time. Returns the
+    /// number of file rows removed.
+    pub async fn purge_deleted_saves(mut self, deleted_before: i64) -> DbResult<(Self, usize)> {
+        let self2 = block(move || {
+            let tx = self
+                .db
+                .transaction()
+                .map_err(|source| DbError::PurgeSaved { source })?;
+            let deleted = tx
+                .execute(
+                    r#"DELETE FROM file WHERE session_or_saved_id IN (
+                            SELECT saved_id FROM saved WHERE deleted_at <= ?
+                        )"#,
+                    params![deleted_before],
+                )
+                .and_then(|deleted| {
+                    for table in &["compile_cache", "log_entry", "log_run"] {
+                        tx.execute(
+                            &format!(
+                                r#"DELETE FROM {} WHERE session_or_saved_id IN (
+                                    SELECT saved_id FROM saved WHERE deleted_at <= ?
+                                )"#,
+                                table
+                            ),
+                            params![deleted_before],
+                        )?;
+                    }
+                    tx.commit()?;
+                    Ok(deleted)
+                })
+                .map_err(|source| DbError::PurgeSaved { source })?;
+
+            Ok((self, deleted))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Store the compile output/deps for the current source of a file, replacing any previous
+    /// entry.
+    pub async fn put_compile_cache(
+        self,
+        session_or_saved_id: &str,
+        file_name: &str,
+        cache: CompileCache,
+    ) -> DbResult<Self> {
+        let session_or_saved_id = session_or_saved_id.to_owned();
+        let file_name = file_name.to_owned();
+        let deps = serde_json::to_string(&cache.deps).expect("ject: DetectedDeps to json");
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"INSERT INTO compile_cache (session_or_saved_id, file_name, source_hash, code, deps) VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT(session_or_saved_id, file_name) DO UPDATE SET source_hash=?3, code=?4, deps=?5"#,
+                    params![session_or_saved_id, file_name, cache.source_hash, cache.code, deps],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::PutCompileCache {
+                    source,
+                    session_or_saved_id,
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn get_compile_cache(
+        self,
+        session_or_saved_id: &str,
+        file_name: &str,
+    ) -> DbResult<(Self, Option<CompileCache>)> {
+        let session_or_saved_id = session_or_saved_id.to_owned();
+        let file_name = file_name.to_owned();
+
+        let self2 = block(move || {
+            let row: Option<(String, Option<String>, String)> = self
+                .db
+                .query_row(
+                    r#"SELECT source_hash, code, deps FROM compile_cache WHERE session_or_saved_id = ?1 AND file_name = ?2"#,
+                    params![session_or_saved_id, file_name],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+                .map_err(|source| DbError::GetCompileCache {
+                    source,
+                    session_or_saved_id,
+                })?;
+
+            let cache = match row {
+                Some((source_hash, code, deps)) => Some(CompileCache {
+                    source_hash,
+                    code,
+                    deps: serde_json::from_str(&deps)
+                        .map_err(|source| DbError::DeCompileCache { source })?,
+                }),
+                None => None,
+            };
+
+            Ok((self, cache))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn incr_session_counter(self, max_value: u32) -> DbResult<(Self, u32)> {
+        static SESSION_LOCK: once_cell::sync::Lazy<Mutex<()>> =
+            once_cell::sync::Lazy::new(Default::default);
+
+        let r =        block(move ||{
+            let _lock = SESSION_LOCK.lock().expect("session_counter_lock should never be poisoned");
+
+            let current: u32 = self.db.query_row(
+                r#"SELECT count FROM session_counter LIMIT 1"#,
+                [],
+                |row| row.get(0),
+            ).ok().unwrap_or(0);
+            let next = current.wrapping_add(1) % max_value;
+            println!("Session counter current: {}, next: {}", current, next);
+            self.db
+                .execute(
+                    r#"INSERT INTO session_counter (id, count) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET count = ?1"#,
+                    params![next],
+                )
+                .map_err(|source| DbError::SessionCounter { source, action: "upsert" } )?;
+            let deleted = self.db
+                .execute(
+                    r#"DELETE FROM file WHERE file_id IN (
+                            SELECT file_id FROM file a INNER JOIN session_index b ON (
+                                b.session_id = a.session_or_saved_id
+                            ) WHERE b.idx = ?
+                        )"#,
+                    [next],
+                )
+                .map_err(|source| DbError::SessionCounter { source, action: "delete" } )?;
+            self.db
+                .execute(
+                    r#"DELETE FROM compile_cache WHERE session_or_saved_id IN (
+                            SELECT session_id FROM session_index WHERE idx = ?
+                        )"#,
+                    [next],
+                )
+                .map_err(|source| DbError::SessionCounter { source, action: "delete compile_cache" } )?;
+            for table in &["log_entry", "log_run", "test_result", "test_run"] {
+                self.db
+                    .execute(
+                        &format!(
+                            r#"DELETE FROM {} WHERE session_or_saved_id IN (
+                                SELECT session_id FROM session_index WHERE idx = ?
+                            )"#,
+                            table
+                        ),
+                        [next],
+                    )
+                    .map_err(|source| DbError::SessionCounter { source, action: "delete logs" } )?;
+            }
+            // Along with its edit token, so the evicted id no longer answers as a session
+            self.db
+                .execute(
+                    r#"DELETE FROM session WHERE session_id IN (
+                            SELECT session_id FROM session_index WHERE idx = ?
+                        )"#,
+                    [next],
+                )
+                .map_err(|source| DbError::SessionCounter { source, action: "delete session" } )?;
+            println!("Deleted {} row(s) for old session with same index", deleted);
+            Ok((self, next))
+        }).await?;
+        Ok(r)
+    }
+}
+
+/// Accounts, see [crate::api::account]. Secrets are only stored as hashes.
+impl Db {
+    fn user_from_row(row: &Row<'_>) -> rusqlite::Result<User> {
+        Ok(User {
+            user_id: row.get("user_id")?,
+            username: row.get("username")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+
+    /// Create a user. Returns false if the username is taken.
+    pub async fn put_user(self, user: User, password_hash: String) -> DbResult<(Self, bool)> {
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"INSERT INTO user (user_id, username, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)
+                        ON CONFLICT(username) DO NOTHING"#,
+                    params![user.user_id, user.username, password_hash, user.created_at],
+                )
+                .map(|inserted| (self, inserted > 0))
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "put user",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The user and their password hash, see [crate::password].
+    pub async fn get_user_password(
+        self,
+        username: &str,
+    ) -> DbResult<(Self, Option<(User, String)>)> {
+        let username = username.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .query_row(
+                    r#"SELECT user_id, username, created_at, password_hash FROM user WHERE username = ?"#,
+                    params![username],
+                    |row| Ok((Self::user_from_row(row)?, row.get("password_hash")?)),
+                )
+                .optional()
+                .map(|user| (self, user))
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "get user",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Store a browser login, and clean up expired ones.
+    pub async fn put_login(
+        self,
+        token_hash: &str,
+        user_id: &str,
+        expires_at: i64,
+    ) -> DbResult<Self> {
+        let token_hash = token_hash.to_owned();
+        let user_id = user_id.to_owned();
+
+        let self2 = block(move || {
+            let now = unix_now();
+            self.db
+                .execute(r#"DELETE FROM login WHERE expires_at <= ?"#, params![now])
+                .and_then(|_| {
+                    self.db.execute(
+                        r#"INSERT INTO login (token_hash, user_id, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)"#,
+                        params![token_hash, user_id, now, expires_at],
+                    )
+                })
+                .map(|_| self)
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "put login",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The user of an unexpired browser login.
+    pub async fn get_login_user(self, token_hash: &str) -> DbResult<(Self, Option<User>)> {
+        let token_hash = token_hash.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .query_row(
+                    r#"SELECT u.user_id, u.username, u.created_at FROM login l
+                        INNER JOIN user u ON (u.user_id = l.user_id)
+                        WHERE l.token_hash = ? AND l.expires_at > ?"#,
+                    params![token_hash, unix_now()],
+                    Self::user_from_row,
+                )
+                .optional()
+                .map(|user| (self, user))
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "get login",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn delete_login(self, token_hash: &str) -> DbResult<Self> {
+        let token_hash = token_hash.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"DELETE FROM login WHERE token_hash = ?"#,
+                    params![token_hash],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "delete login",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn put_api_token(
+        self,
+        user_id: &str,
+        token: ApiToken,
+        token_hash: &str,
+    ) -> DbResult<Self> {
+        let user_id = user_id.to_owned();
+        let token_hash = token_hash.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"INSERT INTO api_token (token_id, token_hash, user_id, name, created_at) VALUES (?1, ?2, ?3, ?4, ?5)"#,
+                    params![token.token_id, token_hash, user_id, token.name, token.created_at],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "put api token",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The owner of an API token, recording that the token was used.
+    pub async fn get_api_token_user(self, token_hash: &str) -> DbResult<(Self, Option<User>)> {
+        let token_hash = token_hash.to_owned();
+
+        let self2 = block(move || {
+            let account_err = |source| DbError::Account {
+                source,
+                action: "get api token",
+            };
+            let user = self
+                .db
+                .query_row(
+                    r#"SELECT u.user_id, u.username, u.created_at FROM api_token t
+                        INNER JOIN user u ON (u.user_id = t.user_id)
+                        WHERE t.token_hash = ?"#,
+                    params![token_hash],
+                    Self::user_from_row,
+                )
+                .optional()
+                .map_err(account_err)?;
+
+            if user.is_some() {
+                self.db
+                    .execute(
+                        r#"UPDATE api_token SET last_used_at = ?2 WHERE token_hash = ?1"#,
+                        params![token_hash, unix_now()],
+                    )
+                    .map_err(account_err)?;
+            }
+
+            Ok((self, user))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn get_api_tokens(self, user_id: &str) -> DbResult<(Self, Vec<ApiToken>)> {
+        let user_id = user_id.to_owned();
+
+        let self2 = block(move || {
+            let tokens = self
+                .db
+                .prepare(
+                    r#"SELECT token_id, name, created_at, last_used_at FROM api_token
+                        WHERE user_id = ? ORDER BY created_at"#,
+                )
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![user_id], |row| {
+                        Ok(ApiToken {
+                            token_id: row.get(0)?,
+                            name: row.get(1)?,
+                            created_at: row.get(2)?,
+                            last_used_at: row.get(3)?,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "get api tokens",
+                })?;
+
+            Ok((self, tokens))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Revoke one of the user's API tokens. Returns false if there was no such token.
+    pub async fn delete_api_token(self, user_id: &str, token_id: &str) -> DbResult<(Self, bool)> {
+        let user_id = user_id.to_owned();
+        let token_id = token_id.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"DELETE FROM api_token WHERE user_id = ? AND token_id = ?"#,
+                    params![user_id, token_id],
+                )
+                .map(|deleted| (self, deleted > 0))
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "delete api token",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn put_saved_owner(self, saved_id: &str, user_id: &str) -> DbResult<Self> {
+        let saved_id = saved_id.to_owned();
+        let user_id = user_id.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .execute(
+                    r#"UPDATE saved SET owner_id = ?2 WHERE saved_id = ?1"#,
+                    params![saved_id, user_id],
+                )
+                .map(|_| self)
+                .map_err(|source| DbError::Account {
+                    source,
+                    action: "put saved owner",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+}
+
+/// Console output captured from frames, see [crate::api::logs].
+impl Db {
+    /// Store a batch of a run's entries, starting the run if it's new. Only the newest
+    /// `keep_runs` runs of a session are kept, with at most `max_entries` each. Returns how
+    /// many entries were stored; ones already stored (by `seq`) are ignored.
+    pub async fn put_log_entries(
+        mut self,
+        session_id: &str,
+        run_id: &str,
+        started_at: i64,
+        entries: Vec<LogEntry>,
+        max_entries: usize,
+        keep_runs: usize,
+    ) -> DbResult<(Self, usize)> {
+        let session_id = session_id.to_owned();
+        let run_id = run_id.to_owned();
+
+        let self2 = block(move || {
+            let stored = (|| {
+                let tx = self.db.transaction()?;
+                let started = tx.execute(
+                    r#"INSERT OR IGNORE INTO log_run (session_or_saved_id, run_id, revision, started_at)
+                        VALUES (?1, ?2, (SELECT revision FROM session WHERE session_id = ?1), ?3)"#,
+                    params![session_id, run_id, started_at],
+                )? == 1;
+                if started {
+                    for table in &["log_entry", "log_run"] {
+                        tx.execute(
+                            &format!(
+                                r#"DELETE FROM {} WHERE session_or_saved_id = ?1 AND run_id IN (
+                                    SELECT run_id FROM log_run WHERE session_or_saved_id = ?1
+                                    ORDER BY started_at DESC, rowid DESC LIMIT -1 OFFSET ?2
+                                )"#,
+                                table
+                            ),
+                            params![session_id, keep_runs as i64],
+                        )?;
+                    }
+                }
+
+                let existing: i64 = tx.query_row(
+                    r#"SELECT count(*) FROM log_entry WHERE session_or_saved_id = ? AND run_id = ?"#,
+                    params![session_id, run_id],
+                    |row| row.get(0),
+                )?;
+                let room = max_entries.saturating_sub(existing as usize);
+                let mut stored = 0;
+                {
+                    let mut insert = tx.prepare(
+                        r#"INSERT OR IGNORE INTO log_entry (session_or_saved_id, run_id, seq, method, args, time)
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+                    )?;
+                    for entry in &entries {
+                        if stored >= room {
+                            break;
+                        }
+                        let args = serde_json::to_string(&entry.args).expect("ject: log args to json");
+                        stored += insert.execute(params![
+                            session_id,
+                            run_id,
+                            entry.seq,
+                            entry.method.name(),
+                            args,
+                            entry.time
+                        ])?;
+                    }
+                }
+                tx.commit()?;
+                Ok(stored)
+            })()
+            .map_err(|source| DbError::Logs {
+                source,
+                id: session_id,
+                action: "store",
+            })?;
+
+            Ok((self, stored))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The newest runs of a session or save, newest first.
+    pub async fn get_log_runs(
+        self,
+        session_or_saved_id: &str,
+        limit: u32,
+    ) -> DbResult<(Self, Vec<LogRun>)> {
+        let id = session_or_saved_id.to_owned();
+
+        let self2 = block(move || {
+            let runs = (|| {
+                let mut runs = self
+                    .db
+                    .prepare(
+                        r#"SELECT run_id, revision, started_at FROM log_run WHERE session_or_saved_id = ?
+                            ORDER BY started_at DESC, rowid DESC LIMIT ?"#,
+                    )?
+                    .query_map(params![id, limit], |row| {
+                        Ok(LogRun {
+                            run_id: row.get("run_id")?,
+                            revision: row.get("revision")?,
+                            started_at: row.get("started_at")?,
+                            entries: vec![],
+                        })
+                    })?
+                    .collect::<Result<Vec<_>>>()?;
+
+                let mut entries = self.db.prepare(
+                    r#"SELECT seq, method, args, time FROM log_entry
+                        WHERE session_or_saved_id = ? AND run_id = ? ORDER BY seq"#,
+                )?;
+                for run in &mut runs {
+                    run.entries = entries
+                        .query_map(params![id, run.run_id], |row| {
+                            let method: String = row.get("method")?;
+                            let args: serde_json::Value = row.get("args")?;
+                            Ok(LogEntry {
+                                seq: row.get("seq")?,
+                                method: LogMethod::from_name(&method).unwrap_or(LogMethod::Log),
+                                // Always stored from a Vec<String> by [Db::put_log_entries]
+                                args: serde_json::from_value(args).unwrap_or_default(),
+                                time: row.get("time")?,
+                            })
+                        })?
+                        .collect::<Result<Vec<_>>>()?;
+                }
+                Ok(runs)
+            })()
+            .map_err(|source| DbError::Logs {
+                source,
+                id,
+                action: "get",
+            })?;
+
+            Ok((self, runs))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Replace the logs of a save with the newest run of the session it was saved from.
+    pub async fn copy_last_log_run(mut self, session_id: &str, saved_id: &str) -> DbResult<Self> {
+        let session_id = session_id.to_owned();
+        let saved_id = saved_id.to_owned();
+
+        let self2 = block(move || {
+            (|| {
+                let tx = self.db.transaction()?;
+                for table in &["log_entry", "log_run"] {
+                    tx.execute(
+                        &format!(r#"DELETE FROM {} WHERE session_or_saved_id = ?"#, table),
+                        params![saved_id],
+                    )?;
+                }
+                tx.execute(
+                    r#"INSERT INTO log_run (session_or_saved_id, run_id, revision, started_at)
+                        SELECT ?2, run_id, revision, started_at FROM log_run WHERE session_or_saved_id = ?1
+                        ORDER BY started_at DESC, rowid DESC LIMIT 1"#,
+                    params![session_id, saved_id],
+                )?;
+                tx.execute(
+                    r#"INSERT INTO log_entry (session_or_saved_id, run_id, seq, method, args, time)
+                        SELECT ?2, run_id, seq, method, args, time FROM log_entry
+                        WHERE session_or_saved_id = ?1
+                        AND run_id = (SELECT run_id FROM log_run WHERE session_or_saved_id = ?2)"#,
+                    params![session_id, saved_id],
+                )?;
+                tx.commit()
+            })()
+            .map_err(|source| DbError::Logs {
+                source,
+                id: saved_id,
+                action: "copy",
+            })?;
+
+            Ok(self)
+        })
+        .await?;
+
+        Ok(self2)
+    }
+}
+
+/// Test results, see [crate::api::tests].
+impl Db {
+    /// Store a session's test run, replacing the previous one. The revision is the session's
+    /// current one unless the run has one.
+    pub async fn put_test_run(mut self, session_id: &str, run: TestRun) -> DbResult<Self> {
+        let session_id = session_id.to_owned();
+
+        let self2 = block(move || {
+            (|| {
+                let tx = self.db.transaction()?;
+                tx.execute(
+                    r#"DELETE FROM test_result WHERE session_or_saved_id = ?"#,
+                    params![session_id],
+                )?;
+                tx.execute(
+                    r#"INSERT INTO test_run (session_or_saved_id, run_id, source, revision, started_at, duration, error)
+                        VALUES (?1, ?2, ?3, coalesce(?4, (SELECT revision FROM session WHERE session_id = ?1)), ?5, ?6, ?7)
+                        ON CONFLICT(session_or_saved_id) DO UPDATE SET
+                            run_id=excluded.run_id, source=excluded.source, revision=excluded.revision,
+                            started_at=excluded.started_at, duration=excluded.duration, error=excluded.error"#,
+                    params![
+                        session_id,
+                        run.run_id,
+                        run.source.name(),
+                        run.revision,
+                        run.started_at,
+                        run.duration,
+                        run.error
+                    ],
+                )?;
+                {
+                    let mut insert = tx.prepare(
+                        r#"INSERT INTO test_result (session_or_saved_id, seq, suite, name, status, duration, failure)
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+                    )?;
+                    for (seq, result) in run.results.iter().enumerate() {
+                        let suite = serde_json::to_string(&result.suite).expect("ject: test suite to json");
+                        insert.execute(params![
+                            session_id,
+                            seq as i64,
+                            suite,
+                            result.name,
+                            result.status.name(),
+                            result.duration,
+                            result.failure
+                        ])?;
+                    }
+                }
+                tx.commit()
+            })()
+            .map_err(|source| DbError::Tests {
+                source,
+                id: session_id,
+                action: "store",
+            })?;
+
+            Ok(self)
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// The newest test run of a session, if its tests have been run.
+    pub async fn get_test_run(self, session_id: &str) -> DbResult<(Self, Option<TestRun>)> {
+        let id = session_id.to_owned();
+
+        let self2 = block(move || {
+            let run = (|| {
+                let run = self
+                    .db
+                    .query_row(
+                        r#"SELECT run_id, source, revision, started_at, duration, error FROM test_run
+                            WHERE session_or_saved_id = ?"#,
+                        params![id],
+                        |row| {
+                            let source: String = row.get("source")?;
+                            let source = TestSource::from_name(&source).unwrap_or(TestSource::Frame);
+                            Ok((
+                                row.get::<_, String>("run_id")?,
+                                source,
+                                row.get::<_, Option<i64>>("revision")?,
+                                row.get::<_, i64>("started_at")?,
+                                row.get::<_, f64>("duration")?,
+                                row.get::<_, Option<String>>("error")?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+                let (run_id, source, revision, started_at, duration, error) = match run {
+                    Some(run) => run,
+                    None => return Ok(None),
+                };
+
+                let results = self
+                    .db
+                    .prepare(
+                        r#"SELECT suite, name, status, duration, failure FROM test_result
+                            WHERE session_or_saved_id = ? ORDER BY seq"#,
+                    )?
+                    .query_map(params![id], |row| {
+                        let suite: serde_json::Value = row.get("suite")?;
+                        let status: String = row.get("status")?;
+                        Ok(TestResult {
+                            // Always stored from a Vec<String> by [Db::put_test_run]
+                            suite: serde_json::from_value(suite).unwrap_or_default(),
+                            name: row.get("name")?,
+                            status: TestStatus::from_name(&status).unwrap_or(TestStatus::Failed),
+                            duration: row.get("duration")?,
+                            failure: row.get("failure")?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>>>()?;
+
+                let mut run = TestRun::new(run_id, source, started_at, duration, error, results);
+                run.revision = revision;
+                Ok(Some(run))
+            })()
+            .map_err(|source| DbError::Tests {
+                source,
+                id,
+                action: "get",
+            })?;
+
+            Ok((self, run))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+}
+
+/// Templates promoted from saves, see [crate::templates].
+impl Db {
+    fn template_from_row(row: &Row<'_>) -> rusqlite::Result<TemplateSummary> {
+        Ok(TemplateSummary {
+            name: row.get("name")?,
+            title: row.get("title")?,
+            description: row.get("description")?,
+            file_kinds: Self::json_column(row, "file_kinds")?,
+            builtin: false,
+            saved_id: row.get("saved_id")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+
+    /// Make a template of an undeleted save, replacing any template of the same name. Its files
+    /// are copied, so the template outlives the save. Returns None, changing nothing, if the
+    /// save doesn't exist or was deleted.
+    pub async fn promote_saved(
+        mut self,
+        saved_id: &str,
+        name: &str,
+        title: Option<String>,
+        description: Option<String>,
+        created_by: &str,
+    ) -> DbResult<(Self, Option<TemplateSummary>)> {
+        let saved_id = saved_id.to_owned();
+        let name = name.to_owned();
+        let created_by = created_by.to_owned();
+        let files_id = crate::templates::files_id(&name);
+
+        let self2 = block(move || {
+            let template = (|| {
+                let tx = self.db.transaction()?;
+                let saved: Option<(String, String, String)> = tx
+                    .query_row(
+                        r#"SELECT file_kinds, title, description FROM saved
+                            WHERE saved_id = ? AND deleted_at IS NULL"#,
+                        params![saved_id],
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                    )
+                    .optional()?;
+                let (file_kinds, saved_title, saved_description) = match saved {
+                    Some(saved) => saved,
+                    // Dropping the transaction rolls it back
+                    None => return Ok(None),
+                };
+
+                tx.execute(
+                    r#"INSERT INTO template (name, title, description, file_kinds, saved_id, created_by, created_at)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                        ON CONFLICT(name) DO UPDATE SET
+                            title=excluded.title, description=excluded.description,
+                            file_kinds=excluded.file_kinds, saved_id=excluded.saved_id,
+                            created_by=excluded.created_by, created_at=excluded.created_at"#,
+                    params![
+                        name,
+                        title.unwrap_or(saved_title),
+                        description.unwrap_or(saved_description),
+                        file_kinds,
+                        saved_id,
+                        created_by,
+                        unix_now()
+                    ],
+                )?;
+                tx.execute(
+                    r#"DELETE FROM file WHERE session_or_saved_id = ?"#,
+                    params![files_id],
+                )?;
+                tx.execute(
+                    r#"INSERT INTO file (file_id, session_or_saved_id, name, contents)
+                        SELECT ?2 || '::' || name, ?2, name, contents FROM file
+                        WHERE session_or_saved_id = ?1"#,
+                    params![saved_id, files_id],
+                )?;
+                let template = tx.query_row(
+                    r#"SELECT * FROM template WHERE name = ?"#,
+                    params![name],
+                    Self::template_from_row,
+                )?;
+                tx.commit()?;
+                Ok(Some(template))
+            })()
+            .map_err(|source| DbError::Template {
+                source,
+                name,
+                action: "promote",
+            })?;
+
+            Ok((self, template))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// All promoted templates, by name.
+    pub async fn list_templates(self) -> DbResult<(Self, Vec<TemplateSummary>)> {
+        let self2 = block(move || {
+            let templates = self
+                .db
+                .prepare(r#"SELECT * FROM template ORDER BY name"#)
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![], Self::template_from_row)?
+                        .collect::<Result<Vec<_>>>()
+                })
+                .map_err(|source| DbError::Template {
+                    source,
+                    name: String::new(),
+                    action: "list",
+                })?;
+
+            Ok((self, templates))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    pub async fn get_template(self, name: &str) -> DbResult<(Self, Option<TemplateSummary>)> {
+        let name = name.to_owned();
+
+        let self2 = block(move || {
+            self.db
+                .query_row(
+                    r#"SELECT * FROM template WHERE name = ?"#,
+                    params![name],
+                    Self::template_from_row,
+                )
+                .optional()
+                .map(|template| (self, template))
+                .map_err(|source| DbError::Template {
+                    source,
+                    name,
+                    action: "get",
+                })
+        })
+        .await?;
+
+        Ok(self2)
+    }
+
+    /// Delete a promoted template and its files. Returns false if there was none.
+    pub async fn delete_template(mut self, name: &str) -> DbResult<(Self, bool)> {
+        let name = name.to_owned();
+        let files_id = crate::templates::files_id(&name);
+
+        let self2 = block(move || {
+            let deleted = (|| {
+                let tx = self.db.transaction()?;
+                let deleted =
+                    tx.execute(r#"DELETE FROM template WHERE name = ?"#, params![name])?;
+                tx.execute(
+                    r#"DELETE FROM file WHERE session_or_saved_id = ?"#,
+                    params![files_id],
+                )?;
+                tx.commit()?;
+                Ok(deleted > 0)
+            })()
+            .map_err(|source| DbError::Template {
+                source,
+                name,
+                action: "delete",
+            })?;
+
+            Ok((self, deleted))
+        })
+        .await?;
+
+        Ok(self2)
+    }
+}
+
+/// A database file with the tables created, removed when dropped. For tests, which need to
+/// reopen it after an error consumes their [Db].
+#[cfg(test)]
+pub struct TempDb {
+    path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDb {
+    pub async fn new() -> Self {
+        let name = format!("ject-test-{}.db3", crate::ids::make_run_id());
+        let temp = TempDb {
+            path: std::env::temp_dir().join(name),
+        };
+        temp.open().create_tables().await.unwrap();
+        temp
+    }
+
+    pub fn open(&self) -> Db {
+        Db {
+            db: Connection::open(&self.path).unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store a save with these files, as `POST /api/save` does.
+    async fn put_test_save(db: Db, saved_id: &str, files: &[(FileKind, &str)], title: &str) -> Db {
+        let mut db = db;
+        for (kind, contents) in files {
+            let file_name = kind.to_default_name();
+            db = db.put_file(saved_id, file_name, contents).await.unwrap();
+        }
+        let meta = SessionMeta {
+            file_kinds: files.iter().map(|(kind, _)| *kind).collect(),
+        };
+        let details = SavedDetails {
+            title: title.to_owned(),
+            ..Default::default()
+        };
+        db.put_saved(saved_id, meta)
+            .await
+            .unwrap()
+            .put_saved_details(saved_id, details)
+            .await
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn promotes_saves_to_templates() {
+        let temp = TempDb::new().await;
+        let files_id = crate::templates::files_id("demo");
+        let db = put_test_save(
+            temp.open(),
+            "first",
+            &[(FileKind::JavaScript, "one"), (FileKind::Html, "<p>1</p>")],
+            "First",
+        )
+        .await;
+        let db = put_test_save(db, "second", &[(FileKind::JavaScript, "two")], "Second").await;
+
+        let (db, template) = db
+            .promote_saved("first", "demo", None, None, "admin")
+            .await
+            .unwrap();
+        let template = template.unwrap();
+        assert_eq!(template.title, "First");
+        assert_eq!(template.saved_id.as_deref(), Some("first"));
+        assert_eq!(
+            template.file_kinds,
+            vec![FileKind::JavaScript, FileKind::Html]
+        );
+        // The files are copied, so changing the save doesn't change the template
+        let db = db.put_file("first", "page.js", "changed").await.unwrap();
+        let (db, code) = db.get_file(&files_id, "page.js").await.unwrap();
+        assert_eq!(code, "one");
+
+        // Promoting another save under the same name replaces the template and its files
+        let title = Some("Replaced".to_owned());
+        let (db, template) = db
+            .promote_saved("second", "demo", title, None, "admin")
+            .await
+            .unwrap();
+        let template = template.unwrap();
+        assert_eq!(template.title, "Replaced");
+        assert_eq!(template.saved_id.as_deref(), Some("second"));
+        assert_eq!(template.file_kinds, vec![FileKind::JavaScript]);
+        let (db, code) = db.get_file(&files_id, "page.js").await.unwrap();
+        assert_eq!(code, "two");
+        assert!(db.get_file(&files_id, "page.html").await.is_err());
+
+        let (db, templates) = temp.open().list_templates().await.unwrap();
+        assert_eq!(templates.len(), 1);
+        let (db, template) = db
+            .delete_saved("first")
+            .await
+            .unwrap()
+            .promote_saved("first", "other", None, None, "admin")
+            .await
+            .unwrap();
+        assert!(template.is_none());
+
+        let (db, deleted) = db.delete_template("demo").await.unwrap();
+        assert!(deleted);
+        let (db, deleted) = db.delete_template("demo").await.unwrap();
+        assert!(!deleted);
+        let (db, template) = db.get_template("demo").await.unwrap();
+        assert!(template.is_none());
+        assert!(db.get_file(&files_id, "page.js").await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn writes_session_files_at_the_expected_revision() {
+        let temp = TempDb::new().await;
+        let meta = SessionMeta {
+            file_kinds: vec![FileKind::JavaScript],
+        };
+        let db = temp
+            .open()
+            .put_session("s", meta)
+            .await
+            .unwrap()
+            .put_file("s", "page.js", "a")
+            .await
+            .unwrap();
+        let full = |js: &str, file_kinds| FileWrites::Full {
+            files: vec![("page.js".to_owned(), js.to_owned())],
+            file_kinds,
+        };
+
+        let file_kinds = Some(vec![FileKind::JavaScript, FileKind::Html]);
+        let (db, written) = db
+            .write_session_files("s", Some(1), full("b", file_kinds))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::Written(2));
+        let (db, meta) = db.get_session("s").await.unwrap();
+        assert_eq!(meta.file_kinds, vec![FileKind::JavaScript, FileKind::Html]);
+
+        // Another write already made revision 2
+        let (db, written) = db
+            .write_session_files("s", Some(1), full("c", None))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::RevisionConflict);
+        let (db, code) = db.get_file("s", "page.js").await.unwrap();
+        assert_eq!(code, "b");
+
+        let (db, written) = db
+            .write_session_files("s", None, full("c", None))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::Written(3));
+
+        // A conflicting replacement rolls back the ones before it, and the revision
+        let replace = |html_was: &str| {
+            FileWrites::Replace(vec![
+                ("page.js".to_owned(), "c".to_owned(), "d".to_owned()),
+                (
+                    "page.html".to_owned(),
+                    html_was.to_owned(),
+                    "<p>".to_owned(),
+                ),
+            ])
+        };
+        let db = db.put_file("s", "page.html", "").await.unwrap();
+        let (db, written) = db
+            .write_session_files("s", None, replace("changed"))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::FileConflict(1));
+        let (db, code) = db.get_file("s", "page.js").await.unwrap();
+        assert_eq!(code, "c");
+        let (db, revision) = db.get_session_revision("s").await.unwrap();
+        assert_eq!(revision, 3);
+
+        let (db, written) = db
+            .write_session_files("s", Some(3), replace(""))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::Written(4));
+        let (db, code) = db.get_file("s", "page.html").await.unwrap();
+        assert_eq!(code, "<p>");
+
+        let (_, written) = db
+            .write_session_files("missing", None, full("a", None))
+            .await
+            .unwrap();
+        assert_eq!(written, SessionWrite::RevisionConflict);
+    }
+
+    #[actix_rt::test]
+    async fn pages_through_saves_with_equal_sort_keys() {
+        let temp = TempDb::new().await;
+        let mut db = temp.open();
+        for saved_id in &["a", "b", "c", "d", "e"] {
+            db = put_test_save(db, saved_id, &[(FileKind::JavaScript, "")], saved_id).await;
+        }
+        // "e", "c" and "a" have no views, so a page ends between saves with the same key
+        db = db.incr_saved_views("b").await.unwrap();
+        db = db.incr_saved_views("d").await.unwrap();
+
+        let mut after = None;
+        let mut listed = vec![];
+        loop {
+            let (db2, page) = db
+                .list_saved(SavedFilter::default(), SavedSort::Views, after, 2)
+                .await
+                .unwrap();
+            db = db2;
+            let last = match page.last() {
+                Some((_, cursor)) => *cursor,
+                None => break,
+            };
+            assert_eq!(last.sort, SavedSort::Views);
+            after = Some(last);
+            listed.extend(page.into_iter().map(|(saved, _)| saved.save_id));
+        }
+        // Each once, the last stored first among equal views
+        assert_eq!(listed, vec!["d", "b", "e", "c", "a"]);
+    }
+
+    #[actix_rt::test]
+    async fn forks_saves_into_sessions() {
+        let temp = TempDb::new().await;
+        let files = [(FileKind::JavaScript, "code"), (FileKind::Html, "<p>")];
+        let db = put_test_save(temp.open(), "orig", &files, "Original").await;
+
+        let (db, meta) = db.fork_saved("orig", "s1", "hash").await.unwrap();
+        let meta = meta.unwrap();
+        assert_eq!(meta.file_kinds, vec![FileKind::JavaScript, FileKind::Html]);
+        let (db, code) = db.get_file("s1", "page.js").await.unwrap();
+        assert_eq!(code, "code");
+        let (db, html) = db.get_file("s1", "page.html").await.unwrap();
+        assert_eq!(html, "<p>");
+        let (db, forked_from) = db.get_session_forked_from("s1").await.unwrap();
+        assert_eq!(forked_from.as_deref(), Some("orig"));
+        let (db, hash) = db.get_edit_token_hash("s1").await.unwrap();
+        assert_eq!(hash.as_deref(), Some("hash"));
+        let (db, saves) = db
+            .list_saved(SavedFilter::default(), SavedSort::Newest, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(saves[0].0.forks, 1);
+
+        // Nothing is created for missing or deleted saves
+        let (db, meta) = db.fork_saved("missing", "s2", "hash").await.unwrap();
+        assert!(meta.is_none());
+        let (db, meta) = db
+            .delete_saved("orig")
+            .await
+            .unwrap()
+            .fork_saved("orig", "s3", "hash")
+            .await
+            .unwrap();
+        assert!(meta.is_none());
+        assert!(db.get_session("s2").await.is_err());
+        assert!(temp.open().get_session("s3").await.is_err());
+        assert!(temp.open().get_file("s3", "page.js").await.is_err());
     }
 }
@@ -6,7 +6,7 @@ pub fn is_production() -> bool {
 }
 
 pub fn domain_main() -> String {
-    if let Some(domain) = std::env::var("JECT_DOMAIN_MAIN").ok() {
+    if let Ok(domain) = std::env::var("JECT_DOMAIN_MAIN") {
         domain
     } else if is_production() {
         "ject.dev".to_owned()
@@ -16,7 +16,7 @@ pub fn domain_main() -> String {
 }
 
 pub fn domain_frame() -> String {
-    if let Some(domain) = std::env::var("JECT_DOMAIN_FRAME").ok() {
+    if let Ok(domain) = std::env::var("JECT_DOMAIN_FRAME") {
         domain
     } else if is_production() {
         "ject.link".to_owned()
@@ -25,6 +25,113 @@ pub fn domain_frame() -> String {
     }
 }
 
+/// Extra origins (comma separated in $JECT_CDN_MIRRORS) that the frame may load scripts and
+/// styles from, in addition to the default CDNs.
+pub fn cdn_mirrors() -> Vec<String> {
+    std::env::var("JECT_CDN_MIRRORS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim())
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| origin.to_owned())
+        .collect()
+}
+
+/// User ids of the accounts that may manage templates (comma separated in $JECT_ADMINS), see
+/// [crate::templates]. Not usernames, which anyone may register before the admin does.
+pub fn admins() -> Vec<String> {
+    std::env::var("JECT_ADMINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|user_id| user_id.trim())
+        .filter(|user_id| !user_id.is_empty())
+        .map(|user_id| user_id.to_owned())
+        .collect()
+}
+
+/// Replaces the generated Content-Security-Policy of the frame page when set.
+pub fn frame_csp() -> Option<String> {
+    std::env::var("JECT_FRAME_CSP")
+        .ok()
+        .filter(|csp| !csp.trim().is_empty())
+}
+
+/// Replaces the default Permissions-Policy of the frame page when set.
+pub fn frame_permissions_policy() -> Option<String> {
+    std::env::var("JECT_FRAME_PERMISSIONS_POLICY").ok()
+}
+
+/// Comma separated CIDRs of reverse proxies whose forwarding headers are trusted
+/// ($JECT_TRUSTED_PROXIES), see [crate::forwarded]. Defaults to loopback, where nginx runs.
+pub fn trusted_proxies() -> String {
+    std::env::var("JECT_TRUSTED_PROXIES").unwrap_or_else(|_| "127.0.0.1/32,::1/128".to_owned())
+}
+
+/// The `<requests>/<seconds>` limit of a rate limit budget ($JECT_RATE_LIMIT_CREATE etc.), or
+/// `off`. See [crate::rate_limit].
+pub fn rate_limit(budget: &str) -> Option<String> {
+    std::env::var(format!("JECT_RATE_LIMIT_{}", budget.to_ascii_uppercase())).ok()
+}
+
+fn usize_var(name: &str, default: usize) -> usize {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            eprintln!("[env] Ignoring invalid {}={:?}", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Largest request body accepted by the API ($JECT_MAX_PAYLOAD_BYTES). Defaults to what a
+/// session at its file quotas needs, with a quarter more for JSON escaping and 64 KiB for
+/// the rest of the request.
+pub fn max_payload_bytes() -> usize {
+    let files = max_files() * max_file_bytes();
+    usize_var("JECT_MAX_PAYLOAD_BYTES", files + files / 4 + 64 * 1024)
+}
+
+/// Largest contents of a single session file ($JECT_MAX_FILE_BYTES).
+pub fn max_file_bytes() -> usize {
+    usize_var("JECT_MAX_FILE_BYTES", 128 * 1024)
+}
+
+/// Most files a session or save may contain ($JECT_MAX_FILES).
+pub fn max_files() -> usize {
+    usize_var("JECT_MAX_FILES", 8)
+}
+
+/// Seconds between writes of collaboratively edited files to the database
+/// ($JECT_COLLAB_FLUSH_SECS). They're also written when the last editor leaves.
+pub fn collab_flush_secs() -> usize {
+    usize_var("JECT_COLLAB_FLUSH_SECS", 5)
+}
+
+/// Most console entries stored per run of a session's frame ($JECT_MAX_LOG_ENTRIES).
+pub fn max_log_entries() -> usize {
+    usize_var("JECT_MAX_LOG_ENTRIES", 1000)
+}
+
+/// Runs of a session's frame whose console output is kept ($JECT_LOG_RUNS).
+pub fn log_runs() -> usize {
+    usize_var("JECT_LOG_RUNS", 5)
+}
+
+/// Milliseconds page.js may run for on the server, timers included ($JECT_RUN_TIME_MS).
+pub fn run_time_ms() -> usize {
+    usize_var("JECT_RUN_TIME_MS", 1000)
+}
+
+/// Most memory page.js may use when run on the server ($JECT_RUN_MEMORY_BYTES).
+pub fn run_memory_bytes() -> usize {
+    usize_var("JECT_RUN_MEMORY_BYTES", 32 * 1024 * 1024)
+}
+
+/// Seconds a deleted save's files are kept before being purged ($JECT_PURGE_GRACE_SECS).
+pub fn purge_grace_secs() -> usize {
+    usize_var("JECT_PURGE_GRACE_SECS", 7 * 24 * 60 * 60)
+}
+
 pub fn open_sqlite_env() -> Result<Connection, rusqlite::Error> {
     let mut path = match std::env::var("JECT_DB") {
         Ok(v) if !v.is_empty() => v,
@@ -0,0 +1,417 @@
+//! Works out the real client address, host and scheme of a request that may have passed
+//! through reverse proxies (nginx in production).
+//!
+//! Forwarding headers are only believed when the request came from a trusted proxy, see
+//! `$JECT_TRUSTED_PROXIES`. The RFC 7239 `Forwarded` header is preferred, with
+//! `X-Forwarded-For`/`-Host`/`-Proto` and `X-Real-IP` as fallbacks.
+
+use crate::env;
+use actix_web::{
+    dev::Payload,
+    http::{HeaderMap, HeaderValue},
+    FromRequest, HttpRequest,
+};
+use futures::future::{ready, Ready};
+use once_cell::sync::Lazy;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            // IPv4 clients of a dual-stack socket show up as ::ffff:a.b.c.d
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full_bytes = (prefix / 8) as usize;
+    let rest_bits = prefix % 8;
+    if a[..full_bytes] != b[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+    let mask = 0xffu8 << (8 - rest_bits);
+    a[full_bytes] & mask == b[full_bytes] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid IP address in CIDR {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|&prefix| prefix <= max)
+                .ok_or_else(|| format!("Invalid prefix length in CIDR {:?}", s))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Parsed once from `$JECT_TRUSTED_PROXIES`; invalid entries are reported and skipped.
+static TRUSTED_PROXIES: Lazy<Vec<Cidr>> = Lazy::new(|| {
+    env::trusted_proxies()
+        .split(',')
+        .filter(|cidr| !cidr.trim().is_empty())
+        .filter_map(|cidr| match cidr.parse() {
+            Ok(cidr) => Some(cidr),
+            Err(err) => {
+                eprintln!(
+                    "[forwarded] Ignoring entry in JECT_TRUSTED_PROXIES: {}",
+                    err
+                );
+                None
+            }
+        })
+        .collect()
+});
+
+/// One hop of a `Forwarded` header, e.g. `for=192.0.2.60;proto=https;host=ject.dev`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ForwardedElement {
+    /// None for "unknown" and obfuscated identifiers like "_hidden"
+    for_ip: Option<IpAddr>,
+    host: Option<String>,
+    proto: Option<String>,
+}
+
+/// Parse a `Forwarded` header value per RFC 7239, including quoted strings.
+fn parse_forwarded(value: &str) -> Vec<ForwardedElement> {
+    let mut elements = vec![];
+    let mut element = ForwardedElement::default();
+    let mut chars = value.chars().peekable();
+
+    loop {
+        // Read `token=value`
+        let mut name = String::new();
+        while let Some(&ch) = chars.peek() {
+            if ch == '=' || ch == ';' || ch == ',' {
+                break;
+            }
+            name.push(ch);
+            chars.next();
+        }
+
+        let mut pair_value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                chars.next();
+            }
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(ch) = chars.next() {
+                    match ch {
+                        '\\' => pair_value.extend(chars.next()),
+                        '"' => break,
+                        _ => pair_value.push(ch),
+                    }
+                }
+            }
+            while let Some(&ch) = chars.peek() {
+                if ch == ';' || ch == ',' {
+                    break;
+                }
+                pair_value.push(ch);
+                chars.next();
+            }
+        }
+
+        let pair_value = pair_value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "for" => element.for_ip = parse_node(pair_value),
+            "host" if !pair_value.is_empty() => element.host = Some(pair_value.to_owned()),
+            "proto" if !pair_value.is_empty() => {
+                element.proto = Some(pair_value.to_ascii_lowercase())
+            }
+            _ => {}
+        }
+
+        match chars.next() {
+            Some(';') => {}
+            Some(',') => elements.push(std::mem::take(&mut element)),
+            _ => {
+                elements.push(element);
+                break;
+            }
+        }
+    }
+
+    elements
+}
+
+/// Parse a node like `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::17]:4711` or `::1`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.split(']').next())
+        .and_then(|ip| ip.parse().ok())
+}
+
+/// Comma separated values of every instance of a header, in order
+fn header_list(headers: &HeaderMap, name: &str) -> Vec<String> {
+    headers
+        .get_all(name)
+        .filter_map(|value: &HeaderValue| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// The original client of a request, see the module docs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The client's address. Only None if the connection has no peer address and nothing is
+    /// forwarded (e.g. in tests).
+    pub ip: Option<IpAddr>,
+    /// The host the client asked for, as reported by a trusted proxy. Domain checks use the
+    /// `Host` header instead, see [crate::http::Host].
+    pub forwarded_host: Option<String>,
+    /// The scheme the client used, as reported by a trusted proxy
+    pub forwarded_proto: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_parts(peer: Option<SocketAddr>, headers: &HeaderMap) -> Self {
+        Self::with_trusted(peer, headers, &TRUSTED_PROXIES)
+    }
+
+    fn with_trusted(peer: Option<SocketAddr>, headers: &HeaderMap, trusted: &[Cidr]) -> Self {
+        let peer_ip = peer.map(|peer| peer.ip());
+        let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+
+        let direct = Self {
+            ip: peer_ip,
+            ..Default::default()
+        };
+        match peer_ip {
+            Some(ip) if is_trusted(ip) => {}
+            // Anyone can send these headers, so only proxies get to set them
+            _ => return direct,
+        }
+
+        let forwarded = header_list(headers, "forwarded").join(",");
+        if !forwarded.is_empty() {
+            let elements = parse_forwarded(&forwarded);
+            // Each proxy appends the hop it received, so walk back from the nearest proxy
+            // until reaching a hop that came from outside the trusted set
+            let mut chosen = None;
+            for element in elements.iter().rev() {
+                chosen = Some(element);
+                match element.for_ip {
+                    Some(ip) if is_trusted(ip) => continue,
+                    _ => break,
+                }
+            }
+
+            return match chosen {
+                Some(element) => Self {
+                    ip: element.for_ip.or(peer_ip),
+                    forwarded_host: element.host.clone(),
+                    forwarded_proto: element.proto.clone(),
+                },
+                None => direct,
+            };
+        }
+
+        let xff: Vec<IpAddr> = header_list(headers, "x-forwarded-for")
+            .iter()
+            .filter_map(|node| parse_node(node))
+            .collect();
+        let hosts = header_list(headers, "x-forwarded-host");
+        let protos = header_list(headers, "x-forwarded-proto");
+
+        // Number of proxies between us and the client
+        let mut hops = 0;
+        let mut ip = None;
+        for &hop in xff.iter().rev() {
+            ip = Some(hop);
+            if !is_trusted(hop) {
+                break;
+            }
+            hops += 1;
+        }
+
+        let ip = ip.or_else(|| {
+            headers
+                .get("x-real-ip")
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_node)
+        });
+
+        // X-Forwarded-Host/Proto are usually set once by the edge proxy, but if proxies append
+        // to them use the entry matching the hop the client connected to
+        let pick = |values: &[String]| -> Option<String> {
+            let index = values.len().checked_sub(1)?.saturating_sub(hops);
+            values.get(index).cloned()
+        };
+
+        Self {
+            ip: ip.or(peer_ip),
+            forwarded_host: pick(&hosts),
+            forwarded_proto: pick(&protos).map(|proto| proto.to_ascii_lowercase()),
+        }
+    }
+}
+
+impl FromRequest for ClientInfo {
+    type Error = ();
+
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Self::from_parts(req.peer_addr(), req.headers())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderName;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        map
+    }
+
+    fn trusted() -> Vec<Cidr> {
+        vec![
+            "127.0.0.1/32".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ]
+    }
+
+    fn peer(ip: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.parse().unwrap(), 50000))
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.200.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.0.1".parse().unwrap()));
+
+        let net: Cidr = "2001:db8::/33".parse().unwrap();
+        assert!(net.contains("2001:db8:7fff::1".parse().unwrap()));
+        assert!(!net.contains("2001:db8:8000::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn parses_forwarded() {
+        let elements = parse_forwarded(
+            r#"for=192.0.2.60;proto=HTTPS;host="ject.dev", For="[2001:db8:cafe::17]:4711", for=unknown;by=10.0.0.1"#,
+        );
+        assert_eq!(
+            elements,
+            vec![
+                ForwardedElement {
+                    for_ip: ip("192.0.2.60"),
+                    host: Some("ject.dev".into()),
+                    proto: Some("https".into()),
+                },
+                ForwardedElement {
+                    for_ip: ip("2001:db8:cafe::17"),
+                    ..Default::default()
+                },
+                ForwardedElement::default(),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let h = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("forwarded", "for=1.2.3.4;host=ject.dev"),
+        ]);
+        let info = ClientInfo::with_trusted(peer("203.0.113.9"), &h, &trusted());
+        assert_eq!(info.ip, ip("203.0.113.9"));
+        assert_eq!(info.forwarded_host, None);
+    }
+
+    #[test]
+    fn walks_forwarded_chain() {
+        let h = headers(&[(
+            "forwarded",
+            "for=6.6.6.6, for=198.51.100.7;host=ject.link;proto=https, for=10.0.0.2;host=internal",
+        )]);
+        let info = ClientInfo::with_trusted(peer("127.0.0.1"), &h, &trusted());
+        assert_eq!(info.ip, ip("198.51.100.7"));
+        assert_eq!(info.forwarded_host.as_deref(), Some("ject.link"));
+        assert_eq!(info.forwarded_proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_chain() {
+        let h = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 198.51.100.7"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-host", "ject.dev"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let info = ClientInfo::with_trusted(peer("127.0.0.1"), &h, &trusted());
+        assert_eq!(info.ip, ip("198.51.100.7"));
+        assert_eq!(info.forwarded_host.as_deref(), Some("ject.dev"));
+        assert_eq!(info.forwarded_proto.as_deref(), Some("https"));
+
+        let h = headers(&[("x-real-ip", "198.51.100.8")]);
+        let info = ClientInfo::with_trusted(peer("127.0.0.1"), &h, &trusted());
+        assert_eq!(info.ip, ip("198.51.100.8"));
+    }
+}
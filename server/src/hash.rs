@@ -0,0 +1,16 @@
+use sha1::{Digest, Sha1};
+use std::fmt::Write;
+
+/// Lowercase hex encoding of the bytes.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(&mut out, "{:02x}", byte).unwrap();
+    }
+    out
+}
+
+/// Lowercase hex sha1 of the input, used for cache keys and for storing random tokens.
+pub fn sha1_hex(data: impl AsRef<[u8]>) -> String {
+    to_hex(&Sha1::digest(data.as_ref()))
+}
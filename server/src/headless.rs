@@ -0,0 +1,705 @@
+//! Running page.js without a browser, for `POST /api/session/{id}/run`, and its tests, for
+//! `POST /api/session/{id}/tests/run`.
+//!
+//! The compiled code is evaluated as a module in QuickJS, with a console and timers (see
+//! headless_prelude.js) but no DOM. Timers don't wait; they run in the order they're due. The
+//! run stops when nothing is left to do or the time limit is reached. Tests are run by
+//! evaluating page.test.js instead, after test_runner.js, with page.js for it to import.
+//!
+//! Each run happens in a child process, the server binary started with [CHILD_ARG], as the
+//! engine doesn't always survive running out of memory. The child writes console entries to
+//! stdout as JSON lines while the code runs, so they're kept even if it crashes, then a last
+//! line with the uncaught errors. The server waits for it asynchronously, so runs don't hold
+//! threads of the blocking pool the database uses.
+
+use crate::{
+    env,
+    state::{LogEntry, LogMethod, TestResult},
+};
+use anyhow::Context as _;
+use rquickjs::{
+    CatchResultExt, CaughtError, Coerced, Context, Ctx, Function, Module, Object, Persistent,
+    Runtime, Value,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Read, Write},
+    process::Stdio,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+};
+
+/// The first argument that makes the server run page.js from stdin instead, see [child_main].
+pub const CHILD_ARG: &str = "--headless-run";
+
+const PRELUDE: &str = include_str!("headless_prelude.js");
+
+const TEST_RUNNER: &str = include_str!("test_runner.js");
+
+/// The module evaluated for a test run: page.test.js collects the tests, then they're run.
+/// Page.test.js imports page.js as `./page.js`, which resolves to the module of that name.
+const TESTS_MAIN: &str = "import 'page.test.js';\nawait globalThis.__jectTests.run();\n";
+
+/// How long after [RunLimits::time] a child that hasn't finished is killed. It normally stops
+/// itself at the limit, or is stopped by the OS's CPU limit soon after, unless it's stuck
+/// without using the CPU.
+const KILL_AFTER: Duration = Duration::from_secs(5);
+
+/// Enough for deeply nested but reasonable code, well under the stack of the main thread.
+const MAX_STACK_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RunLimits {
+    /// Longest the code may run, timers included
+    pub time: Duration,
+    /// Most memory the JavaScript heap may use
+    pub memory_bytes: usize,
+    /// Most console entries kept; the rest are dropped
+    pub max_entries: usize,
+}
+
+impl RunLimits {
+    /// The limits of runs requested through the API, from `$JECT_RUN_TIME_MS`,
+    /// `$JECT_RUN_MEMORY_BYTES` and `$JECT_MAX_LOG_ENTRIES`.
+    pub fn from_env() -> Self {
+        Self {
+            time: Duration::from_millis(env::run_time_ms() as u64),
+            memory_bytes: env::run_memory_bytes(),
+            max_entries: env::max_log_entries(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UncaughtError {
+    pub name: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<String>,
+}
+
+/// The tests of page.test.js that finished, as reported by test_runner.js.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+    /// Milliseconds, or 0 if the run didn't finish
+    pub duration: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RunOutput {
+    pub entries: Vec<LogEntry>,
+    pub errors: Vec<UncaughtError>,
+    /// The time limit was reached before the code finished
+    pub timed_out: bool,
+    /// Some console entries were dropped, see [RunLimits::max_entries]
+    pub truncated: bool,
+    /// Only for test runs; None if the results couldn't be read, e.g. the engine crashed
+    pub tests: Option<TestReport>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeadlessError {
+    #[error("Unable to start the process that runs JavaScript")]
+    Spawn(#[from] io::Error),
+}
+
+/// What the child process reads from stdin.
+#[derive(Debug, Serialize, Deserialize)]
+struct RunInput {
+    /// Compiled page.js
+    page: String,
+    /// Compiled page.test.js, whose tests are run instead of only evaluating page.js
+    tests: Option<String>,
+}
+
+/// What the child process writes to stdout, one per line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChildLine {
+    Entry(LogEntry),
+    Done {
+        errors: Vec<UncaughtError>,
+        timed_out: bool,
+        truncated: bool,
+        tests: Option<TestReport>,
+    },
+}
+
+/// Collects what the code reports while it runs.
+struct Recorder {
+    started: Instant,
+    max_entries: usize,
+    logged: usize,
+    timed_out: Rc<Cell<bool>>,
+    /// Receives entries as they're logged, instead of keeping them in the output
+    stream: Option<Box<dyn FnMut(LogEntry)>>,
+    /// Rejected promises without a handler and their reasons, until a handler is attached
+    rejected: Vec<(Persistent<Value<'static>>, Persistent<Value<'static>>)>,
+    output: RunOutput,
+}
+
+impl Recorder {
+    fn log(&mut self, method: &str, args: Vec<String>) {
+        if self.logged >= self.max_entries {
+            self.output.truncated = true;
+            return;
+        }
+        let entry = LogEntry {
+            seq: self.logged as u32,
+            method: LogMethod::from_name(method).unwrap_or(LogMethod::Log),
+            args,
+            time: self.started.elapsed().as_secs_f64() * 1000.0,
+        };
+        self.logged += 1;
+        match &mut self.stream {
+            Some(stream) => stream(entry),
+            None => self.output.entries.push(entry),
+        }
+    }
+
+    fn error(&mut self, error: UncaughtError) {
+        // What the engine throws to stop the code at the time limit
+        if self.timed_out.get() && error.message == "interrupted" {
+            return;
+        }
+        self.output.errors.push(error);
+    }
+
+    fn is_rejected_with<'js>(&self, ctx: &Ctx<'js>, reason: &Value<'js>) -> bool {
+        self.rejected
+            .iter()
+            .any(|(_, rejected)| rejected.clone().restore(ctx).is_ok_and(|r| r == *reason))
+    }
+
+    /// A promise was rejected without a handler. A module that throws rejects more than one
+    /// promise with the same error, which is only reported once.
+    fn rejection<'js>(&mut self, ctx: &Ctx<'js>, promise: Value<'js>, reason: Value<'js>) {
+        if !self.is_rejected_with(ctx, &reason) {
+            self.error(uncaught_error(reason.clone()));
+        }
+        let saved = (
+            Persistent::save(ctx, promise),
+            Persistent::save(ctx, reason),
+        );
+        self.rejected.push(saved);
+    }
+
+    fn rejection_handled<'js>(&mut self, ctx: &Ctx<'js>, promise: Value<'js>) {
+        let handled = self
+            .rejected
+            .iter()
+            .position(|(rejected, _)| rejected.clone().restore(ctx).is_ok_and(|p| p == promise));
+        let reason = match handled {
+            Some(index) => self.rejected.remove(index).1.restore(ctx),
+            None => return,
+        };
+        if let Ok(reason) = reason {
+            if !self.is_rejected_with(ctx, &reason) {
+                let error = uncaught_error(reason);
+                if let Some(index) = self.output.errors.iter().rposition(|e| *e == error) {
+                    self.output.errors.remove(index);
+                }
+            }
+        }
+    }
+}
+
+fn uncaught_error(value: Value<'_>) -> UncaughtError {
+    if let Some(exception) = value.as_exception() {
+        let name = exception
+            .get::<_, Coerced<String>>("name")
+            .map(|name| name.0)
+            .unwrap_or_else(|_| "Error".to_owned());
+        return UncaughtError {
+            name,
+            message: exception.message().unwrap_or_default(),
+            stack: exception.stack().filter(|stack| !stack.is_empty()),
+        };
+    }
+    let message = value
+        .get::<Coerced<String>>()
+        .map(|message| message.0)
+        .unwrap_or_else(|_| value.type_name().to_owned());
+    UncaughtError {
+        name: "Uncaught".to_owned(),
+        message,
+        stack: None,
+    }
+}
+
+fn caught_error(error: CaughtError<'_>) -> UncaughtError {
+    match error {
+        CaughtError::Exception(exception) => uncaught_error(exception.into_value()),
+        CaughtError::Value(value) => uncaught_error(value),
+        CaughtError::Error(error) => UncaughtError {
+            name: "InternalError".to_owned(),
+            message: error.to_string(),
+            stack: None,
+        },
+    }
+}
+
+/// Run compiled page.js in a child process and return its console output and uncaught errors.
+/// Takes up to about [RunLimits::time].
+pub async fn run(code: &str, limits: RunLimits) -> Result<RunOutput, HeadlessError> {
+    let input = RunInput {
+        page: code.to_owned(),
+        tests: None,
+    };
+    run_child(&input, limits).await
+}
+
+/// Run the tests of compiled page.test.js like [run], with compiled page.js for it to import.
+/// The time limit covers all of the tests.
+pub async fn run_tests(
+    page: &str,
+    tests: &str,
+    limits: RunLimits,
+) -> Result<RunOutput, HeadlessError> {
+    let input = RunInput {
+        page: page.to_owned(),
+        tests: Some(tests.to_owned()),
+    };
+    run_child(&input, limits).await
+}
+
+async fn run_child(input: &RunInput, limits: RunLimits) -> Result<RunOutput, HeadlessError> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg(CHILD_ARG)
+        .arg(limits.time.as_millis().to_string())
+        .arg(limits.memory_bytes.to_string())
+        .arg(limits.max_entries.to_string())
+        .env_clear()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // The child reads all of it before writing anything
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = serde_json::to_vec(input).expect("ject: RunInput to json");
+        stdin.write_all(&input).await?;
+    }
+
+    let mut output = RunOutput::default();
+    let mut done = false;
+    let stdout = child.stdout.take();
+    let read = async {
+        let stdout = match stdout {
+            Some(stdout) => stdout,
+            None => return Ok::<_, io::Error>(()),
+        };
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str(&line) {
+                Ok(ChildLine::Entry(entry)) => output.entries.push(entry),
+                Ok(ChildLine::Done {
+                    errors,
+                    timed_out,
+                    truncated,
+                    tests,
+                }) => {
+                    output.errors = errors;
+                    output.timed_out = timed_out;
+                    output.truncated = truncated;
+                    output.tests = tests;
+                    done = true;
+                }
+                Err(err) => eprintln!("[headless] invalid line from the child process: {}", err),
+            }
+        }
+        Ok(())
+    };
+    if tokio::time::timeout(limits.time + KILL_AFTER, read)
+        .await
+        .is_err()
+    {
+        // Counts as timed out below, like the OS's CPU limit
+        child.kill()?;
+    }
+
+    let status = child.await?;
+    if !done {
+        if stopped_by_cpu_limit(&status) {
+            output.timed_out = true;
+        } else {
+            output.errors.push(UncaughtError {
+                name: "InternalError".to_owned(),
+                message: format!("The JavaScript engine stopped unexpectedly ({})", status),
+                stack: None,
+            });
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(unix)]
+fn stopped_by_cpu_limit(status: &std::process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    matches!(status.signal(), Some(libc::SIGXCPU) | Some(libc::SIGKILL))
+}
+
+#[cfg(not(unix))]
+fn stopped_by_cpu_limit(_status: &std::process::ExitStatus) -> bool {
+    false
+}
+
+/// Limits enforced by the OS, in case the engine's own don't hold. They're looser, so the
+/// engine's are normally reached first and end the run cleanly.
+#[cfg(unix)]
+fn set_process_limits(limits: RunLimits) -> io::Result<()> {
+    let cpu_secs = limits.time.as_secs() + 2;
+    // Room for the rest of the process besides the JavaScript heap
+    let data_bytes = limits.memory_bytes as u64 * 2 + 64 * 1024 * 1024;
+
+    let set = |resource, limit: u64| {
+        let rlimit = libc::rlimit {
+            rlim_cur: limit as libc::rlim_t,
+            rlim_max: limit as libc::rlim_t,
+        };
+        match unsafe { libc::setrlimit(resource, &rlimit) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    };
+    set(libc::RLIMIT_CPU, cpu_secs)?;
+    set(libc::RLIMIT_DATA, data_bytes)
+}
+
+#[cfg(not(unix))]
+fn set_process_limits(_limits: RunLimits) -> io::Result<()> {
+    Ok(())
+}
+
+/// Entry point of the child process started by [run]: evaluates the [RunInput] on stdin with
+/// the limits given as arguments and writes [ChildLine]s to stdout.
+pub fn child_main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let arg = |index: usize| -> anyhow::Result<usize> {
+        let value = args.get(index).context("missing run limit argument")?;
+        Ok(value.parse()?)
+    };
+    let limits = RunLimits {
+        time: Duration::from_millis(arg(0)? as u64),
+        memory_bytes: arg(1)?,
+        max_entries: arg(2)?,
+    };
+    set_process_limits(limits)?;
+
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    let input: RunInput = serde_json::from_str(&input)?;
+
+    let write_line = |line: &ChildLine| {
+        let json = serde_json::to_string(line).expect("ject: ChildLine to json");
+        // Stdout is line buffered, so each entry is sent right away
+        let _ = writeln!(io::stdout(), "{}", json);
+    };
+    let output = evaluate(
+        &input,
+        limits,
+        Some(Box::new(move |entry| write_line(&ChildLine::Entry(entry)))),
+    )?;
+    write_line(&ChildLine::Done {
+        errors: output.errors,
+        timed_out: output.timed_out,
+        truncated: output.truncated,
+        tests: output.tests,
+    });
+
+    Ok(())
+}
+
+/// Evaluate compiled page.js, or run the tests, in this process. Console entries go to
+/// `stream` if given, or are returned with the errors.
+fn evaluate(
+    input: &RunInput,
+    limits: RunLimits,
+    stream: Option<Box<dyn FnMut(LogEntry)>>,
+) -> rquickjs::Result<RunOutput> {
+    let runtime = Runtime::new()?;
+    runtime.set_memory_limit(limits.memory_bytes);
+    runtime.set_max_stack_size(MAX_STACK_BYTES);
+
+    let deadline = Instant::now() + limits.time;
+    let timed_out = Rc::new(Cell::new(false));
+    runtime.set_interrupt_handler(Some(Box::new({
+        let timed_out = timed_out.clone();
+        move || {
+            if Instant::now() >= deadline {
+                timed_out.set(true);
+            }
+            timed_out.get()
+        }
+    })));
+
+    let recorder = Rc::new(RefCell::new(Recorder {
+        started: Instant::now(),
+        max_entries: limits.max_entries,
+        logged: 0,
+        timed_out: timed_out.clone(),
+        stream,
+        rejected: Vec::new(),
+        output: RunOutput::default(),
+    }));
+
+    // Also called for the module, when it throws
+    runtime.set_host_promise_rejection_tracker(Some(Box::new({
+        let recorder = recorder.clone();
+        move |ctx, promise, reason, is_handled| {
+            let mut recorder = recorder.borrow_mut();
+            if is_handled {
+                recorder.rejection_handled(&ctx, promise);
+            } else {
+                recorder.rejection(&ctx, promise, reason);
+            }
+        }
+    })));
+
+    let context = Context::full(&runtime)?;
+    let (run_timer, evaluated) = context.with(|ctx| -> rquickjs::Result<_> {
+        let prelude: Function = ctx.eval(PRELUDE)?;
+        let log = Function::new(ctx.clone(), {
+            let recorder = recorder.clone();
+            move |method: String, args: Vec<String>| recorder.borrow_mut().log(&method, args)
+        })?;
+        let uncaught = Function::new(ctx.clone(), {
+            let recorder = recorder.clone();
+            move |name: String, message: String, stack: String| {
+                recorder.borrow_mut().error(UncaughtError {
+                    name,
+                    message,
+                    stack: Some(stack).filter(|stack| !stack.is_empty()),
+                })
+            }
+        })?;
+        let run_timer: Function = prelude.call((log, uncaught))?;
+
+        // Errors thrown by the module reach the rejection tracker; this only catches those
+        // that stop it from being evaluated at all, like syntax errors
+        let evaluated = match &input.tests {
+            None => Module::declare(ctx.clone(), "page.js", input.page.as_str())
+                .and_then(|module| module.eval())
+                .map(|_| ()),
+            // Declared modules are found by name when imported, and evaluated then
+            Some(tests) => ctx
+                .eval::<(), _>(TEST_RUNNER)
+                .and_then(|_| Module::declare(ctx.clone(), "page.js", input.page.as_str()))
+                .and_then(|_| Module::declare(ctx.clone(), "page.test.js", tests.as_str()))
+                .and_then(|_| Module::declare(ctx.clone(), "ject:tests", TESTS_MAIN))
+                .and_then(|module| module.eval())
+                .map(|_| ()),
+        }
+        .catch(&ctx)
+        .map_err(|err| recorder.borrow_mut().error(caught_error(err)))
+        .is_ok();
+        Ok((Persistent::save(&ctx, run_timer), evaluated))
+    })?;
+
+    // Jobs (promise callbacks) first, then the next timer, until there are neither
+    let mut running = evaluated;
+    while running {
+        while !timed_out.get() {
+            match runtime.execute_pending_job() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(job) => job.0.with(|ctx| {
+                    recorder.borrow_mut().error(uncaught_error(ctx.catch()));
+                }),
+            }
+        }
+        if timed_out.get() || Instant::now() >= deadline {
+            timed_out.set(true);
+            break;
+        }
+
+        running = context.with(|ctx| {
+            let ran = run_timer
+                .clone()
+                .restore(&ctx)
+                .and_then(|run_timer| run_timer.call::<_, bool>(()))
+                .catch(&ctx);
+            ran.unwrap_or_else(|err| {
+                recorder.borrow_mut().error(caught_error(err));
+                false
+            })
+        });
+    }
+
+    // Whatever finished, even if the run was stopped, which would also stop reading them
+    let tests = input.tests.as_ref().and_then(|_| {
+        runtime.set_interrupt_handler(None);
+        context.with(|ctx| test_report(&ctx))
+    });
+
+    // Values kept outside the engine must be freed before it is
+    drop(run_timer);
+    let mut recorder = recorder.borrow_mut();
+    recorder.rejected.clear();
+
+    let mut output = std::mem::take(&mut recorder.output);
+    output.timed_out = timed_out.get();
+    output.tests = tests;
+    Ok(output)
+}
+
+fn test_report(ctx: &Ctx<'_>) -> Option<TestReport> {
+    let tests: Object = ctx.globals().get("__jectTests").ok()?;
+    let state: Value = tests.get("state").ok()?;
+    let json = ctx.json_stringify(state).ok()??.to_string().ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_input(page: &str, tests: Option<&str>) -> RunOutput {
+        let input = RunInput {
+            page: page.to_owned(),
+            tests: tests.map(str::to_owned),
+        };
+        let limits = RunLimits {
+            time: Duration::from_millis(200),
+            memory_bytes: 8 * 1024 * 1024,
+            max_entries: 10,
+        };
+        evaluate(&input, limits, None).unwrap()
+    }
+
+    fn run_code(code: &str) -> RunOutput {
+        run_input(code, None)
+    }
+
+    fn logged(output: &RunOutput) -> Vec<String> {
+        let entries = output.entries.iter();
+        entries.map(|entry| entry.args.join(" ")).collect()
+    }
+
+    #[test]
+    fn captures_console_output_in_order() {
+        let output = run_code(
+            r#"
+            setTimeout(() => console.log('later'), 50);
+            setTimeout(() => console.log('sooner'), 10);
+            Promise.resolve().then(() => console.info('then'));
+            console.log('hi', 1, { a: [1, 'x'] }, null);
+            await null;
+            console.warn('after await');
+            "#,
+        );
+        assert_eq!(
+            logged(&output),
+            [
+                "hi 1 { a: [ 1, 'x' ] } null",
+                "then",
+                "after await",
+                "sooner",
+                "later"
+            ]
+        );
+        assert_eq!(output.entries[1].method, LogMethod::Info);
+        assert!(output.errors.is_empty());
+        assert!(!output.timed_out);
+
+        let output = run_code("for (let i = 0; i < 20; i++) console.log(i);");
+        assert_eq!(output.entries.len(), 10);
+        assert!(output.truncated);
+    }
+
+    #[test]
+    fn reports_uncaught_errors() {
+        let output = run_code(
+            r#"
+            setTimeout(() => { throw new TypeError('in a timer'); });
+            Promise.reject(new Error('unhandled'));
+            Promise.reject(new Error('handled')).catch(() => {});
+            console.log('before');
+            document.title = 'x';
+            "#,
+        );
+        assert_eq!(logged(&output), ["before"]);
+        let errors: Vec<_> = output
+            .errors
+            .iter()
+            .map(|error| format!("{}: {}", error.name, error.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Error: unhandled",
+                "ReferenceError: document is not defined",
+                "TypeError: in a timer",
+            ]
+        );
+
+        let output = run_code("let x = ;");
+        assert_eq!(output.errors[0].name, "SyntaxError");
+    }
+
+    #[test]
+    fn stops_at_the_limits() {
+        let output = run_code("console.log('start'); while (true) {}");
+        assert_eq!(logged(&output), ["start"]);
+        assert!(output.timed_out);
+        assert!(output.errors.is_empty());
+
+        let output = run_code("setInterval(() => {}, 0);");
+        assert!(output.timed_out);
+    }
+
+    #[test]
+    fn runs_tests_against_page_js() {
+        let page = "export const add = (a, b) => a + b; console.log('page');";
+        let output = run_input(
+            page,
+            Some(
+                r#"
+                import { add } from './page.js';
+                describe('add', () => {
+                    it('adds', () => expect(add(1, 2)).toBe(3));
+                    it('compares deeply', () => expect({ a: [add(1, 1)] }).toEqual({ a: [2] }));
+                    it('fails', async () => {
+                        await new Promise((resolve) => setTimeout(resolve, 10));
+                        expect(add(1, 1)).not.toBe(2);
+                    });
+                    it.skip('skips', () => {});
+                });
+                test('never settles', () => new Promise(() => {}));
+                "#,
+            ),
+        );
+        assert_eq!(logged(&output), ["page"]);
+        let tests = output.tests.unwrap();
+        let results: Vec<_> = tests
+            .results
+            .iter()
+            .map(|r| (r.suite.join(" "), r.name.as_str(), r.status.name()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("add".to_owned(), "adds", "passed"),
+                ("add".to_owned(), "compares deeply", "passed"),
+                ("add".to_owned(), "fails", "failed"),
+                ("add".to_owned(), "skips", "skipped"),
+                ("".to_owned(), "never settles", "failed"),
+            ]
+        );
+        let failure = tests.results[2].failure.as_deref().unwrap();
+        assert!(failure.starts_with("AssertionError: Expected 2 not to be 2"));
+        let failure = tests.results[4].failure.as_deref().unwrap();
+        assert!(failure.starts_with("Error: Timed out after 5000 ms"));
+
+        let output = run_input(page, Some("import { nope } from './page.js';"));
+        assert!(output.tests.unwrap().results.is_empty());
+        assert_eq!(output.errors[0].name, "SyntaxError");
+    }
+}
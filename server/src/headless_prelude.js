@@ -0,0 +1,112 @@
+// Globals for running page.js without a browser, see headless.rs. Called with the functions
+// that record console output and uncaught errors; returns one that runs the next timer.
+(function (log, uncaught) {
+  const MAX_DEPTH = 4;
+
+  const isIdentifier = (key) => /^[A-Za-z_$][\w$]*$/.test(key);
+
+  // Roughly what object-inspect gives the browser console
+  const inspect = (value, depth, seen) => {
+    switch (typeof value) {
+      case 'string':
+        return depth ? `'${value.replace(/\\/g, '\\\\').replace(/'/g, "\\'")}'` : value;
+      case 'function':
+        return `[Function${value.name ? `: ${value.name}` : ' (anonymous)'}]`;
+      case 'bigint':
+        return `${value}n`;
+      case 'object':
+        break;
+      default:
+        return String(value);
+    }
+    if (value === null) return 'null';
+    if (seen.includes(value)) return '[Circular]';
+    if (value instanceof Error) return `[${value.name}: ${value.message}]`;
+    if (value instanceof Date) return value.toISOString();
+    if (value instanceof RegExp) return String(value);
+
+    const nested = (item) => inspect(item, depth + 1, [...seen, value]);
+    if (Array.isArray(value)) {
+      if (depth >= MAX_DEPTH) return '[Array]';
+      return value.length ? `[ ${value.map(nested).join(', ')} ]` : '[]';
+    }
+    if (value instanceof Map) {
+      const items = [...value].map(([k, v]) => `${nested(k)} => ${nested(v)}`);
+      return `Map (${value.size}) {${items.length ? ` ${items.join(', ')} ` : ''}}`;
+    }
+    if (value instanceof Set) {
+      const items = [...value].map(nested);
+      return `Set (${value.size}) {${items.length ? ` ${items.join(', ')} ` : ''}}`;
+    }
+    if (depth >= MAX_DEPTH) return '[Object]';
+    const props = Object.keys(value).map(
+      (key) => `${isIdentifier(key) ? key : `'${key}'`}: ${nested(value[key])}`,
+    );
+    return props.length ? `{ ${props.join(', ')} }` : '{}';
+  };
+
+  // The same limits as the frame's console.js
+  const format = (arg) =>
+    typeof arg === 'string'
+      ? arg.slice(0, 1024 * 16)
+      : inspect(arg, 0, []).slice(0, 1024 * 32);
+
+  const console = {};
+  for (const method of ['log', 'info', 'warn', 'error']) {
+    console[method] = (...args) => log(method, args.map(format));
+  }
+  console.debug = console.log;
+  globalThis.console = console;
+
+  const report = (error) =>
+    error instanceof Error
+      ? uncaught(error.name, error.message, error.stack || '')
+      : uncaught('Uncaught', format(error), '');
+
+  // Timers run one after another in order of when they're due, without actually waiting
+  let now = 0;
+  let nextId = 1;
+  const timers = new Map();
+  const schedule = (fn, delay, args, repeat) => {
+    if (typeof fn !== 'function') {
+      throw new TypeError('Timer callbacks must be functions');
+    }
+    const id = nextId++;
+    delay = Math.max(0, Number(delay) || 0);
+    timers.set(id, { id, fn, args, delay, at: now + delay, repeat });
+    return id;
+  };
+  globalThis.setTimeout = (fn, delay, ...args) => schedule(fn, delay, args, false);
+  globalThis.setInterval = (fn, delay, ...args) => schedule(fn, delay, args, true);
+  globalThis.clearTimeout = (id) => {
+    timers.delete(id);
+  };
+  globalThis.clearInterval = globalThis.clearTimeout;
+  globalThis.queueMicrotask = (fn) => {
+    Promise.resolve().then(fn).catch(report);
+  };
+
+  return () => {
+    let next = null;
+    for (const timer of timers.values()) {
+      if (!next || timer.at < next.at) next = timer;
+    }
+    if (!next) return false;
+
+    now = next.at;
+    if (next.repeat) {
+      // Move to the back of the queue, so intervals of 0 don't starve other timers
+      timers.delete(next.id);
+      next.at = now + Math.max(next.delay, 1);
+      timers.set(next.id, next);
+    } else {
+      timers.delete(next.id);
+    }
+    try {
+      next.fn(...next.args);
+    } catch (error) {
+      report(error);
+    }
+    return true;
+  };
+});
@@ -1,8 +1,17 @@
-use actix_web::{dev::Payload, FromRequest, HttpRequest};
-use futures::future::{ready, Ready};
+use crate::{
+    env,
+    http_error::{ErrorMime, HttpError},
+};
+use actix_web::{
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::HeaderMap,
+    FromRequest, HttpRequest,
+};
+use futures::future::{ready, Either, Ready};
+use std::task::{Context, Poll};
 
-fn get_header(req: &HttpRequest, header: &str) -> Option<String> {
-    req.headers()
+fn get_header(headers: &HeaderMap, header: &str) -> Option<String> {
+    headers
         .get(header)
         .and_then(|h| h.to_str().ok())
         .map(|h| h.to_owned())
@@ -11,23 +20,27 @@ fn get_header(req: &HttpRequest, header: &str) -> Option<String> {
 #[derive(Debug, Clone)]
 pub struct Host {
     normal: Option<String>,
-    forwarded: Option<String>,
 }
 
 impl Host {
-    pub fn normal(&self) -> Option<&str> {
-        self.normal.as_deref()
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            normal: get_header(headers, "host"),
+        }
     }
 
-    pub fn forwarded(&self) -> Option<&str> {
-        self.forwarded.as_deref()
+    pub fn normal(&self) -> Option<&str> {
+        self.normal.as_deref()
     }
 
+    /// Compare the `Host` header (ignoring the port) with `domain`. Forwarded hosts aren't
+    /// considered since nginx passes the original `Host` through, and clients can send their
+    /// own `Forwarded` headers.
     pub fn matches(&self, domain: &str) -> bool {
         let host = self.normal().and_then(|h| h.split(':').next());
         match host {
             Some(host) => host.eq_ignore_ascii_case(domain),
-            None => return false,
+            None => false,
         }
     }
 }
@@ -41,9 +54,140 @@ impl FromRequest for Host {
 
     fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
         // println!("Headers: {:?}", req.headers());
-        ready(Ok(Self {
-            normal: get_header(req, "host"),
-            forwarded: get_header(req, "x-forwarded-for"),
+        ready(Ok(Self::from_headers(req.headers())))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Domain {
+    /// The editor and its API, e.g. ject.dev
+    Main,
+    /// User code, e.g. ject.link
+    Frame,
+}
+
+impl Domain {
+    pub fn name(self) -> String {
+        match self {
+            Domain::Main => env::domain_main(),
+            Domain::Frame => env::domain_frame(),
+        }
+    }
+}
+
+/// Middleware that pins a route to one domain, rejecting requests for any other Host with
+/// [HttpError::invalid_host]. This keeps user code on the frame domain from calling the main
+/// API same-origin, and the frame routes from being served as ject.dev content.
+///
+/// Use with the route macros, e.g. `#[get("/path", wrap = "http::MAIN_API")]`
+#[derive(Debug, Clone, Copy)]
+pub struct RequireDomain {
+    pub domain: Domain,
+    /// Format of the error response
+    pub mime: ErrorMime,
+}
+
+pub const MAIN_API: RequireDomain = RequireDomain {
+    domain: Domain::Main,
+    mime: ErrorMime::Json,
+};
+pub const FRAME_API: RequireDomain = RequireDomain {
+    domain: Domain::Frame,
+    mime: ErrorMime::Json,
+};
+pub const FRAME_HTML: RequireDomain = RequireDomain {
+    domain: Domain::Frame,
+    mime: ErrorMime::Html,
+};
+pub const FRAME_JS: RequireDomain = RequireDomain {
+    domain: Domain::Frame,
+    mime: ErrorMime::JavaScript,
+};
+pub const FRAME_CSS: RequireDomain = RequireDomain {
+    domain: Domain::Frame,
+    mime: ErrorMime::Css,
+};
+
+impl<S, B> Transform<S> for RequireDomain
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequireDomainMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireDomainMiddleware {
+            service,
+            require: *self,
         }))
     }
 }
+
+pub struct RequireDomainMiddleware<S> {
+    service: S,
+    require: RequireDomain,
+}
+
+impl<S, B> Service for RequireDomainMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let domain = self.require.domain.name();
+        if Host::from_headers(req.headers()).matches(&domain) {
+            Either::Left(self.service.call(req))
+        } else {
+            let err = HttpError::invalid_host(&domain).with_mime(self.require.mime);
+            Either::Right(ready(Err(err.into())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{dev::Service, test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn forwarded_host_cannot_pass_require_domain() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/")
+                    .wrap(MAIN_API)
+                    .route(web::get().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        let main = Domain::Main.name();
+        let request = |host: &str| {
+            test::TestRequest::get()
+                .uri("/")
+                .peer_addr("127.0.0.1:50000".parse().unwrap())
+                .header("host", host)
+                .header("forwarded", format!("for=198.51.100.7;host={}", main))
+                .to_request()
+        };
+
+        let res = test::call_service(&mut app, request(&main)).await;
+        assert!(res.status().is_success());
+
+        // e.g. a fetch from user code on the frame domain
+        let res = app.call(request(&Domain::Frame.name())).await;
+        assert!(res.is_err());
+    }
+}
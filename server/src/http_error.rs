@@ -3,7 +3,7 @@ use std::{borrow::Cow, fmt::Display};
 use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use serde_json::{json, to_string};
 
-use crate::db::DbError;
+use crate::{db::DbError, rate_limit::RateLimited};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorMime {
@@ -23,6 +23,8 @@ pub struct HttpError {
     pub status: StatusCode,
     /// Error code, for use in code and bug reports
     pub code: Cow<'static, str>,
+    /// Seconds for the `retry-after` header of rate limited responses
+    pub retry_after: Option<u64>,
 
     /// A mime is required if you want to use this as an actix Err type
     pub mime: Option<ErrorMime>,
@@ -43,22 +45,6 @@ impl IntoCowStr for String {
 }
 
 impl HttpError {
-    pub fn session_not_found(mime: ErrorMime) -> Self {
-        Self {
-            title: "Unknown session".cow(),
-            message: "Please try reloading the page".cow(),
-            code: "inject_session_not_found".cow(),
-            // Not sending error as it causes the asset to not be used
-            // An error response on CSS/JS resources will cause it to display strangely
-            status: if mime == ErrorMime::Html {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::OK
-            },
-            mime: None,
-        }
-    }
-
     pub fn file_not_found(mime: ErrorMime) -> Self {
         Self {
             title: match mime {
@@ -76,6 +62,7 @@ impl HttpError {
             } else {
                 StatusCode::OK
             },
+            retry_after: None,
             mime: None,
         }
     }
@@ -88,6 +75,7 @@ impl HttpError {
             message: format!("Expected the domain to be {}", expected).cow(),
             code: "invalid_host".cow(),
             status: StatusCode::UNPROCESSABLE_ENTITY,
+            retry_after: None,
             mime: None,
         }
     }
@@ -98,6 +86,18 @@ impl HttpError {
             message: format!("Invalid HTML Provided\n\nReason:\n{}", error).cow(),
             code: "inject_invalid_html".cow(),
             status: StatusCode::UNPROCESSABLE_ENTITY,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn invalid_deps(error: impl Display) -> Self {
+        Self {
+            title: "Invalid deps.json Provided".cow(),
+            message: format!("Unable to build the import map\n\nReason:\n{}", error).cow(),
+            code: "inject_invalid_deps".cow(),
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            retry_after: None,
             mime: None,
         }
     }
@@ -112,6 +112,7 @@ impl HttpError {
             .cow(),
             code: "inject_failed_html_generation".cow(),
             status: StatusCode::UNPROCESSABLE_ENTITY,
+            retry_after: None,
             mime: None,
         }
     }
@@ -122,6 +123,62 @@ impl HttpError {
             message: format!("Reason:\n{}", error).cow(),
             code: "js_compile_fail".cow(),
             status: StatusCode::OK,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn headless_run_fail(error: impl Display) -> Self {
+        Self {
+            title: "Unable to Run JavaScript".cow(),
+            message: format!("Reason:\n{}", error).cow(),
+            code: "headless_run_fail".cow(),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn saved_gone() -> Self {
+        Self {
+            title: "Save Deleted".cow(),
+            message: "This save has been deleted".cow(),
+            code: "saved_gone".cow(),
+            status: StatusCode::GONE,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn vendor_fail(error: impl Display) -> Self {
+        Self {
+            title: "Unable to Download Dependencies".cow(),
+            message: format!("Reason:\n{}", error).cow(),
+            code: "export_vendor_fail".cow(),
+            status: StatusCode::BAD_GATEWAY,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn export_archive_fail(error: impl Display) -> Self {
+        Self {
+            title: "Unable to Create Archive".cow(),
+            message: format!("Reason:\n{}", error).cow(),
+            code: "export_archive_fail".cow(),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
+            mime: None,
+        }
+    }
+
+    pub fn rate_limited(limited: RateLimited) -> Self {
+        Self {
+            title: "Too Many Requests".cow(),
+            message: limited.to_string().cow(),
+            code: "rate_limited".cow(),
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(limited.retry_after),
             mime: None,
         }
     }
@@ -135,6 +192,7 @@ impl HttpError {
             message,
             code,
             status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: None,
             mime: None,
         }
     }
@@ -219,7 +277,12 @@ impl HttpError {
             "#
         );
 
-        HttpResponse::build(self.status)
+        let mut response = HttpResponse::build(self.status);
+        if let Some(retry_after) = self.retry_after {
+            response.header("retry-after", retry_after.to_string());
+        }
+
+        response
             .header(
                 "content-type",
                 match mime {
@@ -0,0 +1,130 @@
+//! In-process pub/sub of session changes, for the frame's live reload (see
+//! [crate::api::frame::r_get_session_events]). Subscribers on any worker thread get every event
+//! published for their session after they subscribed.
+
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Stream,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// Sent first on each event stream with the session's revision at the time, so a page
+    /// that was loaded before an update can tell it missed it. Never published.
+    Connected { revision: i64 },
+    /// The session was updated. If `compiling`, page.js is being compiled and a
+    /// [SessionEvent::CompileFinished] for the same revision follows.
+    FilesChanged { revision: i64, compiling: bool },
+    /// page.js of the revision was compiled, or failed to with `error`.
+    CompileFinished {
+        revision: i64,
+        error: Option<String>,
+    },
+}
+
+static SUBSCRIBERS: Lazy<Mutex<HashMap<String, Vec<UnboundedSender<SessionEvent>>>>> =
+    Lazy::new(Default::default);
+
+/// The events of a session, until this is dropped, which unsubscribes.
+pub struct Subscription {
+    session_id: String,
+    receiver: UnboundedReceiver<SessionEvent>,
+}
+
+impl Stream for Subscription {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SessionEvent>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Closing the receiver closes its sender, so it's told apart from the others
+        self.receiver.close();
+        let mut subscribers = SUBSCRIBERS
+            .lock()
+            .expect("hub subscribers should never be poisoned");
+
+        if let Some(senders) = subscribers.get_mut(&self.session_id) {
+            senders.retain(|sender| !sender.is_closed());
+            if senders.is_empty() {
+                subscribers.remove(&self.session_id);
+            }
+        }
+    }
+}
+
+/// Receive the events of a session until the subscription is dropped.
+pub fn subscribe(session_id: &str) -> Subscription {
+    let (sender, receiver) = unbounded();
+    SUBSCRIBERS
+        .lock()
+        .expect("hub subscribers should never be poisoned")
+        .entry(session_id.to_owned())
+        .or_default()
+        .push(sender);
+    Subscription {
+        session_id: session_id.to_owned(),
+        receiver,
+    }
+}
+
+/// Whether anything is listening for the session's events.
+pub fn is_subscribed(session_id: &str) -> bool {
+    SUBSCRIBERS
+        .lock()
+        .expect("hub subscribers should never be poisoned")
+        .contains_key(session_id)
+}
+
+/// Send an event to the session's subscribers, forgetting any that went away.
+pub fn publish(session_id: &str, event: SessionEvent) {
+    let mut subscribers = SUBSCRIBERS
+        .lock()
+        .expect("hub subscribers should never be poisoned");
+
+    if let Some(senders) = subscribers.get_mut(session_id) {
+        senders.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+        if senders.is_empty() {
+            subscribers.remove(session_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_to_subscribers_of_the_session() {
+        let mut first = subscribe("hub-test-a");
+        let second = subscribe("hub-test-a");
+        let mut other = subscribe("hub-test-b");
+        drop(second);
+        assert_eq!(SUBSCRIBERS.lock().unwrap()["hub-test-a"].len(), 1);
+
+        let event = SessionEvent::FilesChanged {
+            revision: 2,
+            compiling: false,
+        };
+        publish("hub-test-a", event.clone());
+        assert_eq!(first.receiver.try_next().unwrap(), Some(event));
+        assert!(other.receiver.try_next().is_err());
+
+        // Without another event being published
+        drop(first);
+        assert!(!is_subscribed("hub-test-a"));
+        assert!(is_subscribed("hub-test-b"));
+    }
+}
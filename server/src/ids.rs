@@ -20,3 +20,48 @@ pub fn make_save_id() -> String {
     // Should be long enough for 1k/hr over thousands of years.
     nanoid!(13, BASE58_ALPHA)
 }
+
+/// Generate the secret that authorizes updates to a session. Only its hash is stored.
+pub fn make_edit_token() -> String {
+    nanoid!(32, BASE58_ALPHA)
+}
+
+/// Generate a password salt.
+pub fn make_salt() -> String {
+    nanoid!(22, BASE58_ALPHA)
+}
+
+/// Generate the secret of a browser login, stored in the login cookie.
+pub fn make_login_token() -> String {
+    nanoid!(32, BASE58_ALPHA)
+}
+
+/// Generate a personal API token. The prefix makes leaked tokens easy to search for.
+pub fn make_api_token() -> String {
+    format!("ject_{}", nanoid!(40, BASE58_ALPHA))
+}
+
+/// Generate a user_id.
+pub fn make_user_id() -> String {
+    nanoid!(16, BASE58_ALPHA)
+}
+
+/// Generate the public id of an API token, used to list and revoke it.
+pub fn make_token_id() -> String {
+    nanoid!(12, BASE58_ALPHA)
+}
+
+/// Generate the id of a test run made on the server; the frame picks its own.
+pub fn make_test_run_id() -> String {
+    nanoid!(16, BASE58_ALPHA)
+}
+
+/// Generate the id of a load of a frame page, see [crate::run_token].
+pub fn make_run_id() -> String {
+    nanoid!(16, BASE58_ALPHA)
+}
+
+/// Generate the key that signs run tokens, once per start of the server.
+pub fn make_run_token_key() -> String {
+    nanoid!(43, BASE58_ALPHA)
+}
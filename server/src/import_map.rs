@@ -0,0 +1,165 @@
+use crate::cdn::skypack_src;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// The contents of a session's `deps.json`, mapping a bare import specifier to a version
+/// or a URL. e.g. `{ "react": "17.0.2", "lodash-es": "https://esm.sh/lodash-es@4" }`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DepsManifest {
+    deps: BTreeMap<String, String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DepsError {
+    #[error("deps.json must be a JSON object of package name to version or URL")]
+    Parse { source: serde_json::Error },
+
+    #[error("Invalid package name {:?} in deps.json", name)]
+    InvalidName { name: String },
+
+    #[error("Empty version or URL for package {:?} in deps.json", name)]
+    EmptySpec { name: String },
+}
+
+impl DepsManifest {
+    pub fn parse(json: &str) -> Result<Self, DepsError> {
+        if json.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let manifest: Self =
+            serde_json::from_str(json).map_err(|source| DepsError::Parse { source })?;
+
+        for (name, spec) in &manifest.deps {
+            if name.is_empty() || name.contains(char::is_whitespace) || name.ends_with('/') {
+                return Err(DepsError::InvalidName { name: name.clone() });
+            }
+            if spec.trim().is_empty() {
+                return Err(DepsError::EmptySpec { name: name.clone() });
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Build the `imports` of an import map. Versions resolve to Skypack, and also get a
+    /// trailing-slash entry so that subpath imports like `react-dom/server` work.
+    pub fn to_import_map(&self) -> ImportMap {
+        let mut imports = BTreeMap::new();
+        for (name, spec) in &self.deps {
+            let spec = spec.trim();
+            if is_url(spec) {
+                imports.insert(name.clone(), spec.to_owned());
+            } else {
+                let src = skypack_src(&format!("{}@{}", name, spec));
+                imports.insert(format!("{}/", name), format!("{}/", src));
+                imports.insert(name.clone(), src);
+            }
+        }
+
+        ImportMap { imports }
+    }
+
+    /// The packages given by version, as for a package.json
+    pub fn versions(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.specs().filter(|(_, spec)| !is_url(spec))
+    }
+
+    /// The packages given by URL
+    pub fn urls(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.specs().filter(|(_, spec)| is_url(spec))
+    }
+
+    fn specs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.deps
+            .iter()
+            .map(|(name, spec)| (name.as_str(), spec.trim()))
+    }
+}
+
+fn is_url(spec: &str) -> bool {
+    spec.starts_with("https://") || spec.starts_with("http://") || spec.starts_with('/')
+}
+
+/// See https://github.com/WICG/import-maps
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportMap {
+    pub imports: BTreeMap<String, String>,
+}
+
+impl ImportMap {
+    /// Render as an inline `<script type="importmap">` tag
+    pub fn to_script(&self) -> String {
+        let json = serde_json::to_string(&json!({ "imports": self.imports }))
+            .expect("ject: ImportMap to json")
+            .replace("</", "\\u003c/");
+        format!("<script type=\"importmap\">{}</script>", json)
+    }
+
+    /// The origins of the absolute URLs the map points at, e.g. `https://esm.sh`, for the
+    /// frame's Content-Security-Policy.
+    pub fn origins(&self) -> Vec<String> {
+        let mut origins: Vec<String> = vec![];
+        for url in self.imports.values() {
+            if let Some(origin) = url_origin(url) {
+                if !origins.iter().any(|o| o == origin) {
+                    origins.push(origin.to_owned());
+                }
+            }
+        }
+        origins
+    }
+}
+
+/// The `scheme://host[:port]` of an http(s) URL. None if the host has characters that
+/// don't belong in a CSP source, like whitespace, ';' or userinfo.
+fn url_origin(url: &str) -> Option<&str> {
+    let scheme_len = if url.starts_with("https://") {
+        "https://".len()
+    } else if url.starts_with("http://") {
+        "http://".len()
+    } else {
+        return None;
+    };
+    let host_len = url[scheme_len..]
+        .find(['/', '?', '#'])
+        .unwrap_or(url.len() - scheme_len);
+    let host = &url[scheme_len..scheme_len + host_len];
+    let valid = host
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':');
+    if host.is_empty() || !valid {
+        return None;
+    }
+    Some(&url[..scheme_len + host_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_url_origins() {
+        let deps = DepsManifest::parse(
+            r#"{
+                "a": "https://esm.sh/a@1",
+                "b": "https://esm.sh/b@2?bundle",
+                "c": "http://localhost:8080",
+                "d": "/local/d.js",
+                "e": "https://x.test; script-src *",
+                "f": "1.0.0"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            deps.to_import_map().origins(),
+            [
+                "https://esm.sh",
+                "http://localhost:8080",
+                "https://cdn.skypack.dev"
+            ]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use swc_common::{FileName, SourceMap, Spanned};
+use swc_ecma_ast::{
+    CallExpr, ExportAll, Expr, ExprOrSpread, ExprOrSuper, ImportDecl, Lit, NamedExport,
+};
+use swc_ecma_parser::{lexer::Lexer, JscTarget, Parser, StringInput, Syntax, TsConfig};
+use swc_ecma_visit::{Node, Visit, VisitWith};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportKind {
+    /// `import x from 'a'`
+    Static,
+    /// `export { x } from 'a'` or `export * from 'a'`
+    ReExport,
+    /// `import('a')`
+    Dynamic,
+    /// `require('a')`
+    Require,
+}
+
+/// A module specifier referenced by page.js
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Import {
+    pub specifier: String,
+    pub kind: ImportKind,
+}
+
+/// The result of scanning page.js, as reported by the deps API and cached with the compile
+/// output.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DetectedDeps {
+    pub imports: Vec<Import>,
+    /// Distinct package names of the bare imports, e.g. `react-dom` for `react-dom/server`
+    pub packages: Vec<String>,
+    /// Set if page.js couldn't be parsed, in which case nothing was detected
+    pub error: Option<String>,
+}
+
+impl DetectedDeps {
+    pub fn detect(code: &str) -> Self {
+        match find_imports(code) {
+            Ok(imports) => {
+                let mut packages: Vec<String> = vec![];
+                for import in &imports {
+                    if let Some(name) = package_name(&import.specifier) {
+                        if !packages.iter().any(|p| p == name) {
+                            packages.push(name.to_owned());
+                        }
+                    }
+                }
+                Self {
+                    imports,
+                    packages,
+                    error: None,
+                }
+            }
+            Err(error) => Self {
+                error: Some(error),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ImportVisitor {
+    imports: Vec<Import>,
+}
+
+impl ImportVisitor {
+    fn push(&mut self, specifier: &str, kind: ImportKind) {
+        self.imports.push(Import {
+            specifier: specifier.to_owned(),
+            kind,
+        });
+    }
+}
+
+fn str_arg(args: &[ExprOrSpread]) -> Option<&str> {
+    match args {
+        [ExprOrSpread { spread: None, expr }] => match &**expr {
+            Expr::Lit(Lit::Str(s)) => Some(&*s.value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Visit for ImportVisitor {
+    fn visit_import_decl(&mut self, n: &ImportDecl, _parent: &dyn Node) {
+        if !n.type_only {
+            self.push(&n.src.value, ImportKind::Static);
+        }
+    }
+
+    fn visit_named_export(&mut self, n: &NamedExport, _parent: &dyn Node) {
+        if let (Some(src), false) = (&n.src, n.type_only) {
+            self.push(&src.value, ImportKind::ReExport);
+        }
+    }
+
+    fn visit_export_all(&mut self, n: &ExportAll, _parent: &dyn Node) {
+        self.push(&n.src.value, ImportKind::ReExport);
+    }
+
+    fn visit_call_expr(&mut self, n: &CallExpr, parent: &dyn Node) {
+        if let ExprOrSuper::Expr(callee) = &n.callee {
+            if let Expr::Ident(ident) = &**callee {
+                let kind = match &*ident.sym {
+                    "import" => Some(ImportKind::Dynamic),
+                    "require" => Some(ImportKind::Require),
+                    _ => None,
+                };
+                if let (Some(kind), Some(specifier)) = (kind, str_arg(&n.args)) {
+                    self.push(specifier, kind);
+                }
+            }
+        }
+
+        swc_ecma_visit::visit_call_expr(self, n, parent);
+    }
+}
+
+/// Parse the code (JSX and TypeScript allowed, like ject-compile) and list the module
+/// specifiers it references, in source order.
+pub fn find_imports(code: &str) -> Result<Vec<Import>, String> {
+    let cm = Arc::<SourceMap>::default();
+    let fm = cm.new_source_file(FileName::Custom("page.js".to_owned()), code.to_owned());
+
+    let lexer = Lexer::new(
+        Syntax::Typescript(TsConfig {
+            tsx: true,
+            dynamic_import: true,
+            ..Default::default()
+        }),
+        JscTarget::Es2020,
+        StringInput::from(&*fm),
+        None,
+    );
+
+    let mut parser = Parser::new_from(lexer);
+    let module = parser.parse_module().map_err(|err| {
+        let loc = cm.lookup_char_pos(err.span().lo);
+        format!(
+            "{} at {}:{}",
+            err.into_kind().msg(),
+            loc.line,
+            loc.col_display + 1
+        )
+    })?;
+
+    let mut visitor = ImportVisitor::default();
+    module.visit_with(&module, &mut visitor);
+
+    Ok(visitor.imports)
+}
+
+/// The npm package name of a bare specifier, or None for relative paths and URLs.
+///
+/// `react-dom/server` => `react-dom`, `@babel/core/lib` => `@babel/core`
+pub fn package_name(specifier: &str) -> Option<&str> {
+    if specifier.starts_with('.') || specifier.starts_with('/') || specifier.contains(':') {
+        return None;
+    }
+
+    let mut segments = specifier.splitn(3, '/');
+    let first = segments.next().filter(|s| !s.is_empty())?;
+    if first.starts_with('@') {
+        let second = segments.next().filter(|s| !s.is_empty())?;
+        Some(&specifier[..first.len() + 1 + second.len()])
+    } else {
+        Some(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_all_import_kinds() {
+        let code = r#"
+import React from 'react';
+import type { Props } from './types';
+export * from 'lodash-es';
+const el = <div />;
+const server = require('react-dom/server');
+import('@scope/pkg/sub').then(() => {});
+"#;
+        let deps = DetectedDeps::detect(code);
+        assert_eq!(deps.error, None);
+        let found: Vec<_> = deps
+            .imports
+            .iter()
+            .map(|i| (i.specifier.as_str(), i.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("react", ImportKind::Static),
+                ("lodash-es", ImportKind::ReExport),
+                ("react-dom/server", ImportKind::Require),
+                ("@scope/pkg/sub", ImportKind::Dynamic),
+            ]
+        );
+        assert_eq!(
+            deps.packages,
+            vec!["react", "lodash-es", "react-dom", "@scope/pkg"]
+        );
+    }
+
+    #[test]
+    fn package_names() {
+        assert_eq!(package_name("react"), Some("react"));
+        assert_eq!(package_name("@babel/core/lib/x.js"), Some("@babel/core"));
+        assert_eq!(package_name("./local.js"), None);
+        assert_eq!(package_name("https://cdn.skypack.dev/react"), None);
+        assert_eq!(package_name("@scope"), None);
+    }
+}
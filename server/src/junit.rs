@@ -0,0 +1,149 @@
+//! JUnit XML for a session's test run, for `GET /api/session/{id}/tests.xml`.
+//!
+//! The run is one `<testsuite>` named after page.test.js; each test's `classname` is the
+//! names of its `describe()` blocks. An error that stopped the run is reported as an extra
+//! test case with an `<error>`, which is how most CI tools expect to see them.
+
+use crate::state::{TestRun, TestStatus};
+use std::fmt::Write;
+
+const SUITE_NAME: &str = "page.test.js";
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            // Not allowed in XML 1.0 at all, even escaped
+            c if (c as u32) < 0x20 || c == '\u{fffe}' || c == '\u{ffff}' => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn seconds(ms: f64) -> String {
+    format!("{:.3}", ms.max(0.0) / 1000.0)
+}
+
+/// Render a test run, or an empty report if the tests haven't been run.
+pub fn to_junit_xml(run: Option<&TestRun>) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let run = match run {
+        Some(run) => run,
+        None => {
+            xml.push_str("<testsuites tests=\"0\" failures=\"0\" errors=\"0\" skipped=\"0\"/>\n");
+            return xml;
+        }
+    };
+
+    let errors = run.error.is_some() as usize;
+    let tests = run.results.len() + errors;
+    let counts = format!(
+        "tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{}\"",
+        tests,
+        run.failed,
+        errors,
+        run.skipped,
+        seconds(run.duration)
+    );
+    // Writing to a String can't fail
+    let _ = writeln!(xml, "<testsuites {}>", counts);
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" {} id=\"{}\">",
+        SUITE_NAME,
+        counts,
+        escape(&run.run_id)
+    );
+
+    for result in &run.results {
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{}\"",
+            escape(&result.suite.join(" › ")),
+            escape(&result.name),
+            seconds(result.duration)
+        );
+        match result.status {
+            TestStatus::Passed => xml.push_str("/>\n"),
+            TestStatus::Skipped => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+            TestStatus::Failed => {
+                let failure = result.failure.as_deref().unwrap_or_default();
+                let message = failure.lines().next().unwrap_or_default();
+                let _ = write!(
+                    xml,
+                    ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                    escape(message),
+                    escape(failure)
+                );
+            }
+        }
+    }
+
+    if let Some(error) = &run.error {
+        let message = error.lines().next().unwrap_or_default();
+        let _ = write!(
+            xml,
+            "    <testcase classname=\"\" name=\"{}\" time=\"0.000\">\n      <error message=\"{}\">{}</error>\n    </testcase>\n",
+            SUITE_NAME,
+            escape(message),
+            escape(error)
+        );
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{TestResult, TestSource};
+
+    #[test]
+    fn renders_results_and_escapes() {
+        let result = |name: &str, status, failure: Option<&str>| TestResult {
+            suite: vec!["math".to_owned(), "<add>".to_owned()],
+            name: name.to_owned(),
+            status,
+            duration: 12.0,
+            failure: failure.map(str::to_owned),
+        };
+        let run = TestRun::new(
+            "r1".to_owned(),
+            TestSource::Headless,
+            0,
+            1500.0,
+            Some("Timed out\u{1}".to_owned()),
+            vec![
+                result("a & b", TestStatus::Passed, None),
+                result("fails", TestStatus::Failed, Some("Error: \"x\"\n  at y")),
+                result("later", TestStatus::Skipped, None),
+            ],
+        );
+        let xml = to_junit_xml(Some(&run));
+
+        assert!(xml.contains(
+            r#"<testsuites tests="4" failures="1" errors="1" skipped="1" time="1.500">"#
+        ));
+        assert!(xml.contains(
+            r#"<testcase classname="math › &lt;add&gt;" name="a &amp; b" time="0.012"/>"#
+        ));
+        assert!(xml.contains(
+            r#"<failure message="Error: &quot;x&quot;">Error: &quot;x&quot;
+  at y</failure>"#
+        ));
+        assert!(xml.contains("<skipped/>"));
+        assert!(xml.contains("<error message=\"Timed out\u{fffd}\">"));
+
+        let empty = to_junit_xml(None);
+        assert!(empty
+            .ends_with("<testsuites tests=\"0\" failures=\"0\" errors=\"0\" skipped=\"0\"/>\n"));
+    }
+}
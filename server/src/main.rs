@@ -1,14 +1,30 @@
 mod api;
+mod archive;
 mod cdn;
+mod collab;
 mod compile_service;
+mod csp;
 mod db;
 mod env;
+mod forwarded;
+mod hash;
+mod headless;
 mod http;
 mod http_error;
+mod hub;
 mod ids;
+mod import_map;
+mod imports;
+mod junit;
 // mod js;
+mod ot;
 mod parser;
+mod password;
+mod patch;
+mod rate_limit;
+mod run_token;
 mod state;
+mod templates;
 
 use actix_files as fs;
 
@@ -26,12 +42,14 @@ use ov::*;
 
 use crate::db::Db;
 
-async fn r_index() -> impl Responder {
-    let html = r##"<!DOCTYPE html>
+fn index_html(title: &str, meta: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
 <html>
     <head>
         <meta charset="utf-8" />
-        <title>ject.dev</title>
+        <title>{title}</title>
+        {meta}
         <script>
             // Used by global-init.js
             window.PUBLIC_PATH = '/dist/';
@@ -51,7 +69,24 @@ async fn r_index() -> impl Responder {
     <body>
         <div id="root"></div>
     </body>
-</html>"##;
+</html>"##,
+        title = html_escape::encode_text(title),
+        meta = meta
+    )
+}
+
+async fn r_index() -> impl Responder {
+    HttpResponse::Ok()
+        .header("content-type", "text/html")
+        .body(index_html("ject.dev", ""))
+}
+
+/// The editor, with the save's title and unfurl tags (see [api::unfurl_head]).
+async fn r_saved(info: actix_web::web::Path<String>) -> impl Responder {
+    let html = match api::unfurl_head(&info.0).await {
+        Some((title, meta)) => index_html(&title, &meta),
+        None => index_html("ject.dev", ""),
+    };
     HttpResponse::Ok()
         .header("content-type", "text/html")
         .body(html)
@@ -97,11 +132,18 @@ async fn r_dist(req: HttpRequest) -> Result<HttpResponse, SendRequestError> {
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
+    // The server runs page.js in a copy of itself, see [headless::run]
+    if std::env::args().nth(1).as_deref() == Some(headless::CHILD_ARG) {
+        return headless::child_main();
+    }
+
     env_logger::init();
 
     Db::open_env().await?.create_tables().await?;
     println!("Created tables");
 
+    api::spawn_jobs();
+
     // let domain_main = env::domain_main();
     // let domain_frame = env::domain_frame();
 
@@ -117,6 +159,8 @@ async fn main() -> anyhow::Result<()> {
             .wrap(logger)
             .route("/", actix_web::web::get().to(r_index))
             .route("/new/{templateName}", actix_web::web::get().to(r_index))
+            .route("/collab/{session_id}", actix_web::web::get().to(r_index))
+            .route("/saved/{save_id}", actix_web::web::get().to(r_saved))
             .service(r_favicon)
             .over(|app| {
                 if env::is_production() {
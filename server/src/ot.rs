@@ -0,0 +1,392 @@
+//! Operational transformation of plain text, for collaborative editing (see [crate::collab]).
+//!
+//! An [Operation] walks the whole document, retaining, inserting or deleting text as it goes.
+//! Lengths are in UTF-16 code units, like JavaScript string indices and the editor's offsets.
+//! On the wire it's the ot.js format: an array where a positive number retains that many
+//! units, a negative number deletes that many, and a string is inserted.
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawComponent", into = "RawComponent")]
+pub enum Component {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawComponent {
+    Count(i64),
+    Text(String),
+}
+
+impl TryFrom<RawComponent> for Component {
+    type Error = &'static str;
+
+    fn try_from(raw: RawComponent) -> Result<Self, Self::Error> {
+        match raw {
+            RawComponent::Count(0) => Err("operation components can't be 0"),
+            RawComponent::Count(n) if n > 0 => Ok(Component::Retain(n as usize)),
+            RawComponent::Count(n) => Ok(Component::Delete(n.unsigned_abs() as usize)),
+            RawComponent::Text(text) if text.is_empty() => {
+                Err("operation components can't be empty")
+            }
+            RawComponent::Text(text) => Ok(Component::Insert(text)),
+        }
+    }
+}
+
+impl From<Component> for RawComponent {
+    fn from(component: Component) -> Self {
+        match component {
+            Component::Retain(n) => RawComponent::Count(n as i64),
+            Component::Insert(text) => RawComponent::Text(text),
+            Component::Delete(n) => RawComponent::Count(-(n as i64)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Operation {
+    components: Vec<Component>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum OtError {
+    #[error("The operation expects a document of length {expected}, it's {actual}")]
+    LengthMismatch { expected: usize, actual: usize },
+
+    #[error("The operation splits a character at offset {0}")]
+    SplitsCharacter(usize),
+
+    #[error("The operations weren't made against the same document")]
+    Incompatible,
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Split off the first `units` UTF-16 code units of `text`, returning them and the rest.
+fn split_utf16(text: &str, units: usize) -> Option<(&str, &str)> {
+    let mut counted = 0;
+    for (index, c) in text.char_indices() {
+        if counted == units {
+            return Some(text.split_at(index));
+        }
+        if counted > units {
+            return None;
+        }
+        counted += c.len_utf16();
+    }
+    if counted == units {
+        Some((text, ""))
+    } else {
+        None
+    }
+}
+
+impl Operation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of the document the operation applies to.
+    pub fn base_len(&self) -> usize {
+        self.components
+            .iter()
+            .map(|component| match component {
+                Component::Retain(n) | Component::Delete(n) => *n,
+                Component::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    pub fn retain(mut self, n: usize) -> Self {
+        if n == 0 {
+            return self;
+        }
+        match self.components.last_mut() {
+            Some(Component::Retain(last)) => *last += n,
+            _ => self.components.push(Component::Retain(n)),
+        }
+        self
+    }
+
+    pub fn insert(mut self, text: &str) -> Self {
+        if text.is_empty() {
+            return self;
+        }
+        // Keep inserts before deletes, so equivalent operations compare equal
+        let len = self.components.len();
+        match self.components.as_mut_slice() {
+            [.., Component::Insert(last)] => last.push_str(text),
+            [.., Component::Insert(before), Component::Delete(_)] => before.push_str(text),
+            [.., Component::Delete(_)] => {
+                self.components
+                    .insert(len - 1, Component::Insert(text.to_owned()));
+            }
+            _ => self.components.push(Component::Insert(text.to_owned())),
+        }
+        self
+    }
+
+    pub fn delete(mut self, n: usize) -> Self {
+        if n == 0 {
+            return self;
+        }
+        match self.components.last_mut() {
+            Some(Component::Delete(last)) => *last += n,
+            _ => self.components.push(Component::Delete(n)),
+        }
+        self
+    }
+
+    /// An operation turning `old` into `new`, by replacing what's between their common
+    /// prefix and suffix.
+    pub fn diff(old: &str, new: &str) -> Self {
+        let prefix: usize = old
+            .chars()
+            .zip(new.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+        let (old_rest, new_rest) = (&old[prefix..], &new[prefix..]);
+        let suffix: usize = old_rest
+            .chars()
+            .rev()
+            .zip(new_rest.chars().rev())
+            .take_while(|(a, b)| a == b)
+            .map(|(c, _)| c.len_utf8())
+            .sum();
+        let (deleted, kept) = old_rest.split_at(old_rest.len() - suffix);
+
+        Operation::new()
+            .retain(utf16_len(&old[..prefix]))
+            .insert(&new_rest[..new_rest.len() - suffix])
+            .delete(utf16_len(deleted))
+            .retain(utf16_len(kept))
+    }
+
+    /// Apply the operation to a document, which must be [Operation::base_len] long.
+    pub fn apply(&self, text: &str) -> Result<String, OtError> {
+        let actual = utf16_len(text);
+        if actual != self.base_len() {
+            return Err(OtError::LengthMismatch {
+                expected: self.base_len(),
+                actual,
+            });
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        let mut offset = 0;
+        for component in &self.components {
+            match component {
+                Component::Retain(n) | Component::Delete(n) => {
+                    let (taken, after) =
+                        split_utf16(rest, *n).ok_or(OtError::SplitsCharacter(offset + n))?;
+                    if let Component::Retain(_) = component {
+                        out.push_str(taken);
+                    }
+                    rest = after;
+                    offset += n;
+                }
+                Component::Insert(inserted) => out.push_str(inserted),
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Where an offset into the base document ends up after the operation. Text inserted at
+    /// the offset goes after it.
+    pub fn transform_index(&self, index: usize) -> usize {
+        let mut new_index = index;
+        let mut offset = 0;
+        for component in &self.components {
+            if offset >= index {
+                break;
+            }
+            match component {
+                Component::Retain(n) => offset += n,
+                Component::Insert(text) => new_index += utf16_len(text),
+                Component::Delete(n) => {
+                    new_index -= (*n).min(index - offset);
+                    offset += n;
+                }
+            }
+        }
+        new_index
+    }
+
+    /// Transform two operations made against the same document into `(a', b')`, so that
+    /// applying `a` then `b'` gives the same document as `b` then `a'`. When both insert at
+    /// the same offset, `a`'s text goes first.
+    pub fn transform(a: &Operation, b: &Operation) -> Result<(Operation, Operation), OtError> {
+        if a.base_len() != b.base_len() {
+            return Err(OtError::Incompatible);
+        }
+
+        let mut a_prime = Operation::new();
+        let mut b_prime = Operation::new();
+        let mut a_iter = a.components.iter().cloned();
+        let mut b_iter = b.components.iter().cloned();
+        let mut a_next = a_iter.next();
+        let mut b_next = b_iter.next();
+
+        loop {
+            match (a_next.take(), b_next.take()) {
+                (None, None) => break,
+                (Some(Component::Insert(text)), b_component) => {
+                    let len = utf16_len(&text);
+                    a_prime = a_prime.insert(&text);
+                    b_prime = b_prime.retain(len);
+                    a_next = a_iter.next();
+                    b_next = b_component;
+                }
+                (a_component, Some(Component::Insert(text))) => {
+                    let len = utf16_len(&text);
+                    a_prime = a_prime.retain(len);
+                    b_prime = b_prime.insert(&text);
+                    a_next = a_component;
+                    b_next = b_iter.next();
+                }
+                (None, Some(_)) | (Some(_), None) => return Err(OtError::Incompatible),
+                (Some(a_component), Some(b_component)) => {
+                    let (a_len, b_len) = (component_len(&a_component), component_len(&b_component));
+                    let len = a_len.min(b_len);
+                    match (&a_component, &b_component) {
+                        (Component::Retain(_), Component::Retain(_)) => {
+                            a_prime = a_prime.retain(len);
+                            b_prime = b_prime.retain(len);
+                        }
+                        (Component::Delete(_), Component::Retain(_)) => {
+                            a_prime = a_prime.delete(len);
+                        }
+                        (Component::Retain(_), Component::Delete(_)) => {
+                            b_prime = b_prime.delete(len);
+                        }
+                        // Both deleted the same text
+                        _ => {}
+                    }
+                    a_next = remainder(a_component, len).or_else(|| a_iter.next());
+                    b_next = remainder(b_component, len).or_else(|| b_iter.next());
+                }
+            }
+        }
+
+        Ok((a_prime, b_prime))
+    }
+}
+
+/// Length of a retain or delete.
+fn component_len(component: &Component) -> usize {
+    match component {
+        Component::Retain(n) | Component::Delete(n) => *n,
+        Component::Insert(_) => 0,
+    }
+}
+
+/// What's left of a retain or delete after `len` of it is consumed.
+fn remainder(component: Component, len: usize) -> Option<Component> {
+    match component {
+        Component::Retain(n) if n > len => Some(Component::Retain(n - len)),
+        Component::Delete(n) if n > len => Some(Component::Delete(n - len)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(json: &str) -> Operation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parses_the_ot_js_format() {
+        let parsed = op(r#"[3, "hi", -2, 1]"#);
+        assert_eq!(
+            parsed,
+            Operation::new().retain(3).insert("hi").delete(2).retain(1)
+        );
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), r#"[3,"hi",-2,1]"#);
+        assert!(serde_json::from_str::<Operation>("[0]").is_err());
+        assert!(serde_json::from_str::<Operation>(r#"[""]"#).is_err());
+    }
+
+    #[test]
+    fn applies_with_utf16_lengths() {
+        // The emoji is two UTF-16 code units
+        let text = "a😀b";
+        assert_eq!(op(r#"[1, -2, "x", 1]"#).apply(text).unwrap(), "axb");
+        assert_eq!(
+            op(r#"[2, -2]"#).apply(text),
+            Err(OtError::SplitsCharacter(2))
+        );
+        assert_eq!(
+            op("[3]").apply(text),
+            Err(OtError::LengthMismatch {
+                expected: 3,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn transformed_operations_converge() {
+        let text = "hello world";
+        let cases = [
+            (r#"[5, " there", 6]"#, r#"[6, -5, "ject"]"#),
+            (r#"["a", 11]"#, r#"["b", 11]"#),
+            (r#"[2, -6, 3]"#, r#"[4, -6, 1]"#),
+            (r#"[-11]"#, r#"[3, "x", 8]"#),
+        ];
+        for (a, b) in &cases {
+            let (a, b) = (op(a), op(b));
+            let (a_prime, b_prime) = Operation::transform(&a, &b).unwrap();
+            let ab = b_prime.apply(&a.apply(text).unwrap()).unwrap();
+            let ba = a_prime.apply(&b.apply(text).unwrap()).unwrap();
+            assert_eq!(ab, ba, "{:?} and {:?}", a, b);
+        }
+
+        let (a_prime, _) = Operation::transform(&op(r#"["a", 1]"#), &op(r#"["b", 1]"#)).unwrap();
+        assert_eq!(a_prime.apply("bx").unwrap(), "abx");
+        assert_eq!(
+            Operation::transform(&op("[1]"), &op("[2]")),
+            Err(OtError::Incompatible)
+        );
+    }
+
+    #[test]
+    fn diffs_documents() {
+        let cases = [
+            ("hello world", "hello there world"),
+            ("a😀b", "a😁b"),
+            ("same", "same"),
+            ("aaa", "aa"),
+            ("", "new"),
+        ];
+        for (old, new) in &cases {
+            assert_eq!(Operation::diff(old, new).apply(old).unwrap(), *new);
+        }
+        assert_eq!(Operation::diff("a😀b", "a😁b"), op(r#"[1, "😁", -2, 1]"#));
+    }
+
+    #[test]
+    fn transforms_indices() {
+        let operation = op(r#"[2, "abc", 3, -4, 1]"#);
+        assert_eq!(operation.transform_index(0), 0);
+        assert_eq!(operation.transform_index(2), 2);
+        assert_eq!(operation.transform_index(4), 7);
+        assert_eq!(operation.transform_index(7), 8);
+        assert_eq!(operation.transform_index(9), 8);
+        assert_eq!(operation.transform_index(10), 9);
+    }
+}
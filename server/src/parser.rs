@@ -7,9 +7,9 @@ pub enum HtmlPart<'a> {
 static PRE_INJECT: &str = "inject!(";
 static POST_INJECT: &str = ")";
 
-pub fn parse_html(html_orig: &str) -> Result<Vec<HtmlPart>, anyhow::Error> {
+pub fn parse_html(html_orig: &str) -> Result<Vec<HtmlPart<'_>>, anyhow::Error> {
     let mut parts = vec![];
-    let mut wb = &html_orig[..];
+    let mut wb = html_orig;
 
     loop {
         if let Some(start) = wb.find(PRE_INJECT) {
@@ -29,7 +29,7 @@ pub fn parse_html(html_orig: &str) -> Result<Vec<HtmlPart>, anyhow::Error> {
                 }
                 (Some(end), _) => {
                     let (l, r) = wb.split_at(end);
-                    let contents = (&l[PRE_INJECT.len()..]).trim();
+                    let contents = l[PRE_INJECT.len()..].trim();
                     parts.push(HtmlPart::IncludePath(
                         contents.split('.').map(|segment| segment.trim()).collect(),
                     ));
@@ -0,0 +1,45 @@
+//! Password hashing for local accounts.
+//!
+//! Hashes are argon2id PHC strings, e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+//! The algorithm and its parameters are part of the stored string, so they can be raised
+//! without invalidating existing hashes.
+
+use crate::ids;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+/// Hash a new password with a random salt. This is deliberately slow, so call it from
+/// `web::block`.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::encode_b64(ids::make_salt().as_bytes()).expect("ject: salt length");
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("ject: argon2 with the default params")
+        .to_string()
+}
+
+/// Check a password against a hash from [hash_password]. Also slow.
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    match PasswordHash::new(stored) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_hashed_passwords() {
+        let stored = hash_password("correct horse");
+        assert!(stored.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("correct horse ", &stored));
+        assert!(!verify_password("correct horse", "md5$1$salt$abc"));
+    }
+}
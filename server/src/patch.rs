@@ -0,0 +1,334 @@
+use crate::{
+    hash::sha1_hex,
+    state::{File, FileKind},
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The revision hash of a file's contents, which a [FilePatch] must be based on.
+pub fn revision(contents: &str) -> String {
+    sha1_hex(contents)
+}
+
+/// The [revision] of each file, by file kind, as returned to clients to base patches on.
+pub fn revisions(files: &[File]) -> BTreeMap<&'static str, String> {
+    files
+        .iter()
+        .map(|file| (file.kind.to_variant_name(), revision(&file.contents)))
+        .collect()
+}
+
+/// A change to one file of a session, made against the revision of it the client last saw.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FilePatch {
+    pub kind: FileKind,
+    /// [revision] of the contents the change was made against
+    pub base: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// Replacements of ranges of the base contents
+    Edits(Vec<Edit>),
+    /// A unified diff from the base contents, e.g. the output of `diff -u`
+    Diff(String),
+}
+
+/// Replace `start..end` of the base contents with `text`. Offsets are in UTF-16 code units,
+/// like JavaScript string indices.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PatchError {
+    #[error("Edit range {start}..{end} is outside of the file or not on a character boundary")]
+    InvalidRange { start: usize, end: usize },
+
+    #[error("Edit range {start}..{end} overlaps another edit")]
+    OverlappingEdits { start: usize, end: usize },
+
+    #[error("Invalid diff on line {line}: {message}")]
+    InvalidDiff { line: usize, message: &'static str },
+
+    #[error("Diff hunk on line {line} doesn't match the file")]
+    HunkMismatch { line: usize },
+}
+
+impl Change {
+    pub fn apply(&self, base: &str) -> Result<String, PatchError> {
+        match self {
+            Change::Edits(edits) => apply_edits(base, edits),
+            Change::Diff(diff) => apply_diff(base, diff),
+        }
+    }
+}
+
+/// Byte offset of a UTF-16 offset, or None if it's past the end or inside a character.
+fn byte_offset(text: &str, utf16_offset: usize) -> Option<usize> {
+    let mut units = 0;
+    for (index, c) in text.char_indices() {
+        if units == utf16_offset {
+            return Some(index);
+        }
+        if units > utf16_offset {
+            return None;
+        }
+        units += c.len_utf16();
+    }
+    if units == utf16_offset {
+        Some(text.len())
+    } else {
+        None
+    }
+}
+
+/// Apply edits whose ranges all refer to the base contents, in any order.
+pub fn apply_edits(base: &str, edits: &[Edit]) -> Result<String, PatchError> {
+    let mut ranges = edits
+        .iter()
+        .map(|edit| {
+            let invalid = || PatchError::InvalidRange {
+                start: edit.start,
+                end: edit.end,
+            };
+            let start = byte_offset(base, edit.start).ok_or_else(invalid)?;
+            let end = byte_offset(base, edit.end).ok_or_else(invalid)?;
+            if start > end {
+                return Err(invalid());
+            }
+            Ok((start, end, edit))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    ranges.sort_by_key(|&(start, end, _)| (start, end));
+
+    let mut out = String::with_capacity(base.len());
+    let mut copied = 0;
+    for (start, end, edit) in ranges {
+        if start < copied {
+            return Err(PatchError::OverlappingEdits {
+                start: edit.start,
+                end: edit.end,
+            });
+        }
+        out.push_str(&base[copied..start]);
+        out.push_str(&edit.text);
+        copied = end;
+    }
+    out.push_str(&base[copied..]);
+
+    Ok(out)
+}
+
+/// The index of the first old line of a hunk from its header, e.g. 11 for `@@ -12,7 +12,8 @@`.
+fn hunk_old_index(header: &str) -> Option<usize> {
+    let old = header.strip_prefix("@@ -")?.split(' ').next()?;
+    let (start, count) = match old.split_once(',') {
+        Some((start, count)) => (start.parse().ok()?, count.parse().ok()?),
+        None => (old.parse().ok()?, 1),
+    };
+    // An empty old range is numbered by the line before it
+    if count == 0 {
+        Some(start)
+    } else {
+        start.checked_sub(1)
+    }
+}
+
+/// Apply a unified diff. Context and removed lines must match the base exactly; the line
+/// counts in hunk headers are ignored in favor of the hunk's lines.
+pub fn apply_diff(base: &str, diff: &str) -> Result<String, PatchError> {
+    let old_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(base.len());
+    // Index into old_lines of the next line to copy
+    let mut copied = 0;
+    // Index into old_lines the current hunk has reached, and the hunk's header line
+    let mut hunk: Option<(usize, usize)> = None;
+    let mut last_added = false;
+
+    for (index, line) in diff.split_inclusive('\n').enumerate() {
+        let line_no = index + 1;
+        let invalid = |message| PatchError::InvalidDiff {
+            line: line_no,
+            message,
+        };
+
+        if line.starts_with("@@") {
+            let at = hunk_old_index(line).ok_or_else(|| invalid("bad hunk header"))?;
+            if at < copied || at > old_lines.len() {
+                return Err(invalid("hunks must be in order and inside the file"));
+            }
+            for old in &old_lines[copied..at] {
+                out.push_str(old);
+            }
+            copied = at;
+            hunk = Some((at, line_no));
+            continue;
+        }
+
+        let (at, header) = match hunk {
+            Some(hunk) => hunk,
+            // File headers and anything else before the first hunk
+            None => continue,
+        };
+
+        if line.starts_with('\\') {
+            // "\ No newline at end of file" applies to the line before it, and the old lines
+            // already lack it
+            if last_added && out.ends_with('\n') {
+                out.pop();
+            }
+            continue;
+        }
+
+        let (prefix, text) = if line == "\n" {
+            // Some tools drop the space of empty context lines
+            (' ', line)
+        } else {
+            // Lines may start with any character, not only ASCII ones
+            let mut chars = line.chars();
+            let prefix = chars.next().ok_or_else(|| invalid("empty line"))?;
+            (prefix, chars.as_str())
+        };
+        last_added = prefix == '+';
+        match prefix {
+            ' ' | '-' => {
+                let old = old_lines
+                    .get(at)
+                    .ok_or(PatchError::HunkMismatch { line: header })?;
+                if old.trim_end_matches('\n') != text.trim_end_matches('\n') {
+                    return Err(PatchError::HunkMismatch { line: header });
+                }
+                if prefix == ' ' {
+                    out.push_str(old);
+                }
+                hunk = Some((at + 1, header));
+                copied = at + 1;
+            }
+            '+' => out.push_str(text),
+            _ => {
+                return Err(invalid(
+                    "expected a line starting with ' ', '-', '+' or '@@'",
+                ))
+            }
+        }
+    }
+
+    if hunk.is_none() && !diff.trim().is_empty() {
+        return Err(PatchError::InvalidDiff {
+            line: 1,
+            message: "no hunks",
+        });
+    }
+
+    for old in &old_lines[copied..] {
+        out.push_str(old);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(start: usize, end: usize, text: &str) -> Edit {
+        Edit {
+            start,
+            end,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn applies_edits_against_the_base() {
+        let base = "a { color: red; }";
+        let edits = [edit(11, 14, "blue"), edit(0, 1, "b")];
+        assert_eq!(apply_edits(base, &edits).unwrap(), "b { color: blue; }");
+        assert_eq!(
+            apply_edits(base, &[edit(17, 17, "\n")]).unwrap(),
+            "a { color: red; }\n"
+        );
+
+        assert_eq!(
+            apply_edits(base, &[edit(0, 5, ""), edit(4, 6, "")]),
+            Err(PatchError::OverlappingEdits { start: 4, end: 6 })
+        );
+        assert_eq!(
+            apply_edits(base, &[edit(3, 18, "")]),
+            Err(PatchError::InvalidRange { start: 3, end: 18 })
+        );
+    }
+
+    #[test]
+    fn edit_offsets_are_utf16() {
+        // The emoji is two UTF-16 code units and four bytes
+        let base = "x = '😀';";
+        assert_eq!(apply_edits(base, &[edit(5, 7, "ok")]).unwrap(), "x = 'ok';");
+        assert_eq!(
+            apply_edits(base, &[edit(6, 7, "")]),
+            Err(PatchError::InvalidRange { start: 6, end: 7 })
+        );
+    }
+
+    #[test]
+    fn applies_unified_diffs() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let diff = "--- a/page.txt\n+++ b/page.txt\n@@ -2,3 +2,3 @@\n two\n-three\n+3\n four\n";
+        assert_eq!(apply_diff(base, diff).unwrap(), "one\ntwo\n3\nfour\nfive\n");
+
+        let diff =
+            "@@ -1 +1,2 @@\n+zero\n one\n@@ -5 +6 @@\n-five\n+5\n\\ No newline at end of file\n";
+        assert_eq!(
+            apply_diff(base, diff).unwrap(),
+            "zero\none\ntwo\nthree\nfour\n5"
+        );
+
+        let diff = "@@ -0,0 +1 @@\n+new\n";
+        assert_eq!(apply_diff("", diff).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn rejects_mismatched_diffs() {
+        let base = "one\ntwo\n";
+        assert_eq!(
+            apply_diff(base, "@@ -1,2 +1,2 @@\n one\n-TWO\n+2\n"),
+            Err(PatchError::HunkMismatch { line: 1 })
+        );
+        assert_eq!(
+            apply_diff(base, "@@ -3 +3 @@\n-three\n"),
+            Err(PatchError::HunkMismatch { line: 1 })
+        );
+        assert!(matches!(
+            apply_diff(base, "one\n"),
+            Err(PatchError::InvalidDiff { .. })
+        ));
+    }
+
+    #[test]
+    fn applies_diffs_of_non_ascii_lines() {
+        let base = "café\nnaïve\n";
+        let diff = "@@ -1,2 +1,2 @@\n café\n-naïve\n+日本\n";
+        assert_eq!(apply_diff(base, diff).unwrap(), "café\n日本\n");
+
+        // Lines starting with a multibyte char rather than a prefix
+        assert_eq!(
+            apply_diff(base, "@@ -1 +1 @@\né\n"),
+            Err(PatchError::InvalidDiff {
+                line: 2,
+                message: "expected a line starting with ' ', '-', '+' or '@@'",
+            })
+        );
+        assert!(matches!(
+            apply_diff(base, "@@ -1 +1 @@\n日本\n"),
+            Err(PatchError::InvalidDiff { line: 2, .. })
+        ));
+    }
+}
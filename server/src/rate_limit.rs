@@ -0,0 +1,309 @@
+//! Per-client-IP token buckets for the expensive or storage-consuming API calls. IPv6 clients
+//! are limited per /64, which is usually what one gets, rather than per address.
+//!
+//! Each [Budget] is configured with `$JECT_RATE_LIMIT_<BUDGET>` as `<requests>/<seconds>`, e.g.
+//! `JECT_RATE_LIMIT_SAVE=20/600` allows bursts of 20 saves, refilling at 20 per 10 minutes.
+//! `off` disables a budget.
+
+use crate::env;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Budget {
+    /// POST /api/session/new and /api/saved/{id}/fork
+    Create,
+    /// POST /api/save
+    Save,
+    /// PUT /api/session
+    Update,
+    /// Sending page.js to the compile service (cache misses only)
+    Compile,
+    /// POST /api/account/login and /api/account/register
+    Login,
+    /// Batches of console output from frames, POST /api/session/{id}/logs
+    Logs,
+    /// Running page.js on the server, POST /api/session/{id}/run
+    Run,
+}
+
+impl Budget {
+    pub fn name(self) -> &'static str {
+        match self {
+            Budget::Create => "create",
+            Budget::Save => "save",
+            Budget::Update => "update",
+            Budget::Compile => "compile",
+            Budget::Login => "login",
+            Budget::Logs => "logs",
+            Budget::Run => "run",
+        }
+    }
+
+    const ALL: [Budget; 7] = [
+        Budget::Create,
+        Budget::Save,
+        Budget::Update,
+        Budget::Compile,
+        Budget::Login,
+        Budget::Logs,
+        Budget::Run,
+    ];
+
+    fn default_limit(self) -> Limit {
+        let (burst, secs) = match self {
+            Budget::Create => (30, 600),
+            Budget::Save => (20, 600),
+            // The editor autosaves on every pause in typing
+            Budget::Update => (300, 300),
+            Budget::Compile => (120, 60),
+            // Password guessing
+            Budget::Login => (10, 300),
+            // Frames send a batch at most twice a second
+            Budget::Logs => (240, 60),
+            // Each run is a process of its own for up to the time limit
+            Budget::Run => (30, 60),
+        };
+        Limit {
+            burst,
+            per: Duration::from_secs(secs),
+        }
+    }
+
+    fn configured_limit(self) -> Option<Limit> {
+        match env::rate_limit(self.name()) {
+            Some(value) if value.trim().eq_ignore_ascii_case("off") => None,
+            Some(value) => Limit::parse(&value).or_else(|| {
+                eprintln!(
+                    "[rate_limit] Ignoring invalid limit {:?} for {}, expected e.g. 20/600",
+                    value, self
+                );
+                Some(self.default_limit())
+            }),
+            None => Some(self.default_limit()),
+        }
+    }
+
+    fn limit(self) -> Option<Limit> {
+        LIMITS.get(&self).copied()
+    }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Limit {
+    /// Bucket capacity
+    burst: u32,
+    /// Time to refill an empty bucket
+    per: Duration,
+}
+
+impl Limit {
+    fn parse(value: &str) -> Option<Self> {
+        let (burst, secs) = value.split_once('/')?;
+        let burst = burst.trim().parse().ok().filter(|&burst| burst > 0)?;
+        let secs: u64 = secs.trim().parse().ok().filter(|&secs| secs > 0)?;
+        Some(Self {
+            burst,
+            per: Duration::from_secs(secs),
+        })
+    }
+
+    fn tokens_per_sec(&self) -> f64 {
+        f64::from(self.burst) / self.per.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Returned when a client has used up a budget.
+#[derive(Debug, Clone, Copy, Error)]
+#[error("Too many {budget} requests, try again in {retry_after} second(s)")]
+pub struct RateLimited {
+    pub budget: Budget,
+    /// Seconds until the next request would be allowed
+    pub retry_after: u64,
+}
+
+/// How often buckets that would be full again are dropped, so only recently active clients
+/// are kept.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Budgets that are switched off aren't in the map.
+static LIMITS: Lazy<HashMap<Budget, Limit>> = Lazy::new(|| {
+    Budget::ALL
+        .iter()
+        .filter_map(|&budget| Some((budget, budget.configured_limit()?)))
+        .collect()
+});
+
+struct Buckets {
+    buckets: HashMap<(Budget, IpAddr), Bucket>,
+    pruned: Instant,
+}
+
+impl Buckets {
+    /// Drop the buckets that would be full by now, at most once per [PRUNE_INTERVAL].
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        self.pruned = now;
+        self.buckets
+            .retain(|(budget, _), bucket| match budget.limit() {
+                Some(limit) => now.duration_since(bucket.updated) < limit.per,
+                None => false,
+            });
+    }
+}
+
+static BUCKETS: Lazy<Mutex<Buckets>> = Lazy::new(|| {
+    Mutex::new(Buckets {
+        buckets: HashMap::new(),
+        pruned: Instant::now(),
+    })
+});
+
+/// What a client's buckets are keyed by: IPv4 addresses, or the /64 of IPv6 ones.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !u128::from(u64::MAX))),
+        },
+    }
+}
+
+/// Take a token from the client's bucket for this budget. Requests without a known client IP
+/// aren't limited.
+pub fn check(budget: Budget, ip: Option<IpAddr>) -> Result<(), RateLimited> {
+    let (ip, limit) = match (ip, budget.limit()) {
+        (Some(ip), Some(limit)) => (ip, limit),
+        _ => return Ok(()),
+    };
+
+    let now = Instant::now();
+    let mut buckets = BUCKETS
+        .lock()
+        .expect("rate limit buckets should never be poisoned");
+
+    buckets.prune(now);
+
+    let bucket = buckets
+        .buckets
+        .entry((budget, client_key(ip)))
+        .or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * limit.tokens_per_sec()).min(f64::from(limit.burst));
+    bucket.updated = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        let wait = (1.0 - bucket.tokens) / limit.tokens_per_sec();
+        Err(RateLimited {
+            budget,
+            retry_after: wait.ceil().max(1.0) as u64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limits() {
+        let limit = Limit::parse(" 20 / 600").unwrap();
+        assert_eq!(limit.burst, 20);
+        assert_eq!(limit.per, Duration::from_secs(600));
+        assert_eq!(Limit::parse("20"), None);
+        assert_eq!(Limit::parse("0/60"), None);
+        assert_eq!(Limit::parse("20/0"), None);
+    }
+
+    #[test]
+    fn bucket_runs_out() {
+        let ip = Some("192.0.2.31".parse().unwrap());
+        let burst = Budget::Create.limit().unwrap().burst;
+        for _ in 0..burst {
+            check(Budget::Create, ip).unwrap();
+        }
+        let limited = check(Budget::Create, ip).unwrap_err();
+        assert!(limited.retry_after >= 1);
+
+        // Other budgets and clients are unaffected
+        check(Budget::Save, ip).unwrap();
+        check(Budget::Create, Some("192.0.2.32".parse().unwrap())).unwrap();
+        check(Budget::Create, None).unwrap();
+    }
+
+    #[test]
+    fn limits_ipv6_clients_per_prefix() {
+        let key = |ip: &str| client_key(ip.parse().unwrap());
+        assert_eq!(key("2001:db8:1:2:3:4:5:6"), key("2001:db8:1:2::"));
+        assert_ne!(key("2001:db8:1:2::"), key("2001:db8:1:3::"));
+        assert_eq!(key("::ffff:192.0.2.1"), key("192.0.2.1"));
+        assert_eq!(key("192.0.2.1").to_string(), "192.0.2.1");
+
+        let burst = Budget::Login.limit().unwrap().burst;
+        for n in 0..burst {
+            let ip = format!("2001:db8:31::{:x}", n + 1).parse().unwrap();
+            check(Budget::Login, Some(ip)).unwrap();
+        }
+        let other_address = Some("2001:db8:31::ffff".parse().unwrap());
+        assert!(check(Budget::Login, other_address).is_err());
+        let other_prefix = Some("2001:db8:32::1".parse().unwrap());
+        check(Budget::Login, other_prefix).unwrap();
+    }
+
+    #[test]
+    fn prunes_full_buckets_once_per_interval() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut buckets = Buckets {
+            buckets: HashMap::new(),
+            pruned: secs(90),
+        };
+        let ip = "192.0.2.33".parse().unwrap();
+        let bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+        buckets.buckets.insert((Budget::Create, ip), bucket);
+        buckets.buckets.insert((Budget::Compile, ip), bucket);
+
+        // The compile bucket is full after a minute, but the last prune was too recent
+        buckets.prune(secs(100));
+        assert_eq!(buckets.buckets.len(), 2);
+        assert_eq!(buckets.pruned, secs(90));
+
+        // The create bucket takes ten minutes
+        buckets.prune(secs(180));
+        assert!(buckets.buckets.contains_key(&(Budget::Create, ip)));
+        assert!(!buckets.buckets.contains_key(&(Budget::Compile, ip)));
+        assert_eq!(buckets.pruned, secs(180));
+    }
+}
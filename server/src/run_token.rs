@@ -0,0 +1,83 @@
+//! Tokens that let a load of a session's frame page store its console output and test results
+//! (see [crate::api::logs::r_post_session_logs] and [crate::api::tests::r_post_session_tests]).
+//!
+//! page.html is served with a new one each time, for a run id the server picks. The token is
+//! the run id, the session id and the time, signed with a key made at startup, so a run can't
+//! be made up or sent for another session. They expire after [MAX_AGE_SECS], or on restart.
+
+use crate::{db::unix_now, hash::to_hex, ids};
+use blake2::{
+    digest::{consts::U32, Mac},
+    Blake2bMac,
+};
+use once_cell::sync::Lazy;
+
+/// How long a page may keep sending its output after it was loaded.
+pub const MAX_AGE_SECS: i64 = 24 * 60 * 60;
+
+static KEY: Lazy<String> = Lazy::new(ids::make_run_token_key);
+
+fn mac(session_id: &str, run_id: &str, issued_at: i64) -> Blake2bMac<U32> {
+    let mut mac = Blake2bMac::<U32>::new_from_slice(KEY.as_bytes()).expect("ject: key length");
+    mac.update(format!("{}\n{}\n{}", session_id, run_id, issued_at).as_bytes());
+    mac
+}
+
+/// A token for a new run of the session, as `<run id>.<issued at>.<signature>`.
+pub fn issue(session_id: &str) -> String {
+    let run_id = ids::make_run_id();
+    let issued_at = unix_now();
+    let signature = mac(session_id, &run_id, issued_at).finalize().into_bytes();
+    format!("{}.{}.{}", run_id, issued_at, to_hex(&signature))
+}
+
+/// The run id of a token issued for the session, unless it's forged or expired.
+pub fn verify(token: &str, session_id: &str) -> Option<String> {
+    let mut parts = token.splitn(3, '.');
+    let run_id = parts.next()?;
+    let issued_at: i64 = parts.next()?.parse().ok()?;
+    let signature = from_hex(parts.next()?)?;
+
+    if unix_now() - issued_at > MAX_AGE_SECS {
+        return None;
+    }
+    mac(session_id, run_id, issued_at)
+        .verify_slice(&signature)
+        .ok()
+        .map(|()| run_id.to_owned())
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_issued_tokens() {
+        let token = issue("session-a");
+        let run_id = verify(&token, "session-a").unwrap();
+        assert!(token.starts_with(&format!("{}.", run_id)));
+
+        assert_eq!(verify(&token, "session-b"), None);
+        let forged = token.replacen(&run_id, "someone-else", 1);
+        assert_eq!(verify(&forged, "session-a"), None);
+        assert_eq!(verify("abc.1.00", "session-a"), None);
+        assert_eq!(verify("", "session-a"), None);
+
+        let issued_at = unix_now() - MAX_AGE_SECS - 1;
+        let signature = mac("session-a", "old-run", issued_at)
+            .finalize()
+            .into_bytes();
+        let expired = format!("old-run.{}.{}", issued_at, to_hex(&signature));
+        assert_eq!(verify(&expired, "session-a"), None);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::imports::DetectedDeps;
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FileKind {
@@ -6,17 +8,49 @@ pub enum FileKind {
     Css,
     Html,
     Text,
+    /// The dependency manifest used to build the page's import map, see [crate::import_map]
+    Deps,
+    /// `describe`/`it` tests of page.js, see [crate::api::tests]
+    Tests,
 }
 
 impl FileKind {
+    /// The serialized name, e.g. `"JavaScript"`
+    pub fn to_variant_name(self) -> &'static str {
+        match self {
+            FileKind::JavaScript => "JavaScript",
+            FileKind::Css => "Css",
+            FileKind::Html => "Html",
+            FileKind::Text => "Text",
+            FileKind::Deps => "Deps",
+            FileKind::Tests => "Tests",
+        }
+    }
+
     pub fn to_default_name(self) -> &'static str {
         match self {
             FileKind::JavaScript => "page.js",
             FileKind::Css => "page.css",
             FileKind::Html => "page.html",
             FileKind::Text => "page.txt",
+            FileKind::Deps => "deps.json",
+            FileKind::Tests => "page.test.js",
         }
     }
+
+    pub fn from_default_name(name: &str) -> Option<Self> {
+        [
+            FileKind::JavaScript,
+            FileKind::Css,
+            FileKind::Html,
+            FileKind::Text,
+            FileKind::Deps,
+            FileKind::Tests,
+        ]
+        .iter()
+        .copied()
+        .find(|kind| kind.to_default_name() == name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,3 +74,351 @@ impl File {
 pub struct Session {
     pub files: Vec<File>,
 }
+
+impl Session {
+    /// The distinct kinds of the files in this session, in order of appearance.
+    pub fn file_kinds(&self) -> Vec<FileKind> {
+        let mut kinds = vec![];
+        for file in &self.files {
+            if !kinds.contains(&file.kind) {
+                kinds.push(file.kind);
+            }
+        }
+        kinds
+    }
+}
+
+/// A session's files as currently stored, as returned by `GET /api/session/{id}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionState {
+    pub session_id: String,
+    /// Incremented by every update, for `If-Match`
+    pub revision: i64,
+    pub session: Session,
+    /// Hash of each file by file kind, to base patches on, see [crate::patch::revision]
+    pub revisions: BTreeMap<&'static str, String>,
+    /// The save this session was forked from
+    pub forked_from: Option<String>,
+}
+
+/// Who may modify a save, and when it was created, updated or deleted. Timestamps are unix
+/// seconds, and None for saves made before they were recorded.
+#[derive(Debug, Clone)]
+pub struct SavedInfo {
+    pub owner_id: Option<String>,
+    pub edit_token_hash: Option<String>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub deleted_at: Option<i64>,
+}
+
+/// The user-provided description of a save.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SavedDetails {
+    #[serde(default)]
+    pub title: String,
+    /// Markdown
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A save as shown in search results and listings, without its files.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSummary {
+    pub save_id: String,
+    #[serde(flatten)]
+    pub details: SavedDetails,
+    pub file_kinds: Vec<FileKind>,
+    /// Username of the owner
+    pub owner: Option<String>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub views: i64,
+    /// The save this was forked from
+    pub forked_from: Option<String>,
+    /// Number of times this was forked into a new session
+    pub forks: i64,
+    /// Size in bytes of each file, by file name
+    pub sizes: BTreeMap<String, i64>,
+}
+
+/// Narrows a search or listing of saves.
+#[derive(Debug, Clone, Default)]
+pub struct SavedFilter {
+    pub tag: Option<String>,
+    /// Username of the owner
+    pub owner: Option<String>,
+    pub file_kind: Option<FileKind>,
+    /// Only forks, or only saves that aren't forks
+    pub has_parent: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SavedSort {
+    #[default]
+    Newest,
+    /// Most viewed
+    Views,
+    /// Recently updated
+    Updated,
+}
+
+impl SavedSort {
+    pub fn name(self) -> &'static str {
+        match self {
+            SavedSort::Newest => "newest",
+            SavedSort::Views => "views",
+            SavedSort::Updated => "updated",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [SavedSort::Newest, SavedSort::Views, SavedSort::Updated]
+            .iter()
+            .copied()
+            .find(|sort| sort.name() == name)
+    }
+}
+
+/// Position in a listing of saves: the sort, and the sort key and rowid of the last save on a
+/// page. Rendered as `<sort>.<key>.<rowid>`, which clients should treat as opaque.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavedCursor {
+    pub sort: SavedSort,
+    pub key: i64,
+    pub rowid: i64,
+}
+
+impl fmt::Display for SavedCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.sort.name(), self.key, self.rowid)
+    }
+}
+
+impl FromStr for SavedCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sort, rest) = s.split_once('.').ok_or(())?;
+        let (key, rowid) = rest.split_once('.').ok_or(())?;
+        Ok(Self {
+            sort: SavedSort::from_name(sort).ok_or(())?,
+            key: key.parse().map_err(|_| ())?,
+            rowid: rowid.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// A save matching a search. The highlights mark matches between `\u{1}` and `\u{2}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub saved: SavedSummary,
+    pub title_highlight: String,
+    /// An excerpt of the file contents around the best match
+    pub snippet: String,
+}
+
+/// The compiled page.js and detected deps of a session, valid while `source_hash` matches.
+#[derive(Debug, Clone)]
+pub struct CompileCache {
+    pub source_hash: String,
+    /// None if only the deps have been detected so far
+    pub code: Option<String>,
+    pub deps: DetectedDeps,
+}
+
+/// A local account. Timestamps are unix seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub user_id: String,
+    pub username: String,
+    pub created_at: i64,
+}
+
+/// A personal API token, without the secret (only its hash is stored).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub token_id: String,
+    pub name: String,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogMethod {
+    Log,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogMethod::Log => "log",
+            LogMethod::Info => "info",
+            LogMethod::Warn => "warn",
+            LogMethod::Error => "error",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            LogMethod::Log,
+            LogMethod::Info,
+            LogMethod::Warn,
+            LogMethod::Error,
+        ]
+        .iter()
+        .copied()
+        .find(|method| method.name() == name)
+    }
+}
+
+/// A console call in the frame, with its arguments already formatted as strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Position in the run, so retried batches aren't stored twice
+    pub seq: u32,
+    pub method: LogMethod,
+    pub args: Vec<String>,
+    /// Milliseconds since the frame page started loading
+    pub time: f64,
+}
+
+/// The console output of one load of a session's frame page.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRun {
+    pub run_id: String,
+    /// Session revision that was running, if known
+    pub revision: Option<i64>,
+    pub started_at: i64,
+    pub entries: Vec<LogEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+impl TestStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::Skipped => "skipped",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [TestStatus::Passed, TestStatus::Failed, TestStatus::Skipped]
+            .iter()
+            .copied()
+            .find(|status| status.name() == name)
+    }
+}
+
+/// The outcome of one `it()` in page.test.js.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestResult {
+    /// Names of the enclosing `describe()` blocks, outermost first
+    pub suite: Vec<String>,
+    pub name: String,
+    pub status: TestStatus,
+    /// Milliseconds
+    pub duration: f64,
+    /// The error that failed the test, with its stack if there was one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+}
+
+/// Where a session's tests were run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestSource {
+    /// In the browser, by the frame page's `inject!(tests)`
+    Frame,
+    /// On the server, see [crate::headless]
+    Headless,
+}
+
+impl TestSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            TestSource::Frame => "frame",
+            TestSource::Headless => "headless",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [TestSource::Frame, TestSource::Headless]
+            .iter()
+            .copied()
+            .find(|source| source.name() == name)
+    }
+}
+
+/// A run of a session's page.test.js. Only the newest run of each session is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct TestRun {
+    pub run_id: String,
+    pub source: TestSource,
+    /// Session revision that was tested, if known
+    pub revision: Option<i64>,
+    pub started_at: i64,
+    /// Milliseconds
+    pub duration: f64,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Why the tests couldn't all run, e.g. page.test.js failed to compile or timed out
+    pub error: Option<String>,
+    pub results: Vec<TestResult>,
+}
+
+impl TestRun {
+    pub fn new(
+        run_id: String,
+        source: TestSource,
+        started_at: i64,
+        duration: f64,
+        error: Option<String>,
+        results: Vec<TestResult>,
+    ) -> Self {
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        Self {
+            run_id,
+            source,
+            revision: None,
+            started_at,
+            duration,
+            passed: count(TestStatus::Passed),
+            failed: count(TestStatus::Failed),
+            skipped: count(TestStatus::Skipped),
+            error,
+            results,
+        }
+    }
+}
+
+/// A template that new sessions can start from, see [crate::templates].
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateSummary {
+    pub name: String,
+    pub title: String,
+    pub description: String,
+    pub file_kinds: Vec<FileKind>,
+    /// Compiled into the server, rather than promoted from a save
+    pub builtin: bool,
+    /// The save it was promoted from
+    pub saved_id: Option<String>,
+    pub created_at: Option<i64>,
+}
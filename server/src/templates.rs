@@ -0,0 +1,110 @@
+//! Templates that new sessions start from, for `/new/<name>` and `POST /api/session/new?template=`.
+//!
+//! The built-in ones are in `server/templates/<name>/` and compiled in. Admins can promote
+//! any save to a template, which copies its files; those are stored in the database, see
+//! [crate::db::Db::promote_saved].
+
+use crate::state::{File, FileKind, Session, TemplateSummary};
+
+pub struct BuiltinTemplate {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub files: &'static [(FileKind, &'static str)],
+}
+
+impl BuiltinTemplate {
+    pub fn summary(&self) -> TemplateSummary {
+        TemplateSummary {
+            name: self.name.to_owned(),
+            title: self.title.to_owned(),
+            description: self.description.to_owned(),
+            file_kinds: self.files.iter().map(|(kind, _)| *kind).collect(),
+            builtin: true,
+            saved_id: None,
+            created_at: None,
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        let files = self.files.iter();
+        Session {
+            files: files
+                .map(|(kind, contents)| File::new(*kind, (*contents).to_owned()))
+                .collect(),
+        }
+    }
+}
+
+pub const BUILTIN: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: "default",
+        title: "Blank",
+        description: "An empty page with the console",
+        files: &[
+            (
+                FileKind::JavaScript,
+                include_str!("../templates/default/page.js"),
+            ),
+            (
+                FileKind::Html,
+                include_str!("../templates/default/page.html"),
+            ),
+            (FileKind::Css, include_str!("../templates/default/page.css")),
+        ],
+    },
+    BuiltinTemplate {
+        name: "react",
+        title: "React",
+        description: "A React component rendered into #root",
+        files: &[
+            (
+                FileKind::JavaScript,
+                include_str!("../templates/react/page.js"),
+            ),
+            (FileKind::Html, include_str!("../templates/react/page.html")),
+            (FileKind::Css, include_str!("../templates/react/page.css")),
+        ],
+    },
+];
+
+pub fn builtin(name: &str) -> Option<&'static BuiltinTemplate> {
+    BUILTIN.iter().find(|template| template.name == name)
+}
+
+/// Template names are used in URLs: 1 to 32 lowercase letters, digits or '-'.
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// What the files of a promoted template are stored under in the `file` table, in place of a
+/// session or save id. Those never contain ':'.
+pub fn files_id(name: &str) -> String {
+    format!("template:{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_templates_are_valid() {
+        for template in BUILTIN {
+            assert!(is_valid_name(template.name), "{}", template.name);
+            let session = template.session();
+            assert_eq!(session.file_kinds(), template.summary().file_kinds);
+            assert!(session.files.iter().all(|file| !file.contents.is_empty()));
+        }
+        assert!(builtin("react").is_some());
+        assert!(builtin("nope").is_none());
+
+        assert!(is_valid_name("my-template-2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("React"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name(&"a".repeat(33)));
+    }
+}
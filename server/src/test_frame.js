@@ -0,0 +1,51 @@
+// Follows test_runner.js in the frame: runs the tests once the page has loaded (page.test.js
+// is a module, so it has been evaluated by then), prints the results to the console and sends
+// them to the server, see api/tests.rs.
+(function () {
+  const sessionId = location.pathname.split('/')[3];
+  // Issued by the server with page.html, see run_token.rs
+  const runToken = document.currentScript.dataset.runToken;
+
+  // Errors before the tests run, e.g. page.test.js failing to import page.js
+  let error = null;
+  const onError = (message) => {
+    if (error === null) error = String(message).slice(0, 1024 * 16);
+  };
+  const onWindowError = (event) => onError(event.message);
+  const onRejection = (event) => {
+    const { reason } = event;
+    onError(reason instanceof Error ? `${reason.name}: ${reason.message}` : reason);
+  };
+  window.addEventListener('error', onWindowError);
+  window.addEventListener('unhandledrejection', onRejection);
+
+  window.addEventListener('load', async () => {
+    window.removeEventListener('error', onWindowError);
+    window.removeEventListener('unhandledrejection', onRejection);
+
+    const { results, duration } = await globalThis.__jectTests.run();
+    const counts = { passed: 0, failed: 0, skipped: 0 };
+    for (const result of results) {
+      counts[result.status] += 1;
+      const name = [...result.suite, result.name].join(' › ');
+      if (result.status === 'passed') {
+        console.log(`✓ ${name} (${result.duration} ms)`);
+      } else if (result.status === 'failed') {
+        console.error(`✗ ${name}\n${result.failure}`);
+      } else {
+        console.info(`- ${name} (skipped)`);
+      }
+    }
+    const { passed, failed, skipped } = counts;
+    const summary = `Tests: ${passed} passed, ${failed} failed, ${skipped} skipped`;
+    const report = failed || error ? console.error : console.info;
+    report(error ? `${summary}\n${error}` : summary);
+
+    if (!runToken) return;
+    fetch(`/api/session/${sessionId}/tests`, {
+      method: 'POST',
+      headers: { 'content-type': 'application/json' },
+      body: JSON.stringify({ run_token: runToken, duration, error, results }),
+    }).catch(() => {});
+  });
+})();
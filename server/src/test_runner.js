@@ -0,0 +1,291 @@
+// describe/it/expect for page.test.js. Loaded as a plain script before the tests, in the
+// frame by inject!(tests) and on the server by headless.rs. The tests are only collected
+// while page.test.js is evaluated; `__jectTests.run()` runs them and resolves to the results.
+(function () {
+  const TEST_TIMEOUT_MS = 5000;
+  const MAX_MESSAGE = 1024 * 16;
+
+  const newSuite = (name, parent, skip) => ({
+    name,
+    parent,
+    skip,
+    children: [],
+    beforeEach: [],
+    afterEach: [],
+  });
+  const root = newSuite(null, null, false);
+  let current = root;
+  let started = false;
+
+  const show = (value, depth = 0) => {
+    switch (typeof value) {
+      case 'string':
+        return depth ? JSON.stringify(value) : value;
+      case 'function':
+        return `[Function${value.name ? `: ${value.name}` : ''}]`;
+      case 'bigint':
+        return `${value}n`;
+      case 'object':
+        break;
+      default:
+        return String(value);
+    }
+    if (value === null) return 'null';
+    if (value instanceof Error) return `[${value.name}: ${value.message}]`;
+    if (depth > 3) return Array.isArray(value) ? '[Array]' : '[Object]';
+    if (Array.isArray(value)) {
+      return `[${value.map((item) => show(item, depth + 1)).join(', ')}]`;
+    }
+    const props = Object.keys(value).map(
+      (key) => `${key}: ${show(value[key], depth + 1)}`,
+    );
+    return props.length ? `{ ${props.join(', ')} }` : '{}';
+  };
+
+  const equal = (a, b, seen = []) => {
+    if (Object.is(a, b)) return true;
+    if (typeof a !== 'object' || typeof b !== 'object' || !a || !b) return false;
+    if (Object.getPrototypeOf(a) !== Object.getPrototypeOf(b)) return false;
+    if (seen.some(([x, y]) => x === a && y === b)) return true;
+    seen = [...seen, [a, b]];
+    if (a instanceof Date) return a.getTime() === b.getTime();
+    if (a instanceof RegExp) return String(a) === String(b);
+    if (a instanceof Map || a instanceof Set) {
+      return equal([...a], [...b], seen);
+    }
+    const keys = Object.keys(a);
+    return (
+      keys.length === Object.keys(b).length &&
+      keys.every((key) => Object.prototype.hasOwnProperty.call(b, key)) &&
+      keys.every((key) => equal(a[key], b[key], seen))
+    );
+  };
+
+  class AssertionError extends Error {}
+  AssertionError.prototype.name = 'AssertionError';
+
+  const matchers = {
+    toBe: (actual, expected) => [
+      Object.is(actual, expected),
+      `to be ${show(expected, 1)}`,
+    ],
+    toEqual: (actual, expected) => [
+      equal(actual, expected),
+      `to equal ${show(expected, 1)}`,
+    ],
+    toBeTruthy: (actual) => [!!actual, 'to be truthy'],
+    toBeFalsy: (actual) => [!actual, 'to be falsy'],
+    toBeNull: (actual) => [actual === null, 'to be null'],
+    toBeUndefined: (actual) => [actual === undefined, 'to be undefined'],
+    toBeDefined: (actual) => [actual !== undefined, 'to be defined'],
+    toBeNaN: (actual) => [Number.isNaN(actual), 'to be NaN'],
+    toBeGreaterThan: (actual, n) => [actual > n, `to be greater than ${show(n, 1)}`],
+    toBeGreaterThanOrEqual: (actual, n) => [
+      actual >= n,
+      `to be at least ${show(n, 1)}`,
+    ],
+    toBeLessThan: (actual, n) => [actual < n, `to be less than ${show(n, 1)}`],
+    toBeLessThanOrEqual: (actual, n) => [actual <= n, `to be at most ${show(n, 1)}`],
+    toBeCloseTo: (actual, n, digits = 2) => [
+      Math.abs(actual - n) < 10 ** -digits / 2,
+      `to be close to ${show(n, 1)}`,
+    ],
+    toBeInstanceOf: (actual, type) => [
+      actual instanceof type,
+      `to be an instance of ${type && type.name}`,
+    ],
+    toContain: (actual, item) => [
+      actual != null && actual.includes(item),
+      `to contain ${show(item, 1)}`,
+    ],
+    toHaveLength: (actual, length) => [
+      actual != null && actual.length === length,
+      `to have length ${length}`,
+    ],
+    toMatch: (actual, pattern) => [
+      typeof actual === 'string' &&
+        (typeof pattern === 'string'
+          ? actual.includes(pattern)
+          : pattern.test(actual)),
+      `to match ${show(pattern, 1)}`,
+    ],
+    toThrow: (fn, expected) => {
+      // With `.rejects`, this gets the rejection reason instead of a function
+      let thrown = typeof fn !== 'function';
+      let error = thrown ? fn : undefined;
+      if (!thrown) {
+        try {
+          fn();
+        } catch (err) {
+          thrown = true;
+          error = err;
+        }
+      }
+      const message = error instanceof Error ? error.message : String(error);
+      const pass =
+        thrown &&
+        (expected === undefined ||
+          (typeof expected === 'string' && message.includes(expected)) ||
+          (expected instanceof RegExp && expected.test(message)) ||
+          (typeof expected === 'function' && error instanceof expected));
+      const description =
+        expected === undefined ? 'to throw' : `to throw ${show(expected, 1)}`;
+      return [pass, description];
+    },
+  };
+
+  const expect = (actual) => {
+    const build = (negated, transform) => {
+      const assertions = {};
+      for (const [name, matcher] of Object.entries(matchers)) {
+        const check = (value, args) => {
+          const [pass, description] = matcher(value, ...args);
+          if (pass === negated) {
+            const not = negated ? 'not ' : '';
+            throw new AssertionError(
+              `Expected ${show(value, 1)} ${not}${description}`,
+            );
+          }
+        };
+        assertions[name] = (...args) =>
+          transform
+            ? transform().then((value) => check(value, args))
+            : check(actual, args);
+      }
+      return assertions;
+    };
+    const settled = (resolves) => () =>
+      Promise.resolve(actual).then(
+        (value) => {
+          if (resolves) return value;
+          throw new AssertionError(`Expected a rejection, got ${show(value, 1)}`);
+        },
+        (reason) => {
+          if (!resolves) return reason;
+          throw new AssertionError(
+            `Expected a resolved value, got ${show(reason, 1)}`,
+          );
+        },
+      );
+
+    const assertions = build(false, null);
+    assertions.not = build(true, null);
+    assertions.resolves = build(false, settled(true));
+    assertions.resolves.not = build(true, settled(true));
+    assertions.rejects = build(false, settled(false));
+    assertions.rejects.not = build(true, settled(false));
+    return assertions;
+  };
+
+  const collecting = (name) => {
+    if (started) {
+      throw new Error(`${name}() must be called while page.test.js is evaluated`);
+    }
+  };
+
+  const describe = (name, fn, skip = false) => {
+    collecting('describe');
+    const suite = newSuite(String(name), current, skip || current.skip);
+    current.children.push(suite);
+    current = suite;
+    try {
+      fn();
+    } finally {
+      current = suite.parent;
+    }
+  };
+  describe.skip = (name, fn) => describe(name, fn, true);
+
+  const it = (name, fn, skip = false) => {
+    collecting('it');
+    current.children.push({ name: String(name), fn, skip: skip || current.skip });
+  };
+  it.skip = (name, fn) => it(name, fn, true);
+
+  const hook = (kind) => (fn) => {
+    collecting(kind);
+    current[kind].push(fn);
+  };
+
+  const timeout = (promise) => {
+    let timer;
+    return Promise.race([
+      promise,
+      new Promise((_, reject) => {
+        timer = setTimeout(
+          () => reject(new Error(`Timed out after ${TEST_TIMEOUT_MS} ms`)),
+          TEST_TIMEOUT_MS,
+        );
+      }),
+    ]).finally(() => clearTimeout(timer));
+  };
+
+  const failure = (error) => {
+    if (!(error instanceof Error)) {
+      return `Uncaught ${show(error, 1)}`.slice(0, MAX_MESSAGE);
+    }
+    // Browsers start the stack with the message, QuickJS doesn't
+    const header = `${error.name}: ${error.message}`;
+    const stack = error.stack || '';
+    const text = stack.startsWith(header) ? stack : `${header}\n${stack}`.trimEnd();
+    return text.slice(0, MAX_MESSAGE);
+  };
+
+  const hooks = (suite, kind) => {
+    const fns = [];
+    for (let s = suite; s; s = s.parent) fns.unshift(...s[kind]);
+    return kind === 'afterEach' ? fns.reverse() : fns;
+  };
+
+  const runTest = async (test, suite, path, results) => {
+    const result = { suite: path, name: test.name, status: 'skipped', duration: 0 };
+    results.push(result);
+    if (test.skip || typeof test.fn !== 'function') return;
+
+    const start = Date.now();
+    try {
+      for (const fn of hooks(suite, 'beforeEach')) await timeout(fn());
+      try {
+        await timeout(test.fn());
+      } finally {
+        for (const fn of hooks(suite, 'afterEach')) await timeout(fn());
+      }
+      result.status = 'passed';
+    } catch (error) {
+      result.status = 'failed';
+      result.failure = failure(error);
+    }
+    result.duration = Date.now() - start;
+  };
+
+  const runSuite = async (suite, path, results) => {
+    for (const child of suite.children) {
+      if (child.children) {
+        await runSuite(child, [...path, child.name], results);
+      } else {
+        await runTest(child, suite, path, results);
+      }
+    }
+  };
+
+  // Filled in as tests finish, so a run that's cut short still reports the finished ones
+  const state = { results: [], duration: 0 };
+
+  const run = async () => {
+    started = true;
+    const start = Date.now();
+    await runSuite(root, [], state.results);
+    state.duration = Date.now() - start;
+    return state;
+  };
+
+  Object.assign(globalThis, {
+    describe,
+    it,
+    test: it,
+    expect,
+    beforeEach: hook('beforeEach'),
+    afterEach: hook('afterEach'),
+    __jectTests: { run, state },
+  });
+})();
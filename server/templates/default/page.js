@@ -0,0 +1 @@
+// JavaScript
@@ -0,0 +1,11 @@
+import React from 'react';
+import ReactDOM from 'react-dom';
+
+function App() {
+    const [state, setState] = React.useState(null);
+    return (
+        <div>Hello</div>
+    );
+}
+
+ReactDOM.render(<App />, document.getElementById('root'));
\ No newline at end of file
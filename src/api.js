@@ -12,7 +12,7 @@ const fetch2 = async (url, { json, ...options }) => {
     options.headers = { ...options.headers, 'content-type': 'application/json' };
   }
   const res = await fetch(url, options);
-  const body = parseBody(res);
+  const body = await parseBody(res);
 
   if (res.ok) {
     return body;
@@ -38,12 +38,122 @@ export async function createSession(session) {
   return fetch2(`/api/session/new`, { method: 'POST', json: { session } });
 }
 
-export async function updateSession(session_id, session) {
-  return fetch2(`/api/session`, { method: 'PUT', json: { session_id, session } });
+// Responds like createSession, plus the template's files in `session`
+export async function createSessionFromTemplate(name) {
+  const query = `template=${encodeURIComponent(name)}`;
+  return fetch2(`/api/session/new?${query}`, { method: 'POST' });
 }
 
-export async function save(session) {
-  return fetch2(`/api/save`, { method: 'POST', json: { session } });
+// What the server last stored: the session revision, and the contents and revision
+// hash of each file by kind, from a create, fork, get or update response
+export const syncState = (files, { revision, revisions }, prev = null) => {
+  const synced = { revision, files: { ...prev?.files } };
+  for (const file of files) {
+    if (revisions[file.kind]) {
+      const hash = revisions[file.kind];
+      synced.files[file.kind] = { contents: file.contents, revision: hash };
+    }
+  }
+  return synced;
+};
+
+const isLowSurrogate = (code) => code >= 0xdc00 && code <= 0xdfff;
+
+// One edit turning prev into next, found by trimming their common prefix and suffix
+const diffEdit = (prev, next) => {
+  const max = Math.min(prev.length, next.length);
+  let start = 0;
+  while (start < max && prev[start] === next[start]) start += 1;
+  if (isLowSurrogate(prev.charCodeAt(start))) start -= 1;
+  let end = 0;
+  const same = (fromEnd) =>
+    prev[prev.length - 1 - fromEnd] === next[next.length - 1 - fromEnd];
+  while (end < max - start && same(end)) end += 1;
+  if (isLowSurrogate(prev.charCodeAt(prev.length - end))) end -= 1;
+  return {
+    start,
+    end: prev.length - end,
+    text: next.slice(start, next.length - end),
+  };
+};
+
+// Sends only the changed range of each file when the server has the same files. Fails
+// with a revision_conflict error if the session changed since synced.revision.
+// Resolves to the new synced state, see syncState.
+export async function updateSession(session_id, session, edit_token, synced) {
+  const headers = {
+    'x-edit-token': edit_token,
+    'if-match': String(synced.revision),
+  };
+  const { files } = session;
+  const patchable =
+    files.length === Object.keys(synced.files).length &&
+    files.every((file) => synced.files[file.kind]);
+
+  if (patchable) {
+    const patches = files
+      .filter((file) => file.contents !== synced.files[file.kind].contents)
+      .map((file) => ({
+        kind: file.kind,
+        base: synced.files[file.kind].revision,
+        edits: [diffEdit(synced.files[file.kind].contents, file.contents)],
+      }));
+    try {
+      const res = await fetch2(`/api/session`, {
+        method: 'PUT',
+        json: { session_id, patches },
+        headers,
+      });
+      return syncState(files, res, synced);
+    } catch (error) {
+      if (error.body?.code !== 'patch_conflict') throw error;
+    }
+  }
+
+  const res = await fetch2(`/api/session`, {
+    method: 'PUT',
+    json: { session_id, session },
+    headers,
+  });
+  return syncState(files, res);
+}
+
+const editTokenKey = (save_id) => `ject.saved.${save_id}.edit_token`;
+
+export async function forkSaved(save_id) {
+  return fetch2(`/api/saved/${encodeURIComponent(save_id)}/fork`, { method: 'POST' });
+}
+
+// The session's id is only sent with its edit token, which lets the save keep its output
+export async function save(session, session_id, edit_token) {
+  const res = await fetch2(`/api/save`, {
+    method: 'POST',
+    json: edit_token ? { session, session_id } : { session },
+    headers: edit_token ? { 'x-edit-token': edit_token } : {},
+  });
+  // Kept so this browser can delete the save later
+  localStorage.setItem(editTokenKey(res.save_id), res.edit_token);
+  return res;
+}
+
+export async function deleteSaved(save_id) {
+  const edit_token = localStorage.getItem(editTokenKey(save_id));
+  return fetch2(`/api/saved/${encodeURIComponent(save_id)}`, {
+    method: 'DELETE',
+    headers: edit_token ? { 'x-edit-token': edit_token } : {},
+  });
+}
+
+// The console output captured from the session's newest frame loads, newest first
+export async function getSessionLogs(session_id, runs = null) {
+  const query = runs ? `?runs=${runs}` : '';
+  return fetch2(`/api/session/${encodeURIComponent(session_id)}/logs${query}`, {
+    method: 'GET',
+  });
+}
+
+export async function getSavedLogs(save_id) {
+  return fetch2(`/api/saved/${encodeURIComponent(save_id)}/logs`, { method: 'GET' });
 }
 
 export async function getSaved(save_id) {
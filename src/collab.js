@@ -0,0 +1,366 @@
+import { EventType } from './EventType';
+
+// Operations are in the format the server's ot module uses: an array where a
+// positive number retains that many UTF-16 code units, a negative number deletes that
+// many, and a string is inserted.
+const isRetain = (c) => typeof c === 'number' && c > 0;
+const isDelete = (c) => typeof c === 'number' && c < 0;
+const isInsert = (c) => typeof c === 'string';
+
+function retain(op, n) {
+  if (!n) return;
+  if (isRetain(op[op.length - 1])) op[op.length - 1] += n;
+  else op.push(n);
+}
+
+function insert(op, text) {
+  if (!text) return;
+  const last = op[op.length - 1];
+  if (isInsert(last)) {
+    op[op.length - 1] += text;
+  } else if (isDelete(last)) {
+    // Keep inserts before deletes, like the server does
+    if (isInsert(op[op.length - 2])) op[op.length - 2] += text;
+    else op.splice(op.length - 1, 0, text);
+  } else {
+    op.push(text);
+  }
+}
+
+function del(op, n) {
+  if (!n) return;
+  if (isDelete(op[op.length - 1])) op[op.length - 1] -= n;
+  else op.push(-n);
+}
+
+const isNoop = (op) => op.every(isRetain);
+
+// Transform concurrent operations a and b into [a', b'], where a then b' and b then
+// a' give the same text. When both insert at the same place, a's text goes first.
+export function transform(a, b) {
+  const aPrime = [];
+  const bPrime = [];
+  let [i, j] = [0, 0];
+  let [x, y] = [a[i++], b[j++]];
+  while (x !== undefined || y !== undefined) {
+    if (isInsert(x)) {
+      insert(aPrime, x);
+      retain(bPrime, x.length);
+      x = a[i++];
+      continue;
+    }
+    if (isInsert(y)) {
+      retain(aPrime, y.length);
+      insert(bPrime, y);
+      y = b[j++];
+      continue;
+    }
+    if (x === undefined || y === undefined) {
+      throw new Error('Operations were made against different texts');
+    }
+
+    const len = Math.min(Math.abs(x), Math.abs(y));
+    if (isRetain(x) && isRetain(y)) {
+      retain(aPrime, len);
+      retain(bPrime, len);
+    } else if (isDelete(x) && isRetain(y)) {
+      del(aPrime, len);
+    } else if (isRetain(x) && isDelete(y)) {
+      del(bPrime, len);
+    }
+    x = Math.abs(x) > len ? x - Math.sign(x) * len : a[i++];
+    y = Math.abs(y) > len ? y - Math.sign(y) * len : b[j++];
+  }
+  return [aPrime, bPrime];
+}
+
+// An operation with the effect of a followed by b
+export function compose(a, b) {
+  const out = [];
+  let [i, j] = [0, 0];
+  let [x, y] = [a[i++], b[j++]];
+  while (x !== undefined || y !== undefined) {
+    if (isDelete(x)) {
+      del(out, -x);
+      x = a[i++];
+      continue;
+    }
+    if (isInsert(y)) {
+      insert(out, y);
+      y = b[j++];
+      continue;
+    }
+    if (x === undefined || y === undefined) {
+      throw new Error("Operations can't be composed");
+    }
+
+    const xLen = isInsert(x) ? x.length : x;
+    const len = Math.min(xLen, Math.abs(y));
+    if (isRetain(x) && isRetain(y)) retain(out, len);
+    else if (isInsert(x) && isRetain(y)) insert(out, x.slice(0, len));
+    else if (isRetain(x) && isDelete(y)) del(out, len);
+    // An insert that's deleted again leaves nothing
+
+    x = xLen > len ? (isInsert(x) ? x.slice(len) : x - len) : a[i++];
+    y = Math.abs(y) > len ? y - Math.sign(y) * len : b[j++];
+  }
+  return out;
+}
+
+// Where an offset ends up after the operation. Text inserted at it goes after it.
+export function transformIndex(op, index) {
+  let newIndex = index;
+  let offset = 0;
+  for (const c of op) {
+    if (offset >= index) break;
+    if (isRetain(c)) {
+      offset += c;
+    } else if (isInsert(c)) {
+      newIndex += c.length;
+    } else {
+      newIndex -= Math.min(-c, index - offset);
+      offset -= c;
+    }
+  }
+  return newIndex;
+}
+
+// The operation for monaco's content changes, which are relative to the text before
+// them, of the given length
+export function fromChanges(changes, length) {
+  const op = [];
+  let index = 0;
+  const sorted = [...changes].sort((a, b) => a.rangeOffset - b.rangeOffset);
+  for (const change of sorted) {
+    retain(op, change.rangeOffset - index);
+    insert(op, change.text);
+    del(op, change.rangeLength);
+    index = change.rangeOffset + change.rangeLength;
+  }
+  retain(op, length - index);
+  return op;
+}
+
+// Replacements of {offset, length} of the text before the operation with text
+export function toEdits(op) {
+  const edits = [];
+  let offset = 0;
+  let edit = null;
+  for (const c of op) {
+    if (isRetain(c)) {
+      edit = null;
+      offset += c;
+      continue;
+    }
+    if (!edit) {
+      edit = { offset, length: 0, text: '' };
+      edits.push(edit);
+    }
+    if (isInsert(c)) {
+      edit.text += c;
+    } else {
+      edit.length -= c;
+      offset -= c;
+    }
+  }
+  return edits;
+}
+
+// Class names for a peer's caret and selection, defining them the first time a color
+// is seen
+const peerStyles = new Map();
+export function peerClassNames(color) {
+  const id = color.replace(/[^a-z0-9]/gi, '');
+  if (!peerStyles.has(id)) {
+    const style = document.createElement('style');
+    style.textContent = [
+      `.ject-peer-caret-${id} { border-left: 2px solid ${color}; }`,
+      `.ject-peer-selection-${id} { background: ${color}44; }`,
+    ].join('\n');
+    document.head.appendChild(style);
+    peerStyles.set(id, {
+      caret: `ject-peer-caret-${id}`,
+      selection: `ject-peer-selection-${id}`,
+    });
+  }
+  return peerStyles.get(id);
+}
+
+// The client side of one file. At most one operation is sent and waiting for its
+// ack; edits made meanwhile are buffered, and remote operations are transformed past
+// both.
+class CollabFile {
+  constructor(collab, { kind, revision }) {
+    this.collab = collab;
+    this.kind = kind;
+    this.revision = revision;
+    this.pending = null;
+    this.buffer = null;
+    // Remote cursors in this file by peer id
+    this.cursors = new Map();
+    // Operations from other peers to apply to the editor
+    this.remoteOp = new EventType();
+    // The list of remote cursors, when it changes
+    this.presence = new EventType();
+  }
+
+  get canEdit() {
+    return this.collab.canEdit;
+  }
+
+  peerCursors() {
+    return [...this.cursors.values()];
+  }
+
+  local(op) {
+    if (isNoop(op)) return;
+    this.moveCursors(op);
+    if (this.pending) {
+      this.buffer = this.buffer ? compose(this.buffer, op) : op;
+    } else {
+      this.pending = op;
+      this.sendPending();
+    }
+  }
+
+  cursor(anchor, head) {
+    this.collab.send({ type: 'cursor', file: this.kind, anchor, head });
+  }
+
+  sendPending() {
+    this.collab.send({
+      type: 'op',
+      file: this.kind,
+      revision: this.revision,
+      op: this.pending,
+    });
+  }
+
+  ack(revision) {
+    this.revision = revision;
+    this.pending = this.buffer;
+    this.buffer = null;
+    if (this.pending) this.sendPending();
+  }
+
+  remote(revision, op) {
+    this.revision = revision;
+    if (this.pending) {
+      [this.pending, op] = transform(this.pending, op);
+      if (this.buffer) [this.buffer, op] = transform(this.buffer, op);
+    }
+    this.remoteOp.emit(op);
+    this.moveCursors(op);
+  }
+
+  moveCursors(op) {
+    if (!this.cursors.size) return;
+    for (const cursor of this.cursors.values()) {
+      cursor.anchor = transformIndex(op, cursor.anchor);
+      cursor.head = transformIndex(op, cursor.head);
+    }
+    this.presence.emit(this.peerCursors());
+  }
+
+  setPeer(peer) {
+    const cursor = peer.cursors.find((c) => c.file === this.kind);
+    if (cursor) {
+      const { peer_id: peerId, name, color } = peer;
+      this.cursors.set(peerId, { peerId, name, color, ...cursor });
+    } else if (!this.cursors.delete(peer.peer_id)) {
+      return;
+    }
+    this.presence.emit(this.peerCursors());
+  }
+
+  removePeer(peerId) {
+    if (this.cursors.delete(peerId)) this.presence.emit(this.peerCursors());
+  }
+}
+
+// A connection to the session's collaborative editing socket. `joined` resolves with
+// the files once the server has sent them.
+export class Collab {
+  constructor(sessionId, editToken) {
+    // The edit token is offered as a subprotocol, as URLs end up in logs
+    const protocols = ['ject.collab'];
+    if (editToken) protocols.push(`ject.edit-token.${editToken}`);
+    const protocol = location.protocol === 'https:' ? 'wss:' : 'ws:';
+    this.socket = new WebSocket(
+      `${protocol}//${location.host}/api/session/${sessionId}/collab`,
+      protocols,
+    );
+    this.files = {};
+    this.canEdit = false;
+    this.closing = false;
+    // Someone ran the session, with {revision, peer_id}
+    this.saved = new EventType();
+    // The connection was lost, or the server couldn't apply an operation
+    this.lost = new EventType();
+
+    this.joined = new Promise((resolve, reject) => {
+      this.socket.addEventListener('message', (event) => {
+        const message = JSON.parse(event.data);
+        if (message.type === 'joined') {
+          this.canEdit = message.can_edit;
+          for (const file of message.files) {
+            this.files[file.kind] = new CollabFile(this, file);
+          }
+          message.peers.forEach((peer) => this.setPeer(peer));
+          resolve(message.files);
+        } else {
+          this.receive(message);
+        }
+      });
+      this.socket.addEventListener('close', () => {
+        reject(new Error('Unable to join the session'));
+        if (!this.closing) this.lost.emit();
+      });
+    });
+  }
+
+  receive(message) {
+    const file = this.files[message.file];
+    switch (message.type) {
+      case 'ack':
+        return file.ack(message.revision);
+      case 'op':
+        return file.remote(message.revision, message.op);
+      case 'presence':
+        return this.setPeer(message);
+      case 'left':
+        return Object.values(this.files).forEach((f) =>
+          f.removePeer(message.peer_id),
+        );
+      case 'saved':
+        return this.saved.emit(message);
+      case 'error':
+        console.error(`[collab] ${message.code}: ${message.message}`);
+        // The local text can't be reconciled with the server's any more
+        if (['invalid_revision', 'invalid_operation'].includes(message.code)) {
+          this.lost.emit();
+        }
+        return;
+    }
+  }
+
+  setPeer(peer) {
+    Object.values(this.files).forEach((file) => file.setPeer(peer));
+  }
+
+  send(message) {
+    if (this.socket.readyState === WebSocket.OPEN) {
+      this.socket.send(JSON.stringify(message));
+    }
+  }
+
+  // Write the files and run them in every open frame
+  save() {
+    this.send({ type: 'save' });
+  }
+
+  close() {
+    this.closing = true;
+    this.socket.close();
+  }
+}
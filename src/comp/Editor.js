@@ -6,6 +6,7 @@ import pt from 'prop-types';
 import andromeda from '../theme/andromeda-monaco.json';
 import { EventType } from '../EventType';
 import useMonaco from '../hooks/useMonaco';
+import { fromChanges, toEdits, peerClassNames } from '../collab';
 
 const Root = styled.div`
   height: 100%;
@@ -150,11 +151,72 @@ function Editor(props) {
       },
     });
 
-    ed.onDidChangeModelContent(() => {
+    const { collab } = props;
+    const model = ed.getModel();
+    let length = model.getValueLength();
+    let applyingRemote = false;
+
+    ed.onDidChangeModelContent((event) => {
+      if (collab && !applyingRemote) {
+        collab.local(fromChanges(event.changes, length));
+      }
+      length = model.getValueLength();
       const value = ed.getModel().getValue();
       props.onChange(value);
     });
 
+    if (collab) {
+      ed.updateOptions({ readOnly: !collab.canEdit });
+
+      const range = (offset, length) => {
+        const start = model.getPositionAt(offset);
+        const end = model.getPositionAt(offset + length);
+        return monaco.Range.fromPositions(start, end);
+      };
+
+      collab.remoteOp.on((op) => {
+        const edits = toEdits(op).map(({ offset, length, text }) => ({
+          range: range(offset, length),
+          text,
+        }));
+        applyingRemote = true;
+        try {
+          model.applyEdits(edits);
+        } finally {
+          applyingRemote = false;
+        }
+      });
+
+      let decorations = [];
+      const showCursors = (cursors) => {
+        const peerDecorations = cursors.flatMap(({ name, color, anchor, head }) => {
+          const classNames = peerClassNames(color);
+          const hoverMessage = { value: name };
+          const caret = {
+            range: range(head, 0),
+            options: { beforeContentClassName: classNames.caret, hoverMessage },
+          };
+          if (anchor === head) return [caret];
+          const start = Math.min(anchor, head);
+          const selection = {
+            range: range(start, Math.abs(head - anchor)),
+            options: { className: classNames.selection, hoverMessage },
+          };
+          return [selection, caret];
+        });
+        decorations = ed.deltaDecorations(decorations, peerDecorations);
+      };
+      collab.presence.on(showCursors);
+      showCursors(collab.peerCursors());
+
+      ed.onDidChangeCursorSelection(({ selection }) => {
+        collab.cursor(
+          model.getOffsetAt(selection.getSelectionStart()),
+          model.getOffsetAt(selection.getPosition()),
+        );
+      });
+    }
+
     editorRef.current = ed;
   };
 
@@ -182,6 +244,15 @@ function Editor(props) {
 
 Editor.propTypes = {
   onChange: pt.func,
+  // A file of a Collab session, to edit it together with the other peers
+  collab: pt.shape({
+    canEdit: pt.bool.isRequired,
+    local: pt.func.isRequired,
+    cursor: pt.func.isRequired,
+    peerCursors: pt.func.isRequired,
+    remoteOp: pt.instanceOf(EventType).isRequired,
+    presence: pt.instanceOf(EventType).isRequired,
+  }),
   language: pt.string.isRequired,
   value: pt.shape({
     contents: pt.string.isRequired,
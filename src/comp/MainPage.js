@@ -11,8 +11,8 @@ import { EventType } from '../EventType';
 import { useAsync, useEvent } from 'react-use';
 import { queueMeasureRender } from '../async';
 import * as api from '../api';
-import * as templates from '../templates';
 import useUrl from '../hooks/useUrl';
+import { Collab } from '../collab';
 
 let { JECT_DOMAIN_MAIN, JECT_DOMAIN_FRAME } = process.env;
 if (location.hostname === `${JECT_DOMAIN_MAIN}.local`) {
@@ -30,48 +30,179 @@ function MainPage(props) {
     resize: new EventType(),
     save: new EventType(),
     run: new EventType(),
+    pair: new EventType(),
     consoleMessage: new EventType(),
   }));
-  const session = React.useRef(templates.get(props.templateName) ?? templates.get());
+  const session = React.useRef({ files: [] });
   const resultTab = url.query('rt') === 'console' ? 'console' : 'frame';
-  const urlSaveId = url.query('saved');
+  const urlSaveId = props.saveId ?? url.query('saved');
+  // ?capture stores the frame's console output on the server
+  const captureLogs = url.query('capture') != null;
   const [submitCount, setSubmitCount] = React.useState(1);
+  const editToken = React.useRef(null);
+  const synced = React.useRef(null);
+  const collab = React.useRef(null);
+  const savedLogs = React.useRef(null);
+  const forking = React.useRef(null);
+  const [forkedId, setForkedId] = React.useState(null);
 
-  const loadSave = useAsync(async () => {
-    if (!urlSaveId) return null;
-    const initial = await api.getSaved(urlSaveId);
-    return initial;
-  }, []);
+  // Resolves to { sessionId }, which is null while a save is only being viewed
 
   const createSession = useAsync(async () => {
+    if (props.collabSessionId) {
+      // The edit token is in the hash so it's never sent to the server with the page
+      const token = location.hash.slice(1) || null;
+      const joining = new Collab(props.collabSessionId, token);
+      const files = await joining.joined;
+      session.current = {
+        files: files.map(({ kind, contents }) => ({ kind, contents, version: 1 })),
+      };
+      collab.current = joining;
+      editToken.current = token;
+      return { sessionId: props.collabSessionId };
+    }
     if (urlSaveId) {
-      if (loadSave.value) {
-        const version =
-          Math.max(...session.current.files.map((file) => file.version || 1)) + 1;
-        const next = loadSave.value;
-        session.current = {
-          ...next,
-          files: next.files.map((file) => ({ ...file, version })),
-        };
-      } else if (loadSave.loading) {
-        return null;
-      }
+      // Viewing counts towards the save's views; it's forked by the first change
+      const logs = api.getSavedLogs(urlSaveId).catch(() => null);
+      const saved = await api.getSaved(urlSaveId);
+      savedLogs.current = await logs;
+      const version =
+        Math.max(1, ...session.current.files.map((file) => file.version || 1)) + 1;
+      session.current = {
+        ...saved.session,
+        files: saved.session.files.map((file) => ({ ...file, version })),
+      };
+      return { sessionId: null };
     }
-    const { session_id } = await api.createSession(session.current);
-    return session_id;
-  }, [loadSave.value, loadSave.error]);
+    // Templates are kept on the server, see GET /api/templates
+    const created = await api
+      .createSessionFromTemplate(props.templateName ?? 'default')
+      .catch((err) => {
+        if (err.status !== 404 || !props.templateName) throw err;
+        return api.createSessionFromTemplate('default');
+      });
+    session.current = {
+      ...created.session,
+      files: created.session.files.map((file) => ({ ...file, version: 1 })),
+    };
+    editToken.current = created.edit_token;
+    synced.current = api.syncState(session.current.files, created);
+    return { sessionId: created.session_id };
+  }, []);
+  const sessionId = createSession.value?.sessionId ?? forkedId;
 
-  events.run.use(() => {
-    console.log('Running');
-    api.updateSession(createSession.value, session.current).then(() => {
+  // Fork the save being viewed into a session, once, for editing it
+  const ensureSession = () => {
+    if (sessionId) return Promise.resolve(sessionId);
+    if (!forking.current) {
+      forking.current = api.forkSaved(urlSaveId).then(
+        (forked) => {
+          editToken.current = forked.edit_token;
+          synced.current = api.syncState(forked.session.files, forked);
+          setForkedId(forked.session_id);
+          return forked.session_id;
+        },
+        (error) => {
+          forking.current = null;
+          throw error;
+        },
+      );
+    }
+    return forking.current;
+  };
+
+  const editFile = (kind, value) => {
+    ensureSession().catch((error) =>
+      console.error('Failed to fork the save', error),
+    );
+    session.current = {
+      ...session.current,
+      files: session.current.files.map((file) =>
+        file.kind === kind ? { ...file, contents: value } : file,
+      ),
+    };
+  };
+
+  // Show what the save printed when it was last run, if that was captured
+  React.useEffect(() => {
+    const run = savedLogs.current?.runs[0];
+    if (!run?.entries.length) return;
+    events.consoleMessage.emit({
+      method: 'info',
+      args: ['Output of the last captured run of this save:'],
+    });
+    for (const { method, args } of run.entries) {
+      events.consoleMessage.emit({ method, args });
+    }
+  }, [createSession.value]);
+
+  React.useEffect(() => {
+    if (!collab.current) return;
+    const offSaved = collab.current.saved.on(() => {
+      events.consoleMessage.emit({ method: 'ject_execute', args: [] });
       setSubmitCount((c) => c + 1);
     });
+    // Rejoining gets the current text from the server
+    const offLost = collab.current.lost.on(() => location.reload());
+    return () => {
+      offSaved();
+      offLost();
+      collab.current.close();
+    };
+  }, [createSession.value]);
+
+  // Store the session on the server. Resolves false if it was changed elsewhere and
+  // the user chose to keep those changes.
+  const pushSession = async () => {
+    const id = await ensureSession();
+    const update = (base) =>
+      api.updateSession(id, session.current, editToken.current, base);
+    try {
+      synced.current = await update(synced.current);
+    } catch (error) {
+      if (error.body?.code !== 'revision_conflict') throw error;
+      const overwrite = window.confirm(
+        'This session was changed in another tab. Replace those changes with yours?',
+      );
+      if (!overwrite) return false;
+      const { current } = error.body;
+      synced.current = await update(api.syncState(current.session.files, current));
+    }
+    return true;
+  };
+
+  events.run.use(async () => {
+    console.log('Running');
+    if (collab.current) {
+      // Everyone's console is cleared once the server has saved the files
+      collab.current.save();
+      return;
+    }
+    if (!(await pushSession())) return;
+    // The frame reloads itself once the server has the update
+    events.consoleMessage.emit({ method: 'ject_execute', args: [] });
+    setSubmitCount((c) => c + 1);
+  });
+
+  // Share a link for editing the session together, and switch to editing it that way
+  events.pair.use(async () => {
+    if (!collab.current && !(await pushSession())) return;
+    const id = await ensureSession();
+    const hash = editToken.current ? `#${editToken.current}` : '';
+    const link = `${location.origin}/collab/${id}${hash}`;
+    window.prompt('Send this link to edit the session together', link);
+    if (!collab.current) {
+      location.assign(link);
+    }
   });
 
   events.save.use(() => {
     console.log('Saving');
-    api.save(session.current).then(({ save_id }) => {
-      url.withQuery('saved', save_id).withPath('/').applyByPush();
+    api.save(session.current, sessionId, editToken.current).then(({ save_id }) => {
+      url
+        .withQuery('saved', null)
+        .withPath(`/saved/${encodeURIComponent(save_id)}`)
+        .applyByPush();
     });
   });
 
@@ -112,6 +243,12 @@ function MainPage(props) {
         >
           <span>Run</span>
         </MenuItem>
+        <MenuItem
+          style={{ color: 'var(--orange)' }}
+          onClick={() => events.pair.emit()}
+        >
+          <span>Pair</span>
+        </MenuItem>
         <MenuItem
           style={{ color: 'var(--yellow)' }}
           onClick={() => console.log('TODO: Open About Page')}
@@ -159,30 +296,18 @@ function MainPage(props) {
         <Editor
           events={events}
           language="html"
-          onChange={(value) => {
-            session.current = {
-              ...session.current,
-              files: session.current.files.map((file) =>
-                file.kind === 'Html' ? { ...file, contents: value } : file,
-              ),
-            };
-          }}
+          onChange={(value) => editFile('Html', value)}
           value={session.current.files.find((file) => file.kind === 'Html')}
+          collab={collab.current?.files.Html}
         />
       </>
       <>
         <Editor
           events={events}
           language="typescript"
-          onChange={(value) => {
-            session.current = {
-              ...session.current,
-              files: session.current.files.map((file) =>
-                file.kind === 'JavaScript' ? { ...file, contents: value } : file,
-              ),
-            };
-          }}
+          onChange={(value) => editFile('JavaScript', value)}
           value={session.current.files.find((file) => file.kind === 'JavaScript')}
+          collab={collab.current?.files.JavaScript}
         />
       </>
       <>
@@ -190,15 +315,9 @@ function MainPage(props) {
         <Editor
           events={events}
           language="css"
-          onChange={(value) => {
-            session.current = {
-              ...session.current,
-              files: session.current.files.map((file) =>
-                file.kind === 'Css' ? { ...file, contents: value } : file,
-              ),
-            };
-          }}
+          onChange={(value) => editFile('Css', value)}
           value={session.current.files.find((file) => file.kind === 'Css')}
+          collab={collab.current?.files.Css}
         />
       </>
       <>
@@ -216,10 +335,11 @@ function MainPage(props) {
           >
             <PageFrame
               host={JECT_DOMAIN_FRAME}
-              sessionId={createSession.value}
+              sessionId={sessionId}
+              savedId={urlSaveId}
+              capture={captureLogs && sessionId != null}
               resize={events.resize}
               consoleMessage={events.consoleMessage}
-              key={submitCount}
               data-tab="0"
             />
             <Console
@@ -238,6 +358,8 @@ function MainPage(props) {
 
 MainPage.propTypes = {
   templateName: pt.string,
+  saveId: pt.string,
+  collabSessionId: pt.string,
 };
 
 export default MainPage;
@@ -9,10 +9,15 @@ function PageFrame(props) {
   const ref = React.useRef();
   const [frameSize, setFrameSize] = React.useState(null);
 
-  const url = new URL(
-    `${location.origin}/api/session/${encodeURIComponent(props.sessionId)}/page`,
-  );
+  // A save is shown as it was stored until it's forked into a session
+  const path = props.sessionId
+    ? `/api/session/${encodeURIComponent(props.sessionId)}/page`
+    : `/api/saved/${encodeURIComponent(props.savedId)}/page`;
+  const url = new URL(`${location.origin}${path}`);
   url.hostname = props.host;
+  if (props.capture) {
+    url.search = 'capture';
+  }
 
   const frameOrigin = new URL(url).origin;
   useOnMessage((data) => {
@@ -93,8 +98,12 @@ function PageFrame(props) {
 
 PageFrame.propTypes = {
   resize: pt.instanceOf(EventType).isRequired,
-  sessionId: pt.string.isRequired,
+  // One of these is required
+  sessionId: pt.string,
+  savedId: pt.string,
   host: pt.string.isRequired,
+  // Store the frame's console output on the server, see console.js
+  capture: pt.bool,
   consoleMessage: pt.instanceOf(EventType).isRequired,
   'data-tab': pt.string,
 };
@@ -17,24 +17,73 @@ const getTargetOrigin = () => {
 
 const targetOrigin = getTargetOrigin();
 
+// With ?capture, console calls are also stored on the server as a run of the session.
+// The page is served with a token for each load, which the server requires for that.
+const runToken = document.currentScript.dataset.runToken;
+const capture = new URLSearchParams(location.search).has('capture') && !!runToken;
+const sessionId = location.pathname.split('/')[3];
+const MAX_BATCH = 100;
+// Browsers refuse keepalive requests once their bodies add up to 64 KiB, so batches are kept
+// under that with room for the run token
+const MAX_BATCH_BYTES = 60 * 1024;
+const encoder = new TextEncoder();
+const queue = [];
+let seq = 0;
+let flushTimer = null;
+
+const takeBatch = () => {
+  const entries = [];
+  let bytes = 0;
+  while (queue.length && entries.length < MAX_BATCH) {
+    const size = encoder.encode(JSON.stringify(queue[0])).length;
+    if (entries.length && bytes + size > MAX_BATCH_BYTES) break;
+    entries.push(queue.shift());
+    bytes += size;
+  }
+  return { entries, bytes };
+};
+
+const flush = (keepalive = false) => {
+  clearTimeout(flushTimer);
+  flushTimer = null;
+  let keepaliveBytes = 0;
+  while (queue.length) {
+    const { entries, bytes } = takeBatch();
+    keepaliveBytes += bytes;
+    const body = JSON.stringify({ run_token: runToken, entries });
+    fetch(`/api/session/${sessionId}/logs`, {
+      method: 'POST',
+      headers: { 'content-type': 'application/json' },
+      body,
+      // Lets the last batches finish sending while the page reloads, as far as the 64 KiB
+      // allows; the rest may be cut off
+      keepalive: keepalive && keepaliveBytes <= MAX_BATCH_BYTES,
+    }).catch(() => {});
+  }
+};
+
+const captureEntry = (method, args) => {
+  if (!capture) return;
+  queue.push({ seq: seq++, method, args, time: performance.now() });
+  if (queue.length >= MAX_BATCH) flush();
+  else if (!flushTimer) flushTimer = setTimeout(flush, 500);
+};
+
+window.addEventListener('pagehide', () => flush(true));
+
 normalMethods.forEach((method) => {
   const original = console[method].bind(console);
   console[method] = (...args) => {
-    postMessage(
-      {
-        type: 'console',
-        method,
-        args: args.map((arg) =>
-          typeof arg === 'string'
-            ? arg.slice(0, 1024 * 16)
-            : objectInspect(arg, {
-                customInspect: false,
-                maxStringLength: 1024 * 32,
-              }),
-        ),
-      },
-      targetOrigin,
+    const formatted = args.map((arg) =>
+      typeof arg === 'string'
+        ? arg.slice(0, 1024 * 16)
+        : objectInspect(arg, {
+            customInspect: false,
+            maxStringLength: 1024 * 32,
+          }),
     );
+    postMessage({ type: 'console', method, args: formatted }, targetOrigin);
+    captureEntry(method, formatted);
     original(...args);
   };
 });
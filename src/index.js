@@ -16,6 +16,16 @@ function App() {
             <MainPage templateName={match.params.templateName} />
           )}
         />
+        <Route
+          path="/collab/:sessionId"
+          render={({ match }) => (
+            <MainPage collabSessionId={match.params.sessionId} />
+          )}
+        />
+        <Route
+          path="/saved/:saveId"
+          render={({ match }) => <MainPage saveId={match.params.saveId} />}
+        />
         <Route component={MainPage} />
       </Switch>
     </Router>
@@ -0,0 +1,18 @@
+// Injected into every frame page. Reloads it when the session is updated, from this
+// editor or any other, once page.js has been compiled.
+const sessionId = location.pathname.split('/')[3];
+// The revision the page was loaded at, to catch up on one saved before this connects
+const revision = Number(document.currentScript.dataset.revision);
+const events = new EventSource(`/api/session/${sessionId}/events`);
+
+events.addEventListener('message', (message) => {
+  const event = JSON.parse(message.data);
+  const ready =
+    (event.type === 'connected' && event.revision > revision) ||
+    event.type === 'compile_finished' ||
+    (event.type === 'files_changed' && !event.compiling);
+  if (ready) {
+    events.close();
+    location.reload();
+  }
+});
@@ -20,6 +20,7 @@ module.exports = defineConfig((env, argv) => ({
   entry: {
     app: './src/index.js',
     console: './src/console.js',
+    live: './src/live.js',
     // 'editor.worker': 'monaco-editor/esm/vs/editor/editor.worker.js',
     // 'json.worker': 'monaco-editor/esm/vs/language/json/json.worker',
     // 'css.worker': 'monaco-editor/esm/vs/language/css/css.worker',
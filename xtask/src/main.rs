@@ -22,7 +22,7 @@ fn main() {
 }
 
 fn try_main() -> Result<(), DynError> {
-    let task = env::args().skip(1).next();
+    let task = env::args().nth(1);
 
     let mut only = false;
     for arg in env::args().skip(1) {
@@ -72,8 +72,8 @@ deploy                     transfers cargo and webpack output to the server and
 }
 
 fn dist() -> Result<(), DynError> {
-    let _ = fs::remove_dir_all(&dist_dir());
-    fs::create_dir_all(&dist_dir())?;
+    let _ = fs::remove_dir_all(dist_dir());
+    fs::create_dir_all(dist_dir())?;
 
     docker_build_musl()?;
     dist_binary()?;
@@ -122,7 +122,7 @@ fn dist_manpage() -> Result<(), DynError> {
 }
 
 fn dist_webpack() -> Result<(), DynError> {
-    let status = Command::new("npm").args(&["run", "build"]).status().map_err(|err| format!("npm run build couldn't execute. Likely node/npm not being installed.\nSource: {:?}", err))?;
+    let status = Command::new("npm").args(["run", "build"]).status().map_err(|err| format!("npm run build couldn't execute. Likely node/npm not being installed.\nSource: {:?}", err))?;
 
     if !status.success() {
         Err("npm run build returned a non-zero exit code")?;
@@ -135,7 +135,7 @@ fn run_compile() -> Result<(), DynError> {
     let dir = ject_compile_dir();
     let status = docker_command()
         .current_dir(dir)
-        .args(&[
+        .args([
             "run",
             // "-it",
             "--rm",
@@ -155,7 +155,7 @@ fn build_compile() -> Result<(), DynError> {
     let dir = ject_compile_dir();
     let status = docker_command()
         .current_dir(dir)
-        .args(&["build", "-t", "brigand/ject-compile", "."])
+        .args(["build", "-t", "brigand/ject-compile", "."])
         .status()?;
     if !status.success() {
         Err("Expected docker build for ject-compile to be successful")?;
@@ -168,7 +168,7 @@ fn publish_compile() -> Result<(), DynError> {
     let dir = ject_compile_dir();
     let status = docker_command()
         .current_dir(dir)
-        .args(&["push", "brigand/ject-compile:latest"])
+        .args(["push", "brigand/ject-compile:latest"])
         .status()?;
     if !status.success() {
         Err("Expected docker push for ject-compile to be successful")?;
@@ -181,7 +181,7 @@ fn deploy_compile() -> Result<(), DynError> {
     let ssh = CheckedSsh::root()?;
     let mut cmd = ssh.to_command();
 
-    let bash_commands = vec![
+    let bash_commands = [
         "docker pull brigand/ject-compile:latest",
         "systemctl restart docker.ject-compile",
         "journalctl -u docker.ject-compile.service -n 50 --no-pager",
@@ -227,7 +227,7 @@ fn deploy(ssh: ssh::Both) -> Result<(), DynError> {
     {
         let mut cmd = ssh.root.to_command();
 
-        let bash_commands = vec![
+        let bash_commands = [
             "systemctl restart ject",
             "echo 'Restarted. Waiting 3 seconds to read logs'",
             "sleep 3",
@@ -292,8 +292,10 @@ WantedBy=multi-user.target
 "#,
     );
 
-
-    let write_nginx_config = bash_write_file("/etc/nginx/sites-available/default", include_str!("nginx.conf"));
+    let write_nginx_config = bash_write_file(
+        "/etc/nginx/sites-available/default",
+        include_str!("nginx.conf"),
+    );
 
     let bash_commands = vec![
         // Create users
@@ -347,7 +349,7 @@ fn bash_write_file(file_name: &str, contents: &str) -> String {
     let mut cmd = format!("printf \"{}\" ", fmt);
 
     for line in contents.split('\n') {
-        cmd.push_str("\'");
+        cmd.push('\'');
         cmd.push_str(line);
 
         cmd.push_str("\' ");
@@ -364,7 +366,7 @@ fn bash_write_file(file_name: &str, contents: &str) -> String {
 static BUILDER_IMAGE: &str = "brigand/rust-musl-builder";
 
 fn docker_build_musl() -> Result<(), DynError> {
-    return Ok(());
+    Ok(())
     // println!("Building ject/musl/Dockerfile with tag {}", BUILDER_TAG);
     // let status = docker_command()
     //     .current_dir(&musl_dir())
@@ -392,7 +394,7 @@ fn docker_run_builder(command_args: &[&str]) -> Command {
         "cargo-registry:/home/rust/.cargo/registry",
         BUILDER_IMAGE,
     ];
-    args.extend(command_args.into_iter());
+    args.extend(command_args);
     let mut cmd = docker_command();
     cmd.args(args);
     cmd